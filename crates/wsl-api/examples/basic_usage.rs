@@ -1,6 +1,6 @@
 use std::io::BufRead;
 
-use wsl_api::{ExportFlags, ImportFlags, Version, Wsl2, WslErrorKind};
+use wsl_api::{Captured, ExportFlags, ImportFlags, Version, Wsl2, WslErrorKind};
 
 fn run_command(wsl: &Wsl2, distro_uuid: uuid::Uuid) -> Result<(), Box<dyn std::error::Error>> {
     println!("Running command...");
@@ -109,10 +109,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Exporting distribution...");
     let file = std::fs::File::create("distro.tar.gz").unwrap();
-    let (r, w) = std::io::pipe().unwrap();
-    let result = wsl.export_distribution(default_distro, file, w, ExportFlags::empty());
-    // Keep the read end alive until after the export completes
-    drop(r);
+    let result =
+        wsl.export_distribution_with_progress(default_distro, file, ExportFlags::empty(), |line| {
+            println!("export: {}", line)
+        });
     match result {
         Ok(_) => println!("Successfully exported distribution"),
         Err(e) => {
@@ -124,10 +124,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let file = std::fs::File::open("distro.tar.gz").unwrap();
 
     println!("Registering distribution...");
-    let (r, w) = std::io::pipe().unwrap();
-    let result = wsl.register_distribution("test", Version::WSL2, file, w, ImportFlags::empty());
+    let result = wsl.register_distribution_with_progress(
+        "test",
+        Version::WSL2,
+        file,
+        ImportFlags::empty(),
+        |line| println!("register: {}", line),
+    );
     let guid_copy = match result {
-        Ok((guid, name)) => {
+        Ok(Captured {
+            value: (guid, name),
+            ..
+        }) => {
             println!("Successfully registered distribution: {:?} {}", guid, name);
             guid
         }
@@ -136,10 +144,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             return Err(e.into());
         }
     };
-    drop(r);
 
     println!("Setting version...");
-    let result = wsl.set_version(guid_copy, Version::WSL1, std::io::stderr());
+    let result = wsl.set_version_with_progress(guid_copy, Version::WSL1, |line| {
+        println!("set version: {}", line)
+    });
     match result {
         Ok(_) => println!("Successfully set version"),
         Err(e) => {
@@ -0,0 +1,162 @@
+use std::path::Path;
+
+use uuid::Uuid;
+use windows::Win32::Foundation::HANDLE;
+
+use crate::{
+    AttachFlags, CreateInstanceFlags, Distribution, DistributionConfiguration, DistributionFlags,
+    ExportFlags, MountOptions, RegisterOptions, Version, WslError, WslProcess,
+};
+
+/// The set of operations that [`crate::Wsl2`] performs against the WSL
+/// service.
+///
+/// [`crate::ComBackend`] implements this on top of `ILxssUserSession`, while
+/// [`crate::fake::FakeBackend`] provides an in-memory model of the service so
+/// that code built on [`crate::Wsl2`] can be tested on machines without WSL.
+///
+/// Handles passed to a backend are borrowed for the duration of the call:
+/// the caller keeps ownership and closes them afterwards.
+///
+/// Backends must accept calls from several threads at once: streaming
+/// operations such as [`crate::Wsl2::clone_distribution`] export and register
+/// concurrently through a pipe.
+pub trait WslBackend: Send + Sync {
+    /// Shuts down WSL.
+    fn shutdown(&self, force: bool) -> Result<(), WslError>;
+
+    /// Gets the default distribution.
+    fn get_default_distribution(&self) -> Result<Uuid, WslError>;
+
+    /// Makes a distribution the default.
+    fn set_default_distribution(&self, distro_guid: Uuid) -> Result<(), WslError>;
+
+    /// Looks up a distribution's GUID by name. `flags` are
+    /// `LXSS_GET_DISTRO_ID_*` values.
+    fn get_distribution_id(&self, name: &str, flags: u32) -> Result<Uuid, WslError>;
+
+    /// Starts a distribution without launching a process in it.
+    fn create_instance(
+        &self,
+        distro_guid: Uuid,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError>;
+
+    /// Stops a running distribution.
+    fn terminate_distribution(&self, distro_guid: Uuid) -> Result<(), WslError>;
+
+    /// Removes a distribution and deletes its disk.
+    fn unregister_distribution(&self, distro_guid: Uuid) -> Result<(), WslError>;
+
+    /// Moves a stopped distribution's disk to the `location` directory.
+    fn move_distribution(&self, distro_guid: Uuid, location: &Path) -> Result<(), WslError>;
+
+    /// Resizes a WSL2 distribution's virtual disk to `new_size` bytes,
+    /// writing progress to `output_handle`.
+    fn resize_distribution(
+        &self,
+        distro_guid: Uuid,
+        output_handle: HANDLE,
+        new_size: u64,
+    ) -> Result<(), WslError>;
+
+    /// Turns sparse mode on or off for a WSL2 distribution's virtual disk.
+    fn set_sparse(
+        &self,
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    ) -> Result<(), WslError>;
+
+    /// Launches a Linux process in the specified distribution.
+    fn launch(
+        &self,
+        distro_guid: Uuid,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError>;
+
+    /// Enumerates the distributions.
+    fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError>;
+
+    /// Reads a distribution's configuration.
+    fn get_distribution_configuration(
+        &self,
+        distro_guid: Uuid,
+    ) -> Result<DistributionConfiguration, WslError>;
+
+    /// Sets a distribution's default UID and flags.
+    fn configure_distribution(
+        &self,
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: DistributionFlags,
+    ) -> Result<(), WslError>;
+
+    /// Exports a distribution to `file_handle`, writing diagnostics to
+    /// `stderr_handle`.
+    fn export_distribution(
+        &self,
+        distro_guid: Uuid,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError>;
+
+    /// Exports a distribution to the write end of a pipe, writing
+    /// diagnostics to `stderr_handle`.
+    fn export_distribution_pipe(
+        &self,
+        distro_guid: Uuid,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError>;
+
+    /// Registers a distribution from `file_handle`, returning the new
+    /// distribution's GUID and installed name.
+    fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError>;
+
+    /// Registers a distribution from the read end of a pipe, returning the
+    /// new distribution's GUID and installed name.
+    fn register_distribution_pipe(
+        &self,
+        name: &str,
+        version: Version,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError>;
+
+    /// Registers the WSL2 distribution whose disk is at `vhd_path`, leaving
+    /// the disk where it is. Returns the new distribution's GUID.
+    fn import_distribution_in_place(&self, name: &str, vhd_path: &Path) -> Result<Uuid, WslError>;
+
+    /// Converts a distribution to the given WSL version.
+    fn set_version(
+        &self,
+        distribution: Uuid,
+        version: Version,
+        stderr_handle: HANDLE,
+    ) -> Result<(), WslError>;
+
+    /// Attaches a disk to the WSL2 VM without mounting it.
+    fn attach_disk(&self, disk: &Path, flags: AttachFlags) -> Result<(), WslError>;
+
+    /// Attaches a disk to the WSL2 VM and mounts it, returning the mount
+    /// name.
+    fn mount_disk(&self, disk: &Path, options: &MountOptions) -> Result<String, WslError>;
+
+    /// Unmounts and detaches a disk, or every attached disk if `disk` is
+    /// `None`.
+    fn detach_disk(&self, disk: Option<&Path>) -> Result<(), WslError>;
+}
@@ -0,0 +1,520 @@
+//! Encoder and decoder for the LX_INIT interop messages exchanged with the
+//! Linux side of a WSL2 process.
+//!
+//! Every message starts with a [`MessageHeader`] whose size covers the whole
+//! message, header included. The fixed part of each message follows the
+//! layout of the matching `wsl_com_api_sys::interop` structure, and the
+//! process creation messages append their strings after it as NUL-terminated
+//! UTF-8, located by offsets from the start of the message.
+
+use std::io::Read;
+
+use uuid::Uuid;
+use wsl_com_api_sys::constants::*;
+use wsl_com_api_sys::interop::{
+    LX_INIT_CREATE_NT_PROCESS, LX_INIT_CREATE_NT_PROCESS_COMMON,
+    LX_INIT_CREATE_NT_PROCESS_UTILITY_VM, LX_INIT_CREATE_PROCESS_RESPONSE,
+    LX_INIT_PROCESS_EXIT_STATUS, LX_INIT_WINDOW_SIZE_CHANGED, MESSAGE_HEADER,
+};
+
+/// The size of [`MessageHeader`] on the wire.
+pub const HEADER_SIZE: usize = std::mem::size_of::<MESSAGE_HEADER>();
+
+/// The largest message we are willing to decode, which bounds the memory a
+/// misbehaving peer can make us allocate.
+pub const MAX_MESSAGE_SIZE: u32 = 4 * 1024 * 1024;
+
+const COMMON_SIZE: usize = std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS_COMMON>();
+
+// The codec reads and writes fields by hand, so make sure the sys crate's
+// structures still have the layout it expects
+const _: () = assert!(HEADER_SIZE == 8);
+const _: () = assert!(COMMON_SIZE == 32);
+const _: () = assert!(std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS>() == COMMON_SIZE + 12);
+const _: () =
+    assert!(std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS_UTILITY_VM>() == COMMON_SIZE + 20);
+const _: () = assert!(std::mem::size_of::<LX_INIT_CREATE_PROCESS_RESPONSE>() == 12);
+const _: () = assert!(std::mem::size_of::<LX_INIT_PROCESS_EXIT_STATUS>() == 4);
+const _: () = assert!(std::mem::size_of::<LX_INIT_WINDOW_SIZE_CHANGED>() == 8);
+
+/// Errors raised while encoding or decoding a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// The buffer ends before the message does.
+    Truncated { needed: usize, available: usize },
+    /// The header's size is impossible for the message type.
+    InvalidSize { message_type: u32, size: u32 },
+    /// A string offset points outside the message's string payload.
+    OffsetOutOfBounds { field: &'static str, offset: u32 },
+    /// A string runs to the end of the message without a NUL terminator.
+    UnterminatedString { field: &'static str },
+    /// A string is not valid UTF-8.
+    InvalidUtf8 { field: &'static str },
+    /// A string to be encoded contains a NUL byte.
+    InteriorNul { field: &'static str },
+    /// There are more command line arguments than the message can describe.
+    TooManyArguments(usize),
+    /// The encoded message would exceed [`MAX_MESSAGE_SIZE`].
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Truncated { needed, available } => write!(
+                f,
+                "Truncated message: needed {} bytes, got {}",
+                needed, available
+            ),
+            CodecError::InvalidSize { message_type, size } => {
+                write!(f, "Invalid size {} for message type {}", size, message_type)
+            }
+            CodecError::OffsetOutOfBounds { field, offset } => {
+                write!(f, "Offset {} of {} is out of bounds", offset, field)
+            }
+            CodecError::UnterminatedString { field } => write!(f, "{} is not terminated", field),
+            CodecError::InvalidUtf8 { field } => write!(f, "{} is not valid UTF-8", field),
+            CodecError::InteriorNul { field } => write!(f, "{} contains a NUL byte", field),
+            CodecError::TooManyArguments(count) => {
+                write!(f, "Too many command line arguments ({})", count)
+            }
+            CodecError::TooLarge(size) => write!(f, "Message too large ({} bytes)", size),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<CodecError> for std::io::Error {
+    fn from(value: CodecError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, value)
+    }
+}
+
+/// The header that precedes every message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub message_type: u32,
+    /// The size of the whole message, including this header
+    pub message_size: u32,
+}
+
+impl MessageHeader {
+    /// Decodes and validates a header from the start of `buf`.
+    pub fn decode(buf: &[u8]) -> Result<Self, CodecError> {
+        let mut reader = FieldReader::new(buf);
+        let header = MessageHeader {
+            message_type: reader.u32()?,
+            message_size: reader.u32()?,
+        };
+        if (header.message_size as usize) < HEADER_SIZE || header.message_size > MAX_MESSAGE_SIZE {
+            return Err(CodecError::InvalidSize {
+                message_type: header.message_type,
+                size: header.message_size,
+            });
+        }
+        Ok(header)
+    }
+}
+
+/// The fields shared by both kinds of NT process creation request.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CreateNtProcessCommon {
+    pub filename: String,
+    pub command_line: Vec<String>,
+    pub current_working_directory: String,
+    pub environment: String,
+    pub rows: u32,
+    pub columns: u32,
+    pub create_pseudoconsole: bool,
+}
+
+/// A request from Linux to start a Windows process (`LX_INIT_CREATE_NT_PROCESS`).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CreateNtProcess {
+    pub common: CreateNtProcessCommon,
+    pub std_fd_ids: [u32; LX_INIT_STD_FD_COUNT as usize],
+}
+
+/// A request from the WSL2 VM to start a Windows process
+/// (`LX_INIT_CREATE_NT_PROCESS_UTILITY_VM`).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CreateNtProcessUtilityVm {
+    pub common: CreateNtProcessCommon,
+    pub vm_id: Uuid,
+    pub port: u32,
+}
+
+/// The reply to a process creation request (`LX_INIT_CREATE_PROCESS_RESPONSE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CreateProcessResponse {
+    pub result: i32,
+    /// `LX_INIT_CREATE_PROCESS_RESULT_FLAG_*`
+    pub flags: u32,
+    pub signal_pipe_id: u32,
+}
+
+/// A decoded interop message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    CreateNtProcess(CreateNtProcess),
+    CreateNtProcessUtilityVm(CreateNtProcessUtilityVm),
+    CreateProcessResponse(CreateProcessResponse),
+    ExitStatus {
+        exit_code: u32,
+    },
+    WindowSizeChanged {
+        rows: u32,
+        columns: u32,
+    },
+    /// A message type this codec doesn't understand, kept verbatim.
+    Unknown {
+        message_type: u32,
+        payload: Vec<u8>,
+    },
+}
+
+impl Message {
+    /// The `LX_INIT_MESSAGE_*` type of this message.
+    pub fn message_type(&self) -> u32 {
+        match self {
+            Message::CreateNtProcess(_) => LX_INIT_MESSAGE_CREATE_PROCESS,
+            Message::CreateNtProcessUtilityVm(_) => LX_INIT_MESSAGE_CREATE_PROCESS_UTILITY_VM,
+            Message::CreateProcessResponse(_) => LX_INIT_MESSAGE_CREATE_PROCESS_RESPONSE,
+            Message::ExitStatus { .. } => LX_INIT_MESSAGE_EXIT_STATUS,
+            Message::WindowSizeChanged { .. } => LX_INIT_MESSAGE_WINDOW_SIZE_CHANGED,
+            Message::Unknown { message_type, .. } => *message_type,
+        }
+    }
+
+    /// Decodes one message from the start of `buf`, returning it along with
+    /// the number of bytes it occupied.
+    pub fn decode(buf: &[u8]) -> Result<(Message, usize), CodecError> {
+        let header = MessageHeader::decode(buf)?;
+        let size = header.message_size as usize;
+        if buf.len() < size {
+            return Err(CodecError::Truncated {
+                needed: size,
+                available: buf.len(),
+            });
+        }
+        let message = Self::decode_body(header, &buf[..size])?;
+        Ok((message, size))
+    }
+
+    /// Reads exactly one message from a stream. Malformed messages are
+    /// reported as [`std::io::ErrorKind::InvalidData`] wrapping a
+    /// [`CodecError`].
+    pub fn read_from(reader: &mut impl Read) -> std::io::Result<Message> {
+        let mut buf = vec![0; HEADER_SIZE];
+        reader.read_exact(&mut buf)?;
+        let header = MessageHeader::decode(&buf)?;
+        buf.resize(header.message_size as usize, 0);
+        reader.read_exact(&mut buf[HEADER_SIZE..])?;
+        Ok(Self::decode_body(header, &buf)?)
+    }
+
+    fn decode_body(header: MessageHeader, message: &[u8]) -> Result<Message, CodecError> {
+        let invalid_size = || CodecError::InvalidSize {
+            message_type: header.message_type,
+            size: header.message_size,
+        };
+        let expect_size = |size: usize| {
+            if message.len() == HEADER_SIZE + size {
+                Ok(())
+            } else {
+                Err(invalid_size())
+            }
+        };
+
+        let mut reader = FieldReader::new(message);
+        reader.skip(HEADER_SIZE)?;
+
+        Ok(match header.message_type {
+            LX_INIT_MESSAGE_CREATE_PROCESS => {
+                let fixed = std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS>();
+                if message.len() < HEADER_SIZE + fixed {
+                    return Err(invalid_size());
+                }
+                let offsets = CommonOffsets::read(&mut reader)?;
+                let std_fd_ids = [reader.u32()?, reader.u32()?, reader.u32()?];
+                Message::CreateNtProcess(CreateNtProcess {
+                    common: offsets.resolve(message, HEADER_SIZE + fixed)?,
+                    std_fd_ids,
+                })
+            }
+            LX_INIT_MESSAGE_CREATE_PROCESS_UTILITY_VM => {
+                let fixed = std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS_UTILITY_VM>();
+                if message.len() < HEADER_SIZE + fixed {
+                    return Err(invalid_size());
+                }
+                let offsets = CommonOffsets::read(&mut reader)?;
+                let vm_id = Uuid::from_bytes_le(reader.bytes()?);
+                let port = reader.u32()?;
+                Message::CreateNtProcessUtilityVm(CreateNtProcessUtilityVm {
+                    common: offsets.resolve(message, HEADER_SIZE + fixed)?,
+                    vm_id,
+                    port,
+                })
+            }
+            LX_INIT_MESSAGE_CREATE_PROCESS_RESPONSE => {
+                expect_size(std::mem::size_of::<LX_INIT_CREATE_PROCESS_RESPONSE>())?;
+                Message::CreateProcessResponse(CreateProcessResponse {
+                    result: reader.u32()? as i32,
+                    flags: reader.u32()?,
+                    signal_pipe_id: reader.u32()?,
+                })
+            }
+            LX_INIT_MESSAGE_EXIT_STATUS => {
+                expect_size(std::mem::size_of::<LX_INIT_PROCESS_EXIT_STATUS>())?;
+                Message::ExitStatus {
+                    exit_code: reader.u32()?,
+                }
+            }
+            LX_INIT_MESSAGE_WINDOW_SIZE_CHANGED => {
+                expect_size(std::mem::size_of::<LX_INIT_WINDOW_SIZE_CHANGED>())?;
+                Message::WindowSizeChanged {
+                    rows: reader.u32()?,
+                    columns: reader.u32()?,
+                }
+            }
+            message_type => Message::Unknown {
+                message_type,
+                payload: message[HEADER_SIZE..].to_vec(),
+            },
+        })
+    }
+
+    /// Encodes this message, header included.
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let mut buf = vec![0; HEADER_SIZE];
+        match self {
+            Message::CreateNtProcess(create) => {
+                let fixed = HEADER_SIZE + std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS>();
+                let strings = StringPayload::new(&create.common, fixed)?;
+                strings.write_common(&mut buf, &create.common);
+                for id in create.std_fd_ids {
+                    put_u32(&mut buf, id);
+                }
+                buf.extend_from_slice(&strings.bytes);
+            }
+            Message::CreateNtProcessUtilityVm(create) => {
+                let fixed =
+                    HEADER_SIZE + std::mem::size_of::<LX_INIT_CREATE_NT_PROCESS_UTILITY_VM>();
+                let strings = StringPayload::new(&create.common, fixed)?;
+                strings.write_common(&mut buf, &create.common);
+                buf.extend_from_slice(&create.vm_id.to_bytes_le());
+                put_u32(&mut buf, create.port);
+                buf.extend_from_slice(&strings.bytes);
+            }
+            Message::CreateProcessResponse(response) => {
+                put_u32(&mut buf, response.result as u32);
+                put_u32(&mut buf, response.flags);
+                put_u32(&mut buf, response.signal_pipe_id);
+            }
+            Message::ExitStatus { exit_code } => put_u32(&mut buf, *exit_code),
+            Message::WindowSizeChanged { rows, columns } => {
+                put_u32(&mut buf, *rows);
+                put_u32(&mut buf, *columns);
+            }
+            Message::Unknown { payload, .. } => buf.extend_from_slice(payload),
+        }
+
+        if buf.len() > MAX_MESSAGE_SIZE as usize {
+            return Err(CodecError::TooLarge(buf.len()));
+        }
+        buf[0..4].copy_from_slice(&self.message_type().to_le_bytes());
+        let size = buf.len() as u32;
+        buf[4..8].copy_from_slice(&size.to_le_bytes());
+        Ok(buf)
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// The fixed part of `LX_INIT_CREATE_NT_PROCESS_COMMON`, before the offsets
+/// are resolved to strings.
+struct CommonOffsets {
+    filename: u32,
+    command_line: u32,
+    current_working_directory: u32,
+    environment: u32,
+    command_line_count: u16,
+    rows: u32,
+    columns: u32,
+    create_pseudoconsole: u32,
+}
+
+impl CommonOffsets {
+    fn read(reader: &mut FieldReader) -> Result<Self, CodecError> {
+        let filename = reader.u32()?;
+        let command_line = reader.u32()?;
+        let current_working_directory = reader.u32()?;
+        let environment = reader.u32()?;
+        let command_line_count = reader.u16()?;
+        reader.skip(2)?;
+        Ok(CommonOffsets {
+            filename,
+            command_line,
+            current_working_directory,
+            environment,
+            command_line_count,
+            rows: reader.u32()?,
+            columns: reader.u32()?,
+            create_pseudoconsole: reader.u32()?,
+        })
+    }
+
+    /// Reads the strings from `message`, which must all lie in the payload
+    /// that starts at `payload_start`.
+    fn resolve(
+        &self,
+        message: &[u8],
+        payload_start: usize,
+    ) -> Result<CreateNtProcessCommon, CodecError> {
+        let string_at = |field: &'static str, offset: u32| -> Result<(String, u32), CodecError> {
+            let start = offset as usize;
+            if start < payload_start || start >= message.len() {
+                return Err(CodecError::OffsetOutOfBounds { field, offset });
+            }
+            let len = message[start..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(CodecError::UnterminatedString { field })?;
+            let s = std::str::from_utf8(&message[start..start + len])
+                .map_err(|_| CodecError::InvalidUtf8 { field })?;
+            Ok((s.to_string(), offset + len as u32 + 1))
+        };
+
+        let mut command_line = Vec::with_capacity(self.command_line_count as usize);
+        let mut offset = self.command_line;
+        for _ in 0..self.command_line_count {
+            let (arg, next) = string_at("CommandLine", offset)?;
+            command_line.push(arg);
+            offset = next;
+        }
+
+        Ok(CreateNtProcessCommon {
+            filename: string_at("Filename", self.filename)?.0,
+            command_line,
+            current_working_directory: string_at(
+                "CurrentWorkingDirectory",
+                self.current_working_directory,
+            )?
+            .0,
+            environment: string_at("Environment", self.environment)?.0,
+            rows: self.rows,
+            columns: self.columns,
+            create_pseudoconsole: self.create_pseudoconsole != 0,
+        })
+    }
+}
+
+/// The strings of a process creation request laid out after its fixed part.
+struct StringPayload {
+    bytes: Vec<u8>,
+    filename: u32,
+    command_line: u32,
+    current_working_directory: u32,
+    environment: u32,
+}
+
+impl StringPayload {
+    fn new(common: &CreateNtProcessCommon, payload_start: usize) -> Result<Self, CodecError> {
+        if common.command_line.len() > u16::MAX as usize {
+            return Err(CodecError::TooManyArguments(common.command_line.len()));
+        }
+
+        let mut bytes = vec![];
+        let mut push = |field: &'static str, s: &str| -> Result<u32, CodecError> {
+            if s.as_bytes().contains(&0) {
+                return Err(CodecError::InteriorNul { field });
+            }
+            let offset = payload_start + bytes.len();
+            bytes.extend_from_slice(s.as_bytes());
+            bytes.push(0);
+            u32::try_from(offset).map_err(|_| CodecError::TooLarge(offset))
+        };
+
+        let filename = push("Filename", &common.filename)?;
+        let args = common
+            .command_line
+            .iter()
+            .map(|arg| push("CommandLine", arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let current_working_directory =
+            push("CurrentWorkingDirectory", &common.current_working_directory)?;
+        let environment = push("Environment", &common.environment)?;
+        // With no arguments the offset is never read, so point it at the
+        // next string rather than past the end of the message
+        let command_line = args.first().copied().unwrap_or(current_working_directory);
+
+        Ok(StringPayload {
+            bytes,
+            filename,
+            command_line,
+            current_working_directory,
+            environment,
+        })
+    }
+
+    fn write_common(&self, buf: &mut Vec<u8>, common: &CreateNtProcessCommon) {
+        put_u32(buf, self.filename);
+        put_u32(buf, self.command_line);
+        put_u32(buf, self.current_working_directory);
+        put_u32(buf, self.environment);
+        buf.extend_from_slice(&(common.command_line.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        put_u32(buf, common.rows);
+        put_u32(buf, common.columns);
+        put_u32(buf, common.create_pseudoconsole as u32);
+    }
+}
+
+/// Reads little-endian fields from a buffer with bounds checking.
+struct FieldReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        FieldReader { buf, pos: 0 }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let end = self.pos + N;
+        let bytes = self
+            .buf
+            .get(self.pos..end)
+            .ok_or(CodecError::Truncated {
+                needed: end,
+                available: self.buf.len(),
+            })?
+            .try_into()
+            .expect("slice has the requested length");
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), CodecError> {
+        if self.pos + n > self.buf.len() {
+            return Err(CodecError::Truncated {
+                needed: self.pos + n,
+                available: self.buf.len(),
+            });
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    fn u16(&mut self) -> Result<u16, CodecError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+}
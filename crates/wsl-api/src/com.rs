@@ -0,0 +1,848 @@
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use uuid::Uuid;
+use windows::core::{IUnknown, Interface, GUID, HRESULT, PCSTR, PCWSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_NOT_SUPPORTED, E_UNEXPECTED, HANDLE,
+};
+use windows::Win32::Networking::WinSock::WSAStartup;
+use windows::Win32::Storage::FileSystem::{
+    GetFileType, FILE_TYPE_CHAR, FILE_TYPE_DISK, FILE_TYPE_PIPE, FILE_TYPE_REMOTE,
+    FILE_TYPE_UNKNOWN,
+};
+use windows::Win32::System::Com::{
+    CoInitializeEx, CoInitializeSecurity, CoTaskMemFree, CoUninitialize, IClientSecurity,
+    COINIT_MULTITHREADED, EOAC_DYNAMIC_CLOAKING, EOAC_STATIC_CLOAKING,
+    EOLE_AUTHENTICATION_CAPABILITIES, RPC_C_AUTHN_LEVEL, RPC_C_AUTHN_LEVEL_CONNECT,
+    RPC_C_IMP_LEVEL, RPC_C_IMP_LEVEL_IDENTIFY, RPC_C_IMP_LEVEL_IMPERSONATE,
+};
+use wsl_com_api_sys::{
+    get_lxss_user_session, ILxssUserSession, LxssHandleType, LXSS_HANDLE, LXSS_STD_HANDLES,
+};
+
+use crate::interop::Interop;
+use crate::{
+    from_handle, to_handle, AttachFlags, CreateInstanceFlags, DiskFailure, Distribution,
+    DistributionConfiguration, DistributionFlags, ExportFlags, MountOptions, RegisterOptions,
+    Version, WslBackend, WslError, WslErrorKind, WslProcess, WslProcessInner,
+};
+
+/// Turns the `result` and `step` reported by a disk call into an error
+/// that says which stage failed. A non-zero `result` is a Linux error
+/// number, even when the call itself succeeded.
+fn check_disk_result<T>(
+    res: wsl_com_api_sys::LxssResult<T>,
+    result: i32,
+    step: i32,
+    failed: HRESULT,
+) -> Result<T, WslError> {
+    let failure = DiskFailure {
+        step: step.into(),
+        errno: result.abs(),
+    };
+    match res {
+        Ok(_) if result != 0 => Err(WslError::disk(failed, failure, None)),
+        Ok(value) => Ok(value),
+        Err(e) if result != 0 => {
+            let e = WslError::from(e);
+            Err(WslError::disk(e.hresult(), failure, e.message()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Validates that a file handle is of the expected type
+fn validate_file_handle(
+    name: &str,
+    handle: HANDLE,
+    expected_type: windows::Win32::Storage::FileSystem::FILE_TYPE,
+) -> Result<(), WslError> {
+    let file_type = unsafe { GetFileType(handle) };
+    if file_type == FILE_TYPE_UNKNOWN {
+        return Err(windows::core::Error::new(
+            wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
+            format!(
+                "{} ({:x}) is not a valid file handle: {:?}",
+                name,
+                handle.0,
+                unsafe { GetLastError() }
+            ),
+        )
+        .into());
+    }
+    let type_to_string = |file_type: windows::Win32::Storage::FileSystem::FILE_TYPE| match file_type
+    {
+        FILE_TYPE_DISK => "file",
+        FILE_TYPE_PIPE => "pipe",
+        FILE_TYPE_CHAR => "character device",
+        FILE_TYPE_REMOTE => "remote file",
+        FILE_TYPE_UNKNOWN => "unknown type",
+        _ => "invalid type",
+    };
+
+    if file_type != expected_type {
+        let expected_type_name = type_to_string(expected_type);
+        return Err(windows::core::Error::new(
+            wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
+            format!(
+                "{} ({:x}) must be a {} (got a {})",
+                name,
+                handle.0,
+                expected_type_name,
+                type_to_string(file_type)
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads a distribution's configuration, freeing everything the service
+/// allocated for it
+unsafe fn read_configuration(
+    session: &ILxssUserSession,
+    distro_guid: GUID,
+) -> Result<DistributionConfiguration, WslError> {
+    let result = session.GetDistributionConfiguration(distro_guid)?;
+
+    // Copy everything out before freeing, so that nothing the service
+    // allocated is leaked if a string is malformed
+    let name = result.DistributionName.to_string();
+    let environment = if result.DefaultEnvironment.is_null() {
+        vec![]
+    } else {
+        std::slice::from_raw_parts(
+            result.DefaultEnvironment,
+            result.DefaultEnvironmentCount as usize,
+        )
+        .iter()
+        .map(|entry| {
+            let value = String::from_utf8_lossy(entry.as_bytes()).into_owned();
+            CoTaskMemFree(Some(entry.0 as _));
+            value
+        })
+        .collect::<Vec<_>>()
+    };
+    CoTaskMemFree(Some(result.DefaultEnvironment as _));
+    CoTaskMemFree(Some(result.DistributionName.0 as _));
+
+    Ok(DistributionConfiguration {
+        name: name.map_err(|_| WslError::from(E_UNEXPECTED))?,
+        version: Version::from(result.Version),
+        default_uid: result.DefaultUid,
+        default_environment: DistributionConfiguration::parse_environment(environment),
+        flags: DistributionFlags::from_bits_retain(result.Flags),
+    })
+}
+
+struct CoMultithreadedInterface<T: Interface>(T);
+
+unsafe impl<T: Interface> Send for CoMultithreadedInterface<T> {}
+unsafe impl<T: Interface> Sync for CoMultithreadedInterface<T> {}
+
+/// The production [`WslBackend`], which talks to the WSL service through the
+/// `ILxssUserSession` COM interface.
+pub struct ComBackend {
+    /// Channel sender for communicating with the background COM thread
+    sender: Sender<Box<dyn FnOnce(&ILxssUserSession) + Send>>,
+    /// The WSL session object (thread-safe)
+    session: CoMultithreadedInterface<ILxssUserSession>,
+    /// Handle to the background thread
+    _background_thread: JoinHandle<()>,
+}
+
+impl ComBackend {
+    /// Connects to the WSL service, initializing COM on a background thread
+    pub fn new() -> Result<Self, WslError> {
+        if cfg!(not(windows)) {
+            return Err(WslError::precondition(
+                WslErrorKind::UnsupportedOperatingSystem,
+                ERROR_NOT_SUPPORTED.to_hresult(),
+                "WSL is only available on Windows",
+            ));
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let (tx_init, rx_init) = mpsc::channel();
+
+        let background_thread = thread::spawn(move || {
+            Self::com_thread_worker(receiver, tx_init);
+        });
+
+        let session = rx_init
+            .recv()
+            .expect("thread died (init)?")
+            .map_err(WslError::from)?;
+
+        Ok(ComBackend {
+            sender,
+            session,
+            _background_thread: background_thread,
+        })
+    }
+
+    /// Background thread worker that initializes COM and handles requests
+    fn com_thread_worker(
+        receiver: Receiver<Box<dyn FnOnce(&ILxssUserSession) + Send>>,
+        initialized: Sender<windows::core::Result<CoMultithreadedInterface<ILxssUserSession>>>,
+    ) {
+        unsafe {
+            // Initialize Winsock: this is required (unsure what requires it,
+            // but we get 8007276D otherwise)
+            // "Either the application has not called WSAStartup, or WSAStartup failed"
+            let mut wsa_data = std::mem::zeroed();
+            let result = WSAStartup(0x0202, &mut wsa_data);
+            if result != 0 {
+                initialized
+                    .send(Err(windows::core::Error::new(
+                        wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
+                        format!("WSAStartup failed: 0x{:x}", result),
+                    )
+                    .into()))
+                    .expect("thread died (init tx)?");
+                return;
+            }
+
+            // Initialize COM with apartment threading
+            let result = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if result.is_err() {
+                initialized
+                    .send(Err(result.into()))
+                    .expect("thread died (init tx)?");
+                return;
+            }
+
+            let result = CoInitializeSecurity(
+                None,
+                -1,
+                None,
+                None,
+                RPC_C_AUTHN_LEVEL_CONNECT,
+                RPC_C_IMP_LEVEL_IDENTIFY,
+                None,
+                EOAC_STATIC_CLOAKING,
+                None,
+            );
+            if let Err(e) = result {
+                CoUninitialize();
+                initialized.send(Err(e)).expect("thread died (init tx)?");
+                return;
+            }
+        }
+
+        // Get the WSL user session
+        let session = match unsafe { get_lxss_user_session() } {
+            Ok(session) => session,
+            Err(e) => {
+                unsafe {
+                    CoUninitialize();
+                }
+                initialized
+                    .send(Err(e.into()))
+                    .expect("thread died (init tx)?");
+                return;
+            }
+        };
+
+        let result = Self::set_session_blanket(&session);
+        if let Err(e) = result {
+            unsafe {
+                CoUninitialize();
+            }
+            initialized.send(Err(e)).expect("thread died (init tx)?");
+            return;
+        }
+
+        initialized
+            .send(Ok(CoMultithreadedInterface(session.clone())))
+            .expect("thread died (init tx)?");
+
+        // Process requests from the main thread
+        for request in receiver {
+            request(&session);
+        }
+
+        // Cleanup COM
+        unsafe {
+            CoUninitialize();
+        }
+    }
+
+    /// Enables the WSL session to impersonate the calling user's identity when
+    /// making requests to the WSL service. This allows the session to access
+    /// user-specific resources and run processes with the correct permissions
+    /// without requiring elevated privileges for the entire session.
+    ///
+    /// See
+    /// https://learn.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iclientsecurity-setblanket
+    /// for details on COM security blankets.
+    fn set_session_blanket(session: &ILxssUserSession) -> windows::core::Result<()> {
+        let client_security: IClientSecurity = session.cast()?;
+
+        let mut authn_svc = 0;
+        let mut authz_svc = 0;
+        let mut authn_lvl: RPC_C_AUTHN_LEVEL = RPC_C_AUTHN_LEVEL(0);
+        let mut imp_lvl: RPC_C_IMP_LEVEL = RPC_C_IMP_LEVEL(0);
+        let mut capabilities: EOLE_AUTHENTICATION_CAPABILITIES =
+            EOLE_AUTHENTICATION_CAPABILITIES(0);
+        unsafe {
+            client_security.QueryBlanket::<&IUnknown>(
+                &session.0,
+                std::ptr::from_mut(&mut authn_svc),
+                Some(std::ptr::from_mut(&mut authz_svc)),
+                std::ptr::null_mut(),
+                Some(std::ptr::from_mut(&mut authn_lvl)),
+                Some(std::ptr::from_mut(&mut imp_lvl)),
+                std::ptr::null_mut(),
+                Some(std::ptr::from_mut(&mut capabilities.0) as _),
+            )?;
+        }
+
+        capabilities.0 &= !EOAC_STATIC_CLOAKING.0;
+        capabilities.0 |= EOAC_DYNAMIC_CLOAKING.0;
+
+        unsafe {
+            client_security.SetBlanket::<&IUnknown, PCWSTR>(
+                &session.0,
+                authn_svc,
+                authz_svc,
+                PCWSTR::null(),
+                authn_lvl,
+                RPC_C_IMP_LEVEL_IMPERSONATE,
+                None,
+                capabilities,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes a function on the COM thread
+    fn execute_thread<F, T>(&self, f: F) -> Result<T, WslError>
+    where
+        F: FnOnce(&ILxssUserSession) -> Result<T, WslError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.sender
+            .send(Box::new(move |session| {
+                let result = f(session);
+                _ = tx.send(result);
+            }))
+            .expect("thread died (tx)?");
+        rx.recv().expect("thread died (rx)?")
+    }
+
+    /// Executes a function on the current thread
+    fn execute<F, T>(&self, f: F) -> Result<T, WslError>
+    where
+        F: FnOnce(&ILxssUserSession) -> Result<T, WslError> + Send + 'static,
+        T: Send + 'static,
+    {
+        f(&self.session.0)
+    }
+}
+
+impl WslBackend for ComBackend {
+    fn shutdown(&self, force: bool) -> Result<(), WslError> {
+        self.execute_thread(move |session| unsafe {
+            session.Shutdown(force as i32)?;
+            Ok(())
+        })
+    }
+
+    fn get_default_distribution(&self) -> Result<Uuid, WslError> {
+        self.execute(|session| unsafe {
+            Ok(session
+                .GetDefaultDistribution()
+                .map(|guid| Uuid::from_u128(guid.to_u128()))?)
+        })
+    }
+
+    fn set_default_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.SetDefaultDistribution(GUID::from_u128(distro_guid.as_u128()))?;
+            Ok(())
+        })
+    }
+
+    fn get_distribution_id(&self, name: &str, flags: u32) -> Result<Uuid, WslError> {
+        let wide_name = widestring::U16CString::from_str_truncate(name);
+        self.execute(move |session| unsafe {
+            let guid = session.GetDistributionId(PCWSTR::from_raw(wide_name.as_ptr()), flags)?;
+            Ok(Uuid::from_u128(guid.to_u128()))
+        })
+    }
+
+    fn create_instance(
+        &self,
+        distro_guid: Uuid,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.CreateInstance(GUID::from_u128(distro_guid.as_u128()), flags.bits())?;
+            Ok(())
+        })
+    }
+
+    fn terminate_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.TerminateDistribution(GUID::from_u128(distro_guid.as_u128()))?;
+            Ok(())
+        })
+    }
+
+    fn unregister_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.UnregisterDistribution(GUID::from_u128(distro_guid.as_u128()))?;
+            Ok(())
+        })
+    }
+
+    fn move_distribution(&self, distro_guid: Uuid, location: &Path) -> Result<(), WslError> {
+        let location = widestring::U16CString::from_os_str_truncate(location.as_os_str());
+        self.execute(move |session| unsafe {
+            session.MoveDistribution(
+                GUID::from_u128(distro_guid.as_u128()),
+                PCWSTR::from_raw(location.as_ptr()),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn resize_distribution(
+        &self,
+        distro_guid: Uuid,
+        output_handle: HANDLE,
+        new_size: u64,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.ResizeDistribution(
+                GUID::from_u128(distro_guid.as_u128()),
+                output_handle,
+                new_size,
+            )?;
+            Ok(())
+        })
+    }
+
+    fn set_sparse(
+        &self,
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.SetSparse(
+                GUID::from_u128(distro_guid.as_u128()),
+                sparse as i32,
+                allow_unsafe as i32,
+            )?;
+            Ok(())
+        })
+    }
+
+    fn launch(
+        &self,
+        distro_guid: Uuid,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError> {
+        let username = widestring::U16CString::from_str_truncate(username);
+        let command = CString::new(command).unwrap();
+        let cwd = cwd.map(|cwd| widestring::U16CString::from_str_truncate(cwd));
+        let nt_path = widestring::U16CString::from_str_truncate(
+            std::env::current_dir()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap(),
+        );
+        let args = args
+            .iter()
+            .map(|arg| CString::new(*arg).unwrap())
+            .collect::<Vec<_>>();
+
+        let (stdin_r, stdin_w) = std::io::pipe().unwrap();
+        let (stdout_r, stdout_w) = std::io::pipe().unwrap();
+        let (stderr_r, stderr_w) = std::io::pipe().unwrap();
+
+        let pipe = (
+            to_handle(&stdin_r),
+            to_handle(&stdout_w),
+            to_handle(&stderr_w),
+        );
+
+        let handles = LXSS_STD_HANDLES {
+            StdIn: LXSS_HANDLE {
+                Handle: pipe.0 .0 as _,
+                HandleType: LxssHandleType::LxssHandleInput,
+            },
+            StdOut: LXSS_HANDLE {
+                Handle: pipe.1 .0 as _,
+                HandleType: LxssHandleType::LxssHandleOutput,
+            },
+            StdErr: LXSS_HANDLE {
+                Handle: pipe.2 .0 as _,
+                HandleType: LxssHandleType::LxssHandleOutput,
+            },
+        };
+
+        std::mem::forget(stderr_w);
+        std::mem::forget(stdout_w);
+        std::mem::forget(stdin_r);
+
+        self.execute(move |session| unsafe {
+            let arg_ptrs = args
+                .iter()
+                .map(|arg| arg.to_bytes_with_nul().as_ptr())
+                .collect::<Vec<_>>();
+            let result = session.CreateLxProcess(
+                GUID::from_u128(distro_guid.as_u128()),
+                PCSTR::from_raw(command.as_ptr() as *const u8),
+                args.len() as u32,
+                arg_ptrs.as_ptr() as *const PCSTR,
+                PCWSTR::from_raw(cwd.map(|cwd| cwd.as_ptr()).unwrap_or(std::ptr::null())),
+                PCWSTR::from_raw(nt_path.as_ptr()),
+                std::ptr::null_mut(), // todo
+                0,                    // todo
+                PCWSTR::from_raw(username.as_ptr()),
+                80,
+                25,
+                0,
+                std::ptr::from_ref(&handles),
+                CreateInstanceFlags::empty().bits(),
+            )?;
+
+            #[allow(unreachable_code)]
+            let process = if result.ProcessHandle.is_invalid() {
+                // This is harder to mock on unix, so just bail
+                #[cfg(unix)]
+                #[allow(unused)]
+                let tcp = { unreachable!("Unsupported platform") };
+
+                #[cfg(windows)]
+                let tcp = {
+                    use std::net::TcpStream;
+                    use std::os::windows::io::FromRawSocket;
+                    TcpStream::from_raw_socket(result.InteropSocket.0 as _)
+                };
+
+                WslProcess {
+                    stdin: Some(from_handle(result.StandardIn)),
+                    stdout: Some(from_handle(result.StandardOut)),
+                    stderr: Some(from_handle(result.StandardErr)),
+                    pipe: Some(pipe),
+                    handle: WslProcessInner::WSL2(
+                        Interop::new(tcp),
+                        Some(result.CommunicationChannel),
+                    ),
+                }
+            } else {
+                let process = WslProcess {
+                    stdin: Some(from_handle(to_handle(&stdin_w))),
+                    stdout: Some(from_handle(to_handle(&stdout_r))),
+                    stderr: Some(from_handle(to_handle(&stderr_r))),
+                    pipe: Some(pipe),
+                    handle: WslProcessInner::WSL1(result.ProcessHandle),
+                };
+
+                // Close the server handle
+                _ = CloseHandle(result.ServerHandle);
+
+                std::mem::forget(stdin_w);
+                std::mem::forget(stdout_r);
+                std::mem::forget(stderr_r);
+
+                process
+            };
+
+            Ok(process)
+        })
+    }
+
+    fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
+        self.execute(|session| unsafe {
+            let (count, distros) = session.EnumerateDistributions()?;
+            let distros_copy: Vec<Distribution> = {
+                let slice = std::slice::from_raw_parts(distros, count as usize);
+                slice
+                    .iter()
+                    .map(|distro| Distribution::from(distro))
+                    .collect()
+            };
+            CoTaskMemFree(Some(distros as _));
+
+            Ok(distros_copy)
+        })
+    }
+
+    fn get_distribution_configuration(
+        &self,
+        distro_guid: Uuid,
+    ) -> Result<DistributionConfiguration, WslError> {
+        self.execute(move |session| unsafe {
+            read_configuration(session, GUID::from_u128(distro_guid.as_u128()))
+        })
+    }
+
+    fn configure_distribution(
+        &self,
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: DistributionFlags,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.ConfigureDistribution(
+                GUID::from_u128(distro_guid.as_u128()),
+                default_uid,
+                flags.bits(),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn export_distribution(
+        &self,
+        distro_guid: Uuid,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            // Validate handles in the COM thread to ensure they're still valid
+            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
+            validate_file_handle("file_handle", file_handle, FILE_TYPE_DISK)?;
+
+            session.ExportDistribution(
+                GUID::from_u128(distro_guid.as_u128()),
+                file_handle,
+                stderr_handle,
+                flags.bits(),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn export_distribution_pipe(
+        &self,
+        distro_guid: Uuid,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            // Validate handles in the COM thread to ensure they're still valid
+            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
+            validate_file_handle("pipe_handle", pipe_handle, FILE_TYPE_PIPE)?;
+
+            session.ExportDistributionPipe(
+                GUID::from_u128(distro_guid.as_u128()),
+                pipe_handle,
+                stderr_handle,
+                flags.bits(),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        let wide_name = widestring::U16CString::from_str_truncate(name);
+        let target_directory = options
+            .target_directory
+            .as_ref()
+            .map(|dir| widestring::U16CString::from_os_str_truncate(dir.as_os_str()));
+        let package_family_name = options
+            .package_family_name
+            .as_deref()
+            .map(widestring::U16CString::from_str_truncate);
+        let flags = options.flags;
+        let vhd_size = options.vhd_size.map(|size| size.bytes()).unwrap_or(0);
+
+        self.execute(move |session| unsafe {
+            // Validate handles in the COM thread to ensure they're still valid
+            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
+            validate_file_handle("file_handle", file_handle, FILE_TYPE_DISK)?;
+
+            let result = session.RegisterDistribution(
+                PCWSTR::from_raw(wide_name.as_ptr()),
+                version.into(),
+                file_handle,
+                stderr_handle,
+                target_directory
+                    .as_ref()
+                    .map(|dir| PCWSTR::from_raw(dir.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+                flags.bits(),
+                vhd_size,
+                package_family_name
+                    .as_ref()
+                    .map(|name| PCWSTR::from_raw(name.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+            )?;
+            let name = result.InstalledName.to_string().unwrap_or_default();
+            CoTaskMemFree(Some(result.InstalledName.0 as _));
+            Ok((Uuid::from_u128(result.Guid.to_u128()), name))
+        })
+    }
+
+    fn register_distribution_pipe(
+        &self,
+        name: &str,
+        version: Version,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        let wide_name = widestring::U16CString::from_str_truncate(name);
+        let target_directory = options
+            .target_directory
+            .as_ref()
+            .map(|dir| widestring::U16CString::from_os_str_truncate(dir.as_os_str()));
+        let package_family_name = options
+            .package_family_name
+            .as_deref()
+            .map(widestring::U16CString::from_str_truncate);
+        let flags = options.flags;
+        let vhd_size = options.vhd_size.map(|size| size.bytes()).unwrap_or(0);
+
+        self.execute(move |session| unsafe {
+            // Validate handles in the COM thread to ensure they're still valid
+            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
+            validate_file_handle("pipe_handle", pipe_handle, FILE_TYPE_PIPE)?;
+
+            let result = session.RegisterDistributionPipe(
+                PCWSTR::from_raw(wide_name.as_ptr()),
+                version.into(),
+                pipe_handle,
+                stderr_handle,
+                target_directory
+                    .as_ref()
+                    .map(|dir| PCWSTR::from_raw(dir.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+                flags.bits(),
+                vhd_size,
+                package_family_name
+                    .as_ref()
+                    .map(|name| PCWSTR::from_raw(name.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+            )?;
+            let name = result.InstalledName.to_string().unwrap_or_default();
+            CoTaskMemFree(Some(result.InstalledName.0 as _));
+            Ok((Uuid::from_u128(result.Guid.to_u128()), name))
+        })
+    }
+
+    fn import_distribution_in_place(&self, name: &str, vhd_path: &Path) -> Result<Uuid, WslError> {
+        let wide_name = widestring::U16CString::from_str_truncate(name);
+        let vhd_path = widestring::U16CString::from_os_str_truncate(vhd_path.as_os_str());
+        self.execute(move |session| unsafe {
+            let guid = session.ImportDistributionInplace(
+                PCWSTR::from_raw(wide_name.as_ptr()),
+                PCWSTR::from_raw(vhd_path.as_ptr()),
+            )?;
+            Ok(Uuid::from_u128(guid.to_u128()))
+        })
+    }
+
+    fn set_version(
+        &self,
+        distribution: Uuid,
+        version: Version,
+        stderr_handle: HANDLE,
+    ) -> Result<(), WslError> {
+        self.execute(move |session| unsafe {
+            session.SetVersion(
+                GUID::from_u128(distribution.as_u128()),
+                version.into(),
+                stderr_handle,
+            )?;
+            Ok(())
+        })
+    }
+
+    fn attach_disk(&self, disk: &Path, flags: AttachFlags) -> Result<(), WslError> {
+        let disk = widestring::U16CString::from_os_str_truncate(disk.as_os_str());
+        self.execute(move |session| unsafe {
+            session.AttachDisk(PCWSTR::from_raw(disk.as_ptr()), flags.bits())?;
+            Ok(())
+        })
+    }
+
+    fn mount_disk(&self, disk: &Path, options: &MountOptions) -> Result<String, WslError> {
+        let disk = widestring::U16CString::from_os_str_truncate(disk.as_os_str());
+        let wide = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(widestring::U16CString::from_str_truncate)
+        };
+        let name = wide(&options.name);
+        let filesystem_type = wide(&options.filesystem_type);
+        let mount_options = wide(&options.options);
+        let flags = options.flags;
+        let partition_index = options.partition.unwrap_or(0);
+
+        self.execute(move |session| unsafe {
+            let as_pcwstr = |value: &Option<widestring::U16CString>| {
+                value
+                    .as_ref()
+                    .map(|value| PCWSTR::from_raw(value.as_ptr()))
+                    .unwrap_or(PCWSTR::null())
+            };
+            let (mut result, mut step) = (0, 0);
+            let mount_name = session
+                .MountDisk(
+                    PCWSTR::from_raw(disk.as_ptr()),
+                    flags.bits(),
+                    partition_index,
+                    as_pcwstr(&name),
+                    as_pcwstr(&filesystem_type),
+                    as_pcwstr(&mount_options),
+                    &mut result,
+                    &mut step,
+                )
+                .map(|mount_name| {
+                    let name = mount_name.to_string().unwrap_or_default();
+                    CoTaskMemFree(Some(mount_name.0 as _));
+                    name
+                });
+            check_disk_result(
+                mount_name,
+                result,
+                step,
+                wsl_com_api_sys::error::WSL_E_DISK_MOUNT_FAILED,
+            )
+        })
+    }
+
+    fn detach_disk(&self, disk: Option<&Path>) -> Result<(), WslError> {
+        let disk = disk.map(|disk| widestring::U16CString::from_os_str_truncate(disk.as_os_str()));
+        self.execute(move |session| unsafe {
+            let (mut result, mut step) = (0, 0);
+            let detached = session.DetachDisk(
+                disk.as_ref()
+                    .map(|disk| PCWSTR::from_raw(disk.as_ptr()))
+                    .unwrap_or(PCWSTR::null()),
+                &mut result,
+                &mut step,
+            );
+            check_disk_result(
+                detached,
+                result,
+                step,
+                wsl_com_api_sys::error::WSL_E_DISK_UNMOUNT_FAILED,
+            )
+        })
+    }
+}
@@ -1,20 +1,169 @@
+use serde::{Deserialize, Serialize};
 use windows::core::HRESULT;
 
-#[derive(Debug)]
 enum UnderlyingError {
     Lxss(wsl_com_api_sys::LxssError),
     Windows(windows::core::Error),
+    /// An error raised outside the WSL service, or replayed from a
+    /// transcript, with the service's message if one was captured.
+    Hresult(HRESULT, Option<String>),
+    Io(std::io::Error),
+    /// An error classified by this crate: a check made before calling the
+    /// service, or a service error whose meaning depends on the call.
+    Classified(WslErrorKind, HRESULT, String),
+    /// A disk operation that failed inside the WSL2 VM, with the service's
+    /// message if there was one.
+    Disk(DiskFailure, HRESULT, Option<String>),
+}
+
+impl std::fmt::Debug for UnderlyingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnderlyingError::Lxss(e) => f.debug_tuple("Lxss").field(e).finish(),
+            // The system message lookup is only available on Windows
+            #[cfg(windows)]
+            UnderlyingError::Windows(e) => f.debug_tuple("Windows").field(e).finish(),
+            #[cfg(not(windows))]
+            UnderlyingError::Windows(e) => f.debug_tuple("Windows").field(&e.code()).finish(),
+            UnderlyingError::Hresult(e, message) => {
+                f.debug_tuple("Hresult").field(e).field(message).finish()
+            }
+            UnderlyingError::Io(e) => f.debug_tuple("Io").field(e).finish(),
+            UnderlyingError::Classified(kind, e, message) => f
+                .debug_tuple("Classified")
+                .field(kind)
+                .field(e)
+                .field(message)
+                .finish(),
+            UnderlyingError::Disk(failure, e, message) => f
+                .debug_tuple("Disk")
+                .field(failure)
+                .field(e)
+                .field(message)
+                .finish(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct WslError {
     underlying: UnderlyingError,
+    /// What the service wrote to an output pipe owned by the operation.
+    output: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum WslErrorKind {
     UnsupportedOperatingSystem,
     UnsupportedWslVersion,
+    /// The distribution (or the default distribution) doesn't exist.
+    DistributionNotFound,
+    /// The distribution's name isn't the one the caller expected.
+    DistributionNameMismatch,
+    /// The operation would affect the default distribution.
+    DistributionIsDefault,
+    /// The distribution must be stopped first.
+    DistributionNotStopped,
+    /// A path given to the API is unusable for the operation.
+    InvalidPath,
+    /// A size is missing where one is required, or couldn't be parsed.
+    InvalidSize,
+    /// The requested disk size is smaller than the space already in use.
+    DiskTooSmall,
+    /// The operation is only supported for WSL2 distributions.
+    Wsl2Needed,
+    /// The service declined a request that was otherwise valid.
+    ServiceRefused,
+    /// A distribution with the same name is already registered.
+    DistributionAlreadyExists,
+    /// There is no snapshot with the given label.
+    SnapshotNotFound,
+    /// A snapshot with the given label already exists.
+    SnapshotAlreadyExists,
+    /// A snapshot's tarball doesn't match its manifest.
+    SnapshotCorrupted,
+    /// The disk is already attached to the WSL2 VM.
+    DiskAlreadyAttached,
+    /// The disk, or its mount name, is already mounted.
+    DiskAlreadyMounted,
+    /// Attaching or mounting a disk failed. See [`WslError::disk_failure`].
+    DiskMountFailed,
+    /// Unmounting or detaching a disk failed. See [`WslError::disk_failure`].
+    DiskUnmountFailed,
+    /// The operation must be run from an elevated process.
+    ElevationNeeded,
+    /// The distribution's filesystem must be upgraded before it can start.
+    FilesystemUpgradeNeeded,
+    /// A tarball couldn't be decompressed or read.
+    InvalidTarball,
+    /// A tarball doesn't contain a Linux root filesystem.
+    NotALinuxDistribution,
+    /// A tarball's binaries are built for more than one architecture.
+    MixedArchitectures,
+}
+
+/// The stage of mounting or unmounting a disk inside the WSL2 VM, as
+/// reported by the service alongside a failure.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "i32", into = "i32")]
+pub enum DiskStep {
+    FindDevice,
+    FindPartition,
+    Mount,
+    DetectFilesystem,
+    Unmount,
+    RemoveMountPoint,
+    Unknown(i32),
+}
+
+impl From<i32> for DiskStep {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => DiskStep::FindDevice,
+            2 => DiskStep::FindPartition,
+            3 => DiskStep::Mount,
+            4 => DiskStep::DetectFilesystem,
+            5 => DiskStep::Unmount,
+            6 => DiskStep::RemoveMountPoint,
+            other => DiskStep::Unknown(other),
+        }
+    }
+}
+
+impl From<DiskStep> for i32 {
+    fn from(value: DiskStep) -> Self {
+        match value {
+            DiskStep::FindDevice => 1,
+            DiskStep::FindPartition => 2,
+            DiskStep::Mount => 3,
+            DiskStep::DetectFilesystem => 4,
+            DiskStep::Unmount => 5,
+            DiskStep::RemoveMountPoint => 6,
+            DiskStep::Unknown(other) => other,
+        }
+    }
+}
+
+impl std::fmt::Display for DiskStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiskStep::FindDevice => write!(f, "finding the disk"),
+            DiskStep::FindPartition => write!(f, "finding the partition"),
+            DiskStep::Mount => write!(f, "mounting the filesystem"),
+            DiskStep::DetectFilesystem => write!(f, "detecting the filesystem"),
+            DiskStep::Unmount => write!(f, "unmounting the filesystem"),
+            DiskStep::RemoveMountPoint => write!(f, "removing the mount point"),
+            DiskStep::Unknown(step) => write!(f, "step {step}"),
+        }
+    }
+}
+
+/// Where a disk operation failed inside the WSL2 VM, and why.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub struct DiskFailure {
+    pub step: DiskStep,
+    /// The Linux error number the step failed with.
+    pub errno: i32,
 }
 
 impl WslError {
@@ -22,15 +171,141 @@ impl WslError {
         match &self.underlying {
             UnderlyingError::Lxss(e) => e.0,
             UnderlyingError::Windows(e) => e.code(),
+            UnderlyingError::Hresult(e, _) => *e,
+            UnderlyingError::Classified(_, e, _) => *e,
+            UnderlyingError::Disk(_, e, _) => *e,
+            UnderlyingError::Io(e) => match e.raw_os_error() {
+                #[cfg(windows)]
+                Some(code) => HRESULT::from_win32(code as u32),
+                _ => windows::Win32::Foundation::E_FAIL,
+            },
         }
     }
 
-    pub fn kind(&self) -> Option<WslErrorKind> {
-        #[cfg(not(windows))]
-        return Some(WslErrorKind::UnsupportedOperatingSystem);
+    /// The message the WSL service attached to this error, if any.
+    pub fn message(&self) -> Option<String> {
+        match &self.underlying {
+            UnderlyingError::Lxss((_, info)) if !info.Message.is_null() => {
+                unsafe { info.Message.to_string() }.ok()
+            }
+            UnderlyingError::Hresult(_, message) => message.clone(),
+            UnderlyingError::Classified(_, _, message) => Some(message.clone()),
+            UnderlyingError::Disk(_, _, message) => message.clone(),
+            _ => None,
+        }
+    }
+
+    /// Creates an error with the given HRESULT and service message.
+    pub fn with_message(hresult: HRESULT, message: impl Into<String>) -> Self {
+        WslError {
+            underlying: UnderlyingError::Hresult(hresult, Some(message.into())),
+            output: None,
+        }
+    }
+
+    /// Creates an error for a check that failed before reaching the
+    /// service.
+    pub(crate) fn precondition(
+        kind: WslErrorKind,
+        hresult: HRESULT,
+        message: impl Into<String>,
+    ) -> Self {
+        WslError {
+            underlying: UnderlyingError::Classified(kind, hresult, message.into()),
+            output: None,
+        }
+    }
+
+    /// Creates an error for a disk operation that failed inside the WSL2 VM.
+    pub(crate) fn disk(hresult: HRESULT, failure: DiskFailure, message: Option<String>) -> Self {
+        WslError {
+            underlying: UnderlyingError::Disk(failure, hresult, message),
+            output: None,
+        }
+    }
+
+    /// The stage at which a disk operation failed inside the WSL2 VM, if
+    /// this error came from one.
+    pub fn disk_failure(&self) -> Option<DiskFailure> {
+        match &self.underlying {
+            UnderlyingError::Disk(failure, _, _) => Some(*failure),
+            _ => None,
+        }
+    }
+
+    /// Gives a service error the kind that it means for the call that
+    /// raised it, keeping its HRESULT and message.
+    pub(crate) fn with_kind(self, kind: WslErrorKind) -> Self {
+        let message = self.message().unwrap_or_else(|| self.to_string());
+        WslError {
+            output: self.output.clone(),
+            ..WslError::precondition(kind, self.hresult(), message)
+        }
+    }
+
+    /// Adds `context` to this error's message, keeping its HRESULT, kind
+    /// and output.
+    pub(crate) fn with_context(self, context: impl std::fmt::Display) -> Self {
+        let message = match self.message() {
+            Some(message) => format!("{} ({context})", message.trim_end()),
+            None => context.to_string(),
+        };
+        let underlying = match self.underlying {
+            UnderlyingError::Classified(kind, hresult, _) => {
+                UnderlyingError::Classified(kind, hresult, message)
+            }
+            UnderlyingError::Disk(failure, hresult, _) => {
+                UnderlyingError::Disk(failure, hresult, Some(message))
+            }
+            UnderlyingError::Io(e) => {
+                UnderlyingError::Io(std::io::Error::new(e.kind(), format!("{e} ({context})")))
+            }
+            _ => UnderlyingError::Hresult(self.hresult(), Some(message)),
+        };
+        WslError {
+            underlying,
+            output: self.output,
+        }
+    }
 
-        #[cfg(windows)]
+    /// The progress and warnings the service wrote before failing, for
+    /// operations that read the service's output themselves, such as
+    /// [`crate::Wsl2::set_version_with_progress`].
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    /// Attaches the service's output to this error.
+    pub(crate) fn with_output(self, output: String) -> Self {
+        WslError {
+            output: Some(output),
+            ..self
+        }
+    }
+
+    pub fn kind(&self) -> Option<WslErrorKind> {
+        use wsl_com_api_sys::error::*;
+        if let UnderlyingError::Classified(kind, _, _) = &self.underlying {
+            return Some(*kind);
+        }
         match self.hresult() {
+            WSL_E_DISTRO_NOT_FOUND | WSL_E_DEFAULT_DISTRO_NOT_FOUND => {
+                Some(WslErrorKind::DistributionNotFound)
+            }
+            WSL_E_DISTRO_NOT_STOPPED => Some(WslErrorKind::DistributionNotStopped),
+            WSL_E_WSL2_NEEDED => Some(WslErrorKind::Wsl2Needed),
+            WSL_E_DISK_ALREADY_ATTACHED | WSL_E_USER_VHD_ALREADY_ATTACHED => {
+                Some(WslErrorKind::DiskAlreadyAttached)
+            }
+            WSL_E_DISK_ALREADY_MOUNTED | WSL_E_VM_MODE_MOUNT_NAME_ALREADY_EXISTS => {
+                Some(WslErrorKind::DiskAlreadyMounted)
+            }
+            WSL_E_DISK_MOUNT_FAILED => Some(WslErrorKind::DiskMountFailed),
+            WSL_E_DISK_UNMOUNT_FAILED => Some(WslErrorKind::DiskUnmountFailed),
+            WSL_E_ELEVATION_NEEDED_TO_MOUNT_DISK => Some(WslErrorKind::ElevationNeeded),
+            WSL_E_FS_UPGRADE_NEEDED => Some(WslErrorKind::FilesystemUpgradeNeeded),
+            WSL_E_NOT_A_LINUX_DISTRO => Some(WslErrorKind::NotALinuxDistribution),
+            #[cfg(windows)]
             windows::Win32::Foundation::REGDB_E_CLASSNOTREG => {
                 Some(WslErrorKind::UnsupportedWslVersion)
             }
@@ -41,16 +316,40 @@ impl WslError {
 
 impl std::fmt::Display for WslError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let UnderlyingError::Io(e) = &self.underlying {
+            return write!(f, "I/O error: {}", e);
+        }
         let known_error = known_error(self.hresult());
-        if known_error.is_empty() {
-            write!(
+        let mut message = self.message();
+        if let Some(failure) = self.disk_failure() {
+            let stage = format!(
+                "failed while {} with Linux error {}",
+                failure.step, failure.errno
+            );
+            message = Some(match message {
+                Some(message) => format!("{} ({stage})", message.trim_end()),
+                None => stage,
+            });
+        }
+        match (known_error.is_empty(), message) {
+            (false, Some(message)) => {
+                write!(f, "WSL error: {}: {}", known_error, message.trim_end())
+            }
+            (false, None) => write!(f, "WSL error: {}", known_error),
+            (true, Some(message)) => {
+                write!(
+                    f,
+                    "WSL error 0x{:08x}: {}",
+                    self.hresult().0,
+                    message.trim_end()
+                )
+            }
+            (true, None) => write!(
                 f,
                 "Unknown WSL error 0x{:08x}: {}",
                 self.hresult().0,
-                self.hresult().message()
-            )
-        } else {
-            write!(f, "WSL error: {}", known_error)
+                system_message(self.hresult())
+            ),
         }
     }
 }
@@ -58,8 +357,10 @@ impl std::fmt::Display for WslError {
 impl std::error::Error for WslError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.underlying {
-            UnderlyingError::Lxss(_) => None,
+            #[cfg(windows)]
             UnderlyingError::Windows(e) => Some(e),
+            UnderlyingError::Io(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -68,6 +369,7 @@ impl From<wsl_com_api_sys::LxssError> for WslError {
     fn from(value: wsl_com_api_sys::LxssError) -> Self {
         WslError {
             underlying: UnderlyingError::Lxss(value),
+            output: None,
         }
     }
 }
@@ -76,10 +378,39 @@ impl From<windows::core::Error> for WslError {
     fn from(value: windows::core::Error) -> Self {
         WslError {
             underlying: UnderlyingError::Windows(value),
+            output: None,
+        }
+    }
+}
+
+impl From<HRESULT> for WslError {
+    fn from(value: HRESULT) -> Self {
+        WslError {
+            underlying: UnderlyingError::Hresult(value, None),
+            output: None,
         }
     }
 }
 
+impl From<std::io::Error> for WslError {
+    fn from(value: std::io::Error) -> Self {
+        WslError {
+            underlying: UnderlyingError::Io(value),
+            output: None,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn system_message(error: HRESULT) -> String {
+    error.message()
+}
+
+#[cfg(not(windows))]
+fn system_message(_: HRESULT) -> String {
+    "no system message on this platform".to_string()
+}
+
 fn known_error(error: HRESULT) -> &'static str {
     use wsl_com_api_sys::error::*;
     match error {
@@ -0,0 +1,923 @@
+//! An in-memory model of the WSL service, for testing code built on
+//! [`Wsl2`](crate::Wsl2) without Windows.
+//!
+//! ```
+//! use wsl_api::{fake::FakeBackend, RegisterOptions, Version, Wsl2};
+//!
+//! let fake = FakeBackend::new();
+//! let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+//!
+//! let wsl = Wsl2::with_backend(fake.clone());
+//! assert_eq!(wsl.get_default_distribution().unwrap(), ubuntu);
+//! ```
+
+use std::io::{PipeReader, PipeWriter, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStderr, ChildStdin, ChildStdout};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+use windows::core::HRESULT;
+use windows::Win32::Foundation::{
+    ERROR_ALREADY_EXISTS, ERROR_FILE_NOT_FOUND, E_ACCESSDENIED, E_INVALIDARG, HANDLE,
+};
+use wsl_com_api_sys::error::*;
+
+use crate::codec::Message;
+use crate::interop::Interop;
+use crate::{
+    with_handle, AttachFlags, CreateInstanceFlags, DiskFailure, DiskStep, Distribution,
+    DistributionConfiguration, DistributionFlags, DistributionState, ExportFlags, MountOptions,
+    OwnedRawHandle, RegisterOptions, Version, WslBackend, WslError, WslProcess, WslProcessInner,
+};
+
+/// A process launched in a [`FakeBackend`] distribution, as seen by the
+/// launch handler.
+pub struct FakeProcess {
+    pub distribution: Uuid,
+    pub command: String,
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+    pub username: String,
+    pub stdin: PipeReader,
+    pub stdout: PipeWriter,
+    pub stderr: PipeWriter,
+}
+
+pub(crate) type LaunchHandler = Arc<dyn Fn(FakeProcess) -> u32 + Send + Sync>;
+
+/// A [`WslBackend`] that keeps a registry of distributions in memory and
+/// fails with the same `WSL_E_*` codes as the real service.
+///
+/// Clones share the same state, so a test can keep one clone to set up and
+/// inspect the registry while [`Wsl2`](crate::Wsl2) owns another.
+#[derive(Clone, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    distributions: Vec<FakeDistribution>,
+    default: Option<Uuid>,
+    next_id: u128,
+    launch_handler: Option<LaunchHandler>,
+    disks: Vec<FakeDisk>,
+    /// The error the next registration fails with, if any
+    registration_failure: Option<HRESULT>,
+    /// The error the next detach fails with, if any
+    detach_failure: Option<HRESULT>,
+    /// The error the next enumeration fails with, if any
+    enumeration_failure: Option<HRESULT>,
+    /// The error the next configuration read fails with, if any
+    configuration_failure: Option<HRESULT>,
+}
+
+/// A disk attached to the fake WSL2 VM.
+struct FakeDisk {
+    path: PathBuf,
+    /// The mounted partitions, zero for the whole disk, and their names
+    mounts: Vec<(u32, String)>,
+}
+
+/// The filesystems the fake VM can mount.
+const FAKE_FILESYSTEMS: &[&str] = &["ext4", "ext3", "ext2", "xfs", "btrfs", "vfat", "ntfs"];
+
+/// Names a mount after its disk and partition, like the service does.
+fn default_mount_name(disk: &Path, partition: u32) -> String {
+    let disk = disk.to_string_lossy();
+    let file_name = disk.rsplit(['\\', '/']).next().unwrap_or_default();
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    match partition {
+        0 => stem.to_string(),
+        partition => format!("{stem}p{partition}"),
+    }
+}
+
+struct FakeDistribution {
+    uuid: Uuid,
+    name: String,
+    version: Version,
+    running: bool,
+    users: Vec<String>,
+    default_uid: u32,
+    default_environment: Vec<(String, String)>,
+    flags: DistributionFlags,
+    /// Where the distribution was last moved to
+    location: Option<PathBuf>,
+    /// The size the distribution's disk was last resized to
+    disk_size: Option<u64>,
+    sparse: bool,
+    /// Whether the distribution's filesystem must be upgraded before it
+    /// can start
+    needs_fs_upgrade: bool,
+}
+
+/// The flags the service gives a newly registered distribution.
+fn default_flags(version: Version) -> DistributionFlags {
+    let flags = DistributionFlags::ENABLE_INTEROP
+        | DistributionFlags::APPEND_NT_PATH
+        | DistributionFlags::ENABLE_DRIVE_MOUNTING;
+    if version == Version::WSL2 {
+        flags | DistributionFlags::VM_MODE
+    } else {
+        flags
+    }
+}
+
+/// Writes to an output handle that the caller still owns, as the service
+/// does with progress and warnings. Failures are ignored, as they are by the
+/// service.
+fn write_output(handle: HANDLE, text: &str) {
+    _ = with_handle(handle, |file| file.write_all(text.as_bytes()));
+}
+
+/// The tarball the fake exports: just enough of a root filesystem for
+/// [`tarball::inspect`](crate::tarball::inspect) to accept it.
+fn export_tarball(distro: &FakeDistribution) -> Vec<u8> {
+    let mut sh = b"\x7fELF\x02\x01\x01".to_vec();
+    sh.resize(18, 0);
+    sh.extend_from_slice(&62u16.to_le_bytes());
+    sh.resize(64, 0);
+    let os_release = format!("NAME=\"{}\"\nID=fake\n", distro.name);
+
+    let mut builder = tar::Builder::new(vec![]);
+    for (path, contents) in [
+        ("bin/sh", sh.as_slice()),
+        ("etc/os-release", os_release.as_bytes()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o755);
+        _ = builder.append_data(&mut header, path, contents);
+    }
+    builder.into_inner().unwrap_or_default()
+}
+
+/// Whether `bytes` is a tarball with at least one entry, as the service
+/// needs to import anything.
+fn is_tarball(bytes: &[u8]) -> bool {
+    let mut archive = tar::Archive::new(bytes);
+    match archive.entries() {
+        Ok(entries) => {
+            let mut count = 0;
+            for entry in entries {
+                if entry.is_err() {
+                    return false;
+                }
+                count += 1;
+            }
+            count > 0
+        }
+        Err(_) => false,
+    }
+}
+
+impl FakeState {
+    fn find(&self, distro_guid: Uuid) -> Result<&FakeDistribution, WslError> {
+        self.distributions
+            .iter()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WSL_E_DISTRO_NOT_FOUND.into())
+    }
+
+    fn find_mut(&mut self, distro_guid: Uuid) -> Result<&mut FakeDistribution, WslError> {
+        self.distributions
+            .iter_mut()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WSL_E_DISTRO_NOT_FOUND.into())
+    }
+
+    /// Fails the way the service does for a name or version it won't
+    /// register, before reading the tarball.
+    fn check_registration(&self, name: &str, version: Version) -> Result<(), WslError> {
+        if name.is_empty() {
+            return Err(WSL_E_DISTRIBUTION_NAME_NEEDED.into());
+        }
+        if !matches!(version, Version::WSL1 | Version::WSL2) {
+            return Err(E_INVALIDARG.into());
+        }
+        // Distribution names are case-insensitive, like the registry keys
+        // backing them
+        if self
+            .distributions
+            .iter()
+            .any(|distro| distro.name.eq_ignore_ascii_case(name))
+        {
+            return Err(ERROR_ALREADY_EXISTS.to_hresult().into());
+        }
+        Ok(())
+    }
+
+    fn register(&mut self, name: &str, version: Version) -> Result<Uuid, WslError> {
+        self.check_registration(name, version)?;
+        self.next_id += 1;
+        let uuid = Uuid::from_u128(self.next_id);
+        self.distributions.push(FakeDistribution {
+            uuid,
+            name: name.to_string(),
+            version,
+            running: false,
+            users: vec!["root".to_string()],
+            default_uid: 0,
+            default_environment: DistributionConfiguration::parse_environment([
+                "HOSTTYPE=x86_64",
+                "LANG=en_US.UTF-8",
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin:/usr/games:/usr/local/games",
+                "TERM=xterm-256color",
+            ]),
+            flags: default_flags(version),
+            location: None,
+            disk_size: None,
+            sparse: false,
+            needs_fs_upgrade: false,
+        });
+        self.default.get_or_insert(uuid);
+        Ok(uuid)
+    }
+}
+
+impl FakeBackend {
+    /// Creates a fake service with no distributions registered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes the next registration fail with `error`, as the service does
+    /// when it can't import a tarball.
+    pub fn fail_next_registration(&self, error: HRESULT) {
+        self.lock().registration_failure = Some(error);
+    }
+
+    /// Makes the next enumeration of the distributions fail with `error`.
+    pub fn fail_next_enumeration(&self, error: HRESULT) {
+        self.lock().enumeration_failure = Some(error);
+    }
+
+    /// Makes the next read of a distribution's configuration fail with
+    /// `error`.
+    pub fn fail_next_configuration(&self, error: HRESULT) {
+        self.lock().configuration_failure = Some(error);
+    }
+
+    /// Makes the next disk detach fail with `error`.
+    pub fn fail_next_detach(&self, error: HRESULT) {
+        self.lock().detach_failure = Some(error);
+    }
+
+    /// Registers a stopped distribution with a `root` user. The first
+    /// distribution registered becomes the default.
+    ///
+    /// Panics if the service would reject the registration.
+    pub fn add_distribution(&self, name: &str, version: Version) -> Uuid {
+        self.lock()
+            .register(name, version)
+            .expect("fake registration failed")
+    }
+
+    /// Adds a user that processes may be launched as.
+    pub fn add_user(&self, distro_guid: Uuid, username: &str) {
+        let mut state = self.lock();
+        let distro = state
+            .find_mut(distro_guid)
+            .expect("unknown fake distribution");
+        distro.users.push(username.to_string());
+    }
+
+    /// Sets the function that plays the part of every launched process. It
+    /// runs on its own thread and returns the exit code. Without a handler,
+    /// processes exit immediately with code zero.
+    pub fn on_launch(&self, handler: impl Fn(FakeProcess) -> u32 + Send + Sync + 'static) {
+        self.lock().launch_handler = Some(Arc::new(handler));
+    }
+
+    /// Returns the directory the distribution was last moved to, if it has
+    /// been moved.
+    pub fn location(&self, distro_guid: Uuid) -> Option<PathBuf> {
+        self.lock()
+            .find(distro_guid)
+            .ok()
+            .and_then(|distro| distro.location.clone())
+    }
+
+    /// Returns the size in bytes the distribution's disk was last resized
+    /// to, if it has been resized.
+    pub fn disk_size(&self, distro_guid: Uuid) -> Option<u64> {
+        self.lock()
+            .find(distro_guid)
+            .ok()
+            .and_then(|distro| distro.disk_size)
+    }
+
+    /// Returns whether the distribution's disk is sparse.
+    pub fn is_sparse(&self, distro_guid: Uuid) -> bool {
+        self.lock()
+            .find(distro_guid)
+            .map(|distro| distro.sparse)
+            .unwrap_or_default()
+    }
+
+    /// Returns the disks attached to the fake WSL2 VM, in the order they
+    /// were attached.
+    pub fn attached_disks(&self) -> Vec<PathBuf> {
+        self.lock()
+            .disks
+            .iter()
+            .map(|disk| disk.path.clone())
+            .collect()
+    }
+
+    /// Makes the distribution's filesystem need an upgrade, so that it only
+    /// starts with [`CreateInstanceFlags::ALLOW_FS_UPGRADE`](crate::CreateInstanceFlags::ALLOW_FS_UPGRADE).
+    pub fn require_fs_upgrade(&self, distro_guid: Uuid) {
+        let mut state = self.lock();
+        let distro = state
+            .find_mut(distro_guid)
+            .expect("unknown fake distribution");
+        distro.needs_fs_upgrade = true;
+    }
+
+    /// Returns whether the distribution is currently running.
+    pub fn is_running(&self, distro_guid: Uuid) -> bool {
+        self.lock()
+            .find(distro_guid)
+            .map(|distro| distro.running)
+            .unwrap_or_default()
+    }
+}
+
+impl WslBackend for FakeBackend {
+    fn shutdown(&self, _force: bool) -> Result<(), WslError> {
+        for distro in &mut self.lock().distributions {
+            distro.running = false;
+        }
+        Ok(())
+    }
+
+    fn get_default_distribution(&self) -> Result<Uuid, WslError> {
+        self.lock()
+            .default
+            .ok_or(WSL_E_DEFAULT_DISTRO_NOT_FOUND.into())
+    }
+
+    fn set_default_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        let mut state = self.lock();
+        state.find(distro_guid)?;
+        state.default = Some(distro_guid);
+        Ok(())
+    }
+
+    fn get_distribution_id(&self, name: &str, _flags: u32) -> Result<Uuid, WslError> {
+        self.lock()
+            .distributions
+            .iter()
+            .find(|distro| distro.name.eq_ignore_ascii_case(name))
+            .map(|distro| distro.uuid)
+            .ok_or(WSL_E_DISTRO_NOT_FOUND.into())
+    }
+
+    fn create_instance(
+        &self,
+        distro_guid: Uuid,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distro_guid)?;
+        if distro.needs_fs_upgrade {
+            if !flags.contains(CreateInstanceFlags::ALLOW_FS_UPGRADE) {
+                return Err(WSL_E_FS_UPGRADE_NEEDED.into());
+            }
+            distro.needs_fs_upgrade = false;
+        }
+        distro.running = true;
+        Ok(())
+    }
+
+    fn terminate_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.lock().find_mut(distro_guid)?.running = false;
+        Ok(())
+    }
+
+    fn unregister_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        let mut state = self.lock();
+        state.find(distro_guid)?;
+        state
+            .distributions
+            .retain(|distro| distro.uuid != distro_guid);
+        // The service promotes another distribution when the default goes
+        if state.default == Some(distro_guid) {
+            state.default = state.distributions.first().map(|distro| distro.uuid);
+        }
+        Ok(())
+    }
+
+    fn move_distribution(&self, distro_guid: Uuid, location: &Path) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distro_guid)?;
+        if distro.running {
+            return Err(WSL_E_DISTRO_NOT_STOPPED.into());
+        }
+        distro.location = Some(location.to_path_buf());
+        Ok(())
+    }
+
+    fn resize_distribution(
+        &self,
+        distro_guid: Uuid,
+        _output_handle: HANDLE,
+        new_size: u64,
+    ) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distro_guid)?;
+        if distro.version != Version::WSL2 {
+            return Err(WSL_E_WSL2_NEEDED.into());
+        }
+        if distro.running {
+            return Err(WSL_E_DISTRO_NOT_STOPPED.into());
+        }
+        distro.disk_size = Some(new_size);
+        Ok(())
+    }
+
+    fn set_sparse(
+        &self,
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    ) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distro_guid)?;
+        if distro.version != Version::WSL2 {
+            return Err(WSL_E_WSL2_NEEDED.into());
+        }
+        if distro.running {
+            return Err(WSL_E_DISTRO_NOT_STOPPED.into());
+        }
+        // Current releases of the service only make disks sparse when told
+        // that it is safe to
+        if sparse && !allow_unsafe {
+            return Err(WslError::with_message(
+                E_ACCESSDENIED,
+                "Sparse VHD support is currently disabled due to potential data corruption",
+            ));
+        }
+        distro.sparse = sparse;
+        Ok(())
+    }
+
+    fn launch(
+        &self,
+        distro_guid: Uuid,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError> {
+        let handler = {
+            let mut state = self.lock();
+            let distro = state.find_mut(distro_guid)?;
+            if !distro.users.iter().any(|user| user == username) {
+                return Err(WSL_E_USER_NOT_FOUND.into());
+            }
+            distro.running = true;
+            state.launch_handler.clone()
+        };
+
+        Ok(spawn_process(
+            distro_guid,
+            command,
+            args,
+            cwd,
+            username,
+            move |process| Some(handler.map_or(0, |handler| handler(process))),
+        ))
+    }
+
+    fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
+        let mut state = self.lock();
+        if let Some(error) = state.enumeration_failure.take() {
+            return Err(error.into());
+        }
+        Ok(state
+            .distributions
+            .iter()
+            .map(|distro| Distribution {
+                name: distro.name.clone(),
+                uuid: distro.uuid,
+                version: distro.version,
+                state: if distro.running {
+                    DistributionState::Running
+                } else {
+                    DistributionState::Stopped
+                },
+                // Like the service, the enumeration doesn't report flags
+                flags: None,
+                is_default: state.default == Some(distro.uuid),
+            })
+            .collect())
+    }
+
+    fn get_distribution_configuration(
+        &self,
+        distro_guid: Uuid,
+    ) -> Result<DistributionConfiguration, WslError> {
+        let mut state = self.lock();
+        if let Some(error) = state.configuration_failure.take() {
+            return Err(error.into());
+        }
+        let distro = state.find(distro_guid)?;
+        Ok(DistributionConfiguration {
+            name: distro.name.clone(),
+            version: distro.version,
+            default_uid: distro.default_uid,
+            default_environment: distro.default_environment.clone(),
+            flags: distro.flags,
+        })
+    }
+
+    fn configure_distribution(
+        &self,
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: DistributionFlags,
+    ) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distro_guid)?;
+        if !DistributionFlags::all().contains(flags) {
+            return Err(E_INVALIDARG.into());
+        }
+        // The mode flags belong to the service
+        let managed = DistributionFlags::all() - DistributionFlags::CONFIGURABLE;
+        distro.default_uid = default_uid;
+        distro.flags = (flags & DistributionFlags::CONFIGURABLE) | (distro.flags & managed);
+        Ok(())
+    }
+
+    fn export_distribution(
+        &self,
+        distro_guid: Uuid,
+        file_handle: HANDLE,
+        _stderr_handle: HANDLE,
+        _flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        let tarball = {
+            let state = self.lock();
+            let distro = state.find(distro_guid)?;
+            // WSL1 distributions are exported straight from the host
+            // filesystem, which must not be in use
+            if distro.version == Version::WSL1 && distro.running {
+                return Err(WSL_E_DISTRO_NOT_STOPPED.into());
+            }
+            export_tarball(distro)
+        };
+        // Written without the lock, as a pipe blocks until it is drained
+        with_handle(file_handle, |file| file.write_all(&tarball))
+            .map_err(|_| WslError::from(WSL_E_EXPORT_FAILED))
+    }
+
+    fn export_distribution_pipe(
+        &self,
+        distro_guid: Uuid,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        self.export_distribution(distro_guid, pipe_handle, stderr_handle, flags)
+    }
+
+    fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        {
+            let mut state = self.lock();
+            if let Some(error) = state.registration_failure.take() {
+                return Err(error.into());
+            }
+            state.check_registration(name, version)?;
+        }
+        // Read without the lock, as an export feeding a pipe needs it
+        let mut tarball = vec![];
+        let read = with_handle(file_handle, |file| file.read_to_end(&mut tarball));
+        if read.is_err() || !is_tarball(&tarball) {
+            // The service passes on what its tar had to say
+            write_output(
+                stderr_handle,
+                "bsdtar: Error opening archive: Unrecognized archive format\n",
+            );
+            return Err(WSL_E_IMPORT_FAILED.into());
+        }
+        let mut state = self.lock();
+        let uuid = state.register(name, version)?;
+        let distro = state.find_mut(uuid)?;
+        distro.location = options.target_directory.clone();
+        distro.disk_size = options.vhd_size.map(|size| size.bytes());
+        Ok((uuid, name.to_string()))
+    }
+
+    fn register_distribution_pipe(
+        &self,
+        name: &str,
+        version: Version,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        self.register_distribution(name, version, pipe_handle, stderr_handle, options)
+    }
+
+    fn import_distribution_in_place(&self, name: &str, vhd_path: &Path) -> Result<Uuid, WslError> {
+        let mut state = self.lock();
+        let uuid = state.register(name, Version::WSL2)?;
+        state.find_mut(uuid)?.location = vhd_path.parent().map(Path::to_path_buf);
+        Ok(uuid)
+    }
+
+    fn set_version(
+        &self,
+        distribution: Uuid,
+        version: Version,
+        stderr_handle: HANDLE,
+    ) -> Result<(), WslError> {
+        let mut state = self.lock();
+        let distro = state.find_mut(distribution)?;
+        if !matches!(version, Version::WSL1 | Version::WSL2) {
+            return Err(E_INVALIDARG.into());
+        }
+        if distro.running {
+            return Err(WSL_E_DISTRO_NOT_STOPPED.into());
+        }
+        if distro.version == version {
+            return Err(WSL_E_VM_MODE_INVALID_STATE.into());
+        }
+        write_output(
+            stderr_handle,
+            "Conversion in progress, this may take a few minutes.\r\n",
+        );
+        distro.version = version;
+        distro
+            .flags
+            .set(DistributionFlags::VM_MODE, version == Version::WSL2);
+        Ok(())
+    }
+
+    fn attach_disk(&self, disk: &Path, flags: AttachFlags) -> Result<(), WslError> {
+        let mut state = self.lock();
+        if flags.contains(AttachFlags::VHD) && !disk.exists() {
+            return Err(ERROR_FILE_NOT_FOUND.to_hresult().into());
+        }
+        if state.disks.iter().any(|attached| attached.path == disk) {
+            return Err(WSL_E_DISK_ALREADY_ATTACHED.into());
+        }
+        state.disks.push(FakeDisk {
+            path: disk.to_path_buf(),
+            mounts: vec![],
+        });
+        Ok(())
+    }
+
+    fn mount_disk(&self, disk: &Path, options: &MountOptions) -> Result<String, WslError> {
+        let mut state = self.lock();
+        if options.flags.contains(AttachFlags::VHD) && !disk.exists() {
+            return Err(ERROR_FILE_NOT_FOUND.to_hresult().into());
+        }
+        let partition = options.partition.unwrap_or(0);
+        let name = options
+            .name
+            .clone()
+            .unwrap_or_else(|| default_mount_name(disk, partition));
+        if state
+            .disks
+            .iter()
+            .flat_map(|attached| &attached.mounts)
+            .any(|(_, mounted)| *mounted == name)
+        {
+            return Err(WSL_E_VM_MODE_MOUNT_NAME_ALREADY_EXISTS.into());
+        }
+        let filesystem_type = options.filesystem_type.as_deref().unwrap_or("ext4");
+        if !FAKE_FILESYSTEMS.contains(&filesystem_type) {
+            // ENODEV, as mount(2) reports for an unknown filesystem type
+            let failure = DiskFailure {
+                step: DiskStep::Mount,
+                errno: 19,
+            };
+            return Err(WslError::disk(WSL_E_DISK_MOUNT_FAILED, failure, None));
+        }
+
+        let index = match state
+            .disks
+            .iter()
+            .position(|attached| attached.path == disk)
+        {
+            Some(index) => index,
+            None => {
+                state.disks.push(FakeDisk {
+                    path: disk.to_path_buf(),
+                    mounts: vec![],
+                });
+                state.disks.len() - 1
+            }
+        };
+        let attached = &mut state.disks[index];
+        if attached
+            .mounts
+            .iter()
+            .any(|(mounted, _)| *mounted == partition)
+        {
+            return Err(WSL_E_DISK_ALREADY_MOUNTED.into());
+        }
+        attached.mounts.push((partition, name.clone()));
+        Ok(name)
+    }
+
+    fn detach_disk(&self, disk: Option<&Path>) -> Result<(), WslError> {
+        let mut state = self.lock();
+        if let Some(error) = state.detach_failure.take() {
+            return Err(error.into());
+        }
+        match disk {
+            Some(disk) => {
+                let index = state
+                    .disks
+                    .iter()
+                    .position(|attached| attached.path == disk)
+                    .ok_or(WslError::from(ERROR_FILE_NOT_FOUND.to_hresult()))?;
+                state.disks.remove(index);
+            }
+            None => state.disks.clear(),
+        }
+        Ok(())
+    }
+}
+
+/// Starts a stand-in process on its own thread, connected to the returned
+/// [`WslProcess`] the same way a WSL2 process is. `handler` plays the part
+/// of the process and returns its exit code, or `None` to close the interop
+/// socket without one, which makes waiting for the process fail.
+pub(crate) fn spawn_process(
+    distro_guid: Uuid,
+    command: &str,
+    args: &[&str],
+    cwd: Option<&str>,
+    username: &str,
+    handler: impl FnOnce(FakeProcess) -> Option<u32> + Send + 'static,
+) -> WslProcess {
+    let (stdin_r, stdin_w) = std::io::pipe().unwrap();
+    let (stdout_r, stdout_w) = std::io::pipe().unwrap();
+    let (stderr_r, stderr_w) = std::io::pipe().unwrap();
+
+    // Like the service, report the exit status over a socket using the
+    // LX_INIT interop protocol
+    let (interop, mut peer) = socket_pair().unwrap();
+
+    let process = FakeProcess {
+        distribution: distro_guid,
+        command: command.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        cwd: cwd.map(str::to_string),
+        username: username.to_string(),
+        stdin: stdin_r,
+        stdout: stdout_w,
+        stderr: stderr_w,
+    };
+
+    thread::spawn(move || {
+        if let Some(exit_code) = handler(process) {
+            let message = Message::ExitStatus { exit_code }.encode().unwrap();
+            _ = peer.write_all(&message);
+        }
+    });
+
+    WslProcess {
+        stdin: Some(ChildStdin::from(OwnedRawHandle::from(stdin_w))),
+        stdout: Some(ChildStdout::from(OwnedRawHandle::from(stdout_r))),
+        stderr: Some(ChildStderr::from(OwnedRawHandle::from(stderr_r))),
+        pipe: None,
+        handle: WslProcessInner::WSL2(Interop::new(interop), None),
+    }
+}
+
+/// Connects two local TCP sockets to each other.
+fn socket_pair() -> std::io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    let (server, _) = listener.accept()?;
+    Ok((client, server))
+}
+
+enum PeerAction {
+    Send(Vec<u8>),
+    Delay(Duration),
+    WaitForClose,
+}
+
+/// A scripted stand-in for the Linux end of a WSL2 process' interop socket.
+///
+/// The script runs on its own thread once the peer is connected, and the
+/// socket is closed when it finishes. Scripts can send well-formed messages
+/// or arbitrary bytes, which makes it possible to check how
+/// [`WslProcess::wait`] copes with a peer that misbehaves.
+///
+/// ```
+/// use std::time::Duration;
+/// use wsl_api::codec::Message;
+/// use wsl_api::fake::InteropPeer;
+///
+/// let mut process = InteropPeer::new()
+///     .send(&Message::WindowSizeChanged { rows: 25, columns: 80 })
+///     .delay(Duration::from_millis(50))
+///     .exit(3)
+///     .into_process();
+/// assert!(process.wait_timeout(Duration::ZERO).unwrap().is_none());
+/// assert_eq!(process.wait().unwrap().code(), Some(3));
+/// ```
+#[derive(Default)]
+pub struct InteropPeer {
+    actions: Vec<PeerAction>,
+}
+
+impl InteropPeer {
+    /// Creates a peer with an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends an encoded message.
+    ///
+    /// Panics if the message cannot be encoded.
+    pub fn send(self, message: &Message) -> Self {
+        self.send_raw(message.encode().expect("message should encode"))
+    }
+
+    /// Sends the process' exit status.
+    pub fn exit(self, exit_code: u32) -> Self {
+        self.send(&Message::ExitStatus { exit_code })
+    }
+
+    /// Sends bytes exactly as given, eg: a truncated or corrupt frame.
+    pub fn send_raw(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.actions.push(PeerAction::Send(bytes.into()));
+        self
+    }
+
+    /// Pauses before the next action.
+    pub fn delay(mut self, duration: Duration) -> Self {
+        self.actions.push(PeerAction::Delay(duration));
+        self
+    }
+
+    /// Keeps the socket open until the other end closes it.
+    pub fn wait_for_close(mut self) -> Self {
+        self.actions.push(PeerAction::WaitForClose);
+        self
+    }
+
+    /// Starts the script, returning the Windows end of the socket and the
+    /// script's thread.
+    pub fn spawn(self) -> std::io::Result<(TcpStream, thread::JoinHandle<std::io::Result<()>>)> {
+        let (local, mut peer) = socket_pair()?;
+        let thread = thread::spawn(move || {
+            for action in self.actions {
+                match action {
+                    PeerAction::Send(bytes) => {
+                        peer.write_all(&bytes)?;
+                        peer.flush()?;
+                    }
+                    PeerAction::Delay(duration) => thread::sleep(duration),
+                    PeerAction::WaitForClose => {
+                        // Anything sent our way is ignored
+                        std::io::copy(&mut peer, &mut std::io::sink())?;
+                    }
+                }
+            }
+            Ok(())
+        });
+        Ok((local, thread))
+    }
+
+    /// Starts the script and returns a process without stdio whose exit
+    /// status comes from this peer.
+    pub fn into_process(self) -> WslProcess {
+        let (local, _) = self.spawn().expect("failed to connect interop peer");
+        WslProcess {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            pipe: None,
+            handle: WslProcessInner::WSL2(Interop::new(local), None),
+        }
+    }
+}
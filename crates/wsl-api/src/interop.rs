@@ -1,15 +1,17 @@
 use std::{
-    io::{BufRead, BufReader, Read},
+    io::BufReader,
     net::TcpStream,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
     thread,
+    time::Duration,
 };
-use wsl_com_api_sys::constants::*;
-use wsl_com_api_sys::interop::{LX_INIT_PROCESS_EXIT_STATUS, MESSAGE_HEADER};
+
+use crate::codec::Message;
 
 #[derive(Debug)]
 pub struct Interop {
-    term: Receiver<u32>,
+    term: Receiver<std::io::Result<u32>>,
+    exit_code: Option<u32>,
     _thread: thread::JoinHandle<()>,
 }
 
@@ -18,52 +20,53 @@ impl Interop {
         let (tx, rx) = mpsc::channel();
 
         let thread_handle = thread::spawn(move || {
-            let _ = Self::process_messages(socket, tx);
+            let _ = tx.send(Self::process_messages(socket));
         });
 
         Self {
             term: rx,
+            exit_code: None,
             _thread: thread_handle,
         }
     }
 
-    fn process_messages(socket: TcpStream, tx: Sender<u32>) -> std::io::Result<()> {
+    /// Reads messages until the exit status arrives, skipping any others,
+    /// such as terminal resizes. The socket closing first, or a malformed
+    /// message, is an error.
+    fn process_messages(socket: TcpStream) -> std::io::Result<u32> {
         let mut reader = BufReader::new(&socket);
-        let mut buffer = [0u8; 1024];
 
         loop {
-            let header = unsafe { read_direct::<MESSAGE_HEADER, _>(&mut reader)? };
-            match header.MessageType {
-                LX_INIT_MESSAGE_EXIT_STATUS => {
-                    let exit_status =
-                        unsafe { read_direct::<LX_INIT_PROCESS_EXIT_STATUS, _>(&mut reader)? };
-                    let _ = tx.send(exit_status.ExitCode);
-                    return Ok(());
-                }
-                unknown => {
-                    eprintln!("Unknown message type: {}", unknown);
-                    // Unknown message type, try to read the rest of the message
-                    let remaining_size =
-                        header.MessageSize as usize - std::mem::size_of::<MESSAGE_HEADER>();
-                    if remaining_size > 0 && remaining_size <= buffer.len() {
-                        let _ = reader.read_exact(&mut buffer[..remaining_size]);
-                    }
-                }
+            if let Message::ExitStatus { exit_code } = Message::read_from(&mut reader)? {
+                return Ok(exit_code);
             }
         }
     }
 
-    pub fn recv_exit_code(&self) -> Option<u32> {
-        self.term.recv().ok()
-    }
-}
+    /// Waits for the process' exit code, returning `None` if `timeout`
+    /// elapses first.
+    pub fn recv_exit_code(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<u32>> {
+        if let Some(exit_code) = self.exit_code {
+            return Ok(Some(exit_code));
+        }
+
+        let result = match timeout {
+            None => self.term.recv().ok(),
+            Some(timeout) => match self.term.recv_timeout(timeout) {
+                Ok(result) => Some(result),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
 
-/// Safety: the value you read must be POD.
-unsafe fn read_direct<T, S: std::io::Read>(reader: &mut BufReader<S>) -> std::io::Result<T> {
-    while reader.buffer().len() < std::mem::size_of::<T>() {
-        reader.fill_buf()?;
+        // The reader only sends once, so a second failure finds it gone
+        let exit_code = result.unwrap_or_else(|| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "interop reader has stopped",
+            ))
+        })?;
+        self.exit_code = Some(exit_code);
+        Ok(Some(exit_code))
     }
-    let res = std::ptr::read_unaligned(reader.buffer().as_ptr() as *const T);
-    reader.consume(std::mem::size_of::<T>());
-    Ok(res)
 }
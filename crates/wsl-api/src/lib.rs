@@ -1,612 +1,2174 @@
-use std::ffi::CString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{ChildStderr, ChildStdin, ChildStdout, ExitStatus};
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::thread::{self, JoinHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use windows::core::{IUnknown, Interface, GUID, PCSTR, PCWSTR};
-use windows::Win32::Foundation::{CloseHandle, GetLastError, HANDLE};
-use windows::Win32::Networking::WinSock::WSAStartup;
-use windows::Win32::Storage::FileSystem::{
-    GetFileType, FILE_TYPE_CHAR, FILE_TYPE_DISK, FILE_TYPE_PIPE, FILE_TYPE_REMOTE,
-    FILE_TYPE_UNKNOWN,
-};
-use windows::Win32::System::Com::{
-    CoInitializeEx, CoInitializeSecurity, CoTaskMemFree, CoUninitialize, IClientSecurity,
-    COINIT_MULTITHREADED, EOAC_DYNAMIC_CLOAKING, EOAC_STATIC_CLOAKING,
-    EOLE_AUTHENTICATION_CAPABILITIES, RPC_C_AUTHN_LEVEL, RPC_C_AUTHN_LEVEL_CONNECT,
-    RPC_C_IMP_LEVEL, RPC_C_IMP_LEVEL_IDENTIFY, RPC_C_IMP_LEVEL_IMPERSONATE,
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{
+    ERROR_ALREADY_EXISTS, ERROR_DIRECTORY, ERROR_FILE_EXISTS, ERROR_FILE_NOT_FOUND, ERROR_TIMEOUT,
+    E_INVALIDARG, HANDLE,
 };
+#[cfg(windows)]
 use windows::Win32::System::IO::DeviceIoControl;
-use wsl_com_api_sys::{
-    constants::*, get_lxss_user_session, ILxssUserSession, LxssHandleType, LXSS_ENUMERATE_INFO,
-    LXSS_HANDLE, LXSS_STD_HANDLES,
-};
+use wsl_com_api_sys::{constants::*, LXSS_ENUMERATE_INFO};
+
+#[cfg(windows)]
+use wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_PARAMETERS;
+
+mod backend;
+pub use backend::WslBackend;
+pub mod codec;
+mod com;
+pub use com::ComBackend;
+mod error;
+pub use error::*;
+pub mod fake;
+mod interop;
+mod output;
+pub use output::Captured;
+mod size;
+pub use size::DiskSize;
+pub mod snapshot;
+pub mod tarball;
+pub mod transcript;
+pub mod watch;
+
+// Allows this code to compile on both Windows and Unix
+
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle as OwnedRawHandle;
+#[cfg(windows)]
+fn to_handle(handle: &impl AsRawHandle) -> HANDLE {
+    HANDLE(handle.as_raw_handle() as isize)
+}
+#[cfg(windows)]
+fn from_handle<T: From<std::os::windows::io::OwnedHandle>>(handle: HANDLE) -> T {
+    use std::os::windows::io::FromRawHandle;
+    unsafe {
+        T::from(std::os::windows::io::OwnedHandle::from_raw_handle(
+            handle.0 as _,
+        ))
+    }
+}
+#[cfg(windows)]
+unsafe fn close_handle(handle: HANDLE) {
+    _ = windows::Win32::Foundation::CloseHandle(handle);
+}
+
+#[cfg(unix)]
+use std::os::fd::AsRawFd as AsRawHandle;
+#[cfg(unix)]
+use std::os::fd::OwnedFd as OwnedRawHandle;
+
+use crate::interop::Interop;
+/// On Unix, file descriptors stand in for handles so that non-COM backends
+/// can still be driven through the public API.
+#[cfg(unix)]
+fn to_handle(handle: &impl AsRawHandle) -> HANDLE {
+    HANDLE(handle.as_raw_fd() as isize)
+}
+#[cfg(unix)]
+fn from_handle<T>(_: HANDLE) -> T {
+    unreachable!("This should never be called on Unix: we only support Windows");
+}
+#[cfg(unix)]
+unsafe fn close_handle(_: HANDLE) {
+    unreachable!("This should never be called on Unix: we only support Windows");
+}
+
+/// Uses a handle that the caller still owns as a file, without closing it.
+fn with_handle<T>(handle: HANDLE, f: impl FnOnce(&mut std::fs::File) -> T) -> T {
+    #[cfg(unix)]
+    let file: std::fs::File =
+        unsafe { std::os::fd::FromRawFd::from_raw_fd(handle.0 as std::os::fd::RawFd) };
+    #[cfg(windows)]
+    let file: std::fs::File =
+        unsafe { std::os::windows::io::FromRawHandle::from_raw_handle(handle.0 as _) };
+    f(&mut std::mem::ManuallyDrop::new(file))
+}
+
+/// WSL-specific process waiting function that uses LXBUS IOCTL
+#[cfg(windows)]
+unsafe fn wait_for_wsl_process(process_handle: HANDLE, timeout_ms: u32) -> Result<u32, WslError> {
+    let mut parameters = LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_PARAMETERS {
+        Input: wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_INPUT {
+            TimeoutMs: timeout_ms,
+        },
+    };
+
+    DeviceIoControl(
+        process_handle,
+        LXBUS_IPC_LX_PROCESS_IOCTL_WAIT_FOR_TERMINATION,
+        Some(&parameters.Input as *const _ as *const _),
+        std::mem::size_of::<wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_INPUT>(
+        ) as u32,
+        Some(&mut parameters.Output as *mut _ as *mut _),
+        std::mem::size_of::<
+            wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_OUTPUT,
+        >() as u32,
+        None,
+        None,
+    )?;
+
+    // The exit status is stored shifted, with status in the lower 8 bits
+    Ok((parameters.Output.ExitStatus as u32) >> 8)
+}
+
+#[cfg(unix)]
+unsafe fn wait_for_wsl_process(_: HANDLE, _: u32) -> Result<u32, WslError> {
+    unreachable!("This should never be called on Unix: we only support Windows");
+}
+
+/// A higher-level API for interacting with WSL2, on top of a [`WslBackend`]
+/// that talks to the service: a [`ComBackend`] in production, or a
+/// [`fake::FakeBackend`] or [`transcript::ReplayBackend`] in tests.
+pub struct Wsl2 {
+    /// The backend that services requests (COM in production)
+    backend: Box<dyn WslBackend>,
+}
+
+impl Wsl2 {
+    /// Creates a new WSL API instance with a background COM thread
+    pub fn new() -> Result<Self, WslError> {
+        Ok(Self::with_backend(ComBackend::new()?))
+    }
+
+    /// Creates a WSL API instance that routes every request through the
+    /// given backend (for example, [`fake::FakeBackend`] in tests).
+    pub fn with_backend(backend: impl WslBackend + 'static) -> Self {
+        Wsl2 {
+            backend: Box::new(backend),
+        }
+    }
+
+    /// Shuts down WSL and closes this handle.
+    pub fn shutdown(self, force: bool) -> Result<(), WslError> {
+        self.backend.shutdown(force)
+    }
+
+    /// Gets the default distribution.
+    pub fn get_default_distribution(&self) -> Result<Uuid, WslError> {
+        self.backend.get_default_distribution()
+    }
+
+    /// Makes a distribution the default, which is the one used when no
+    /// distribution is named.
+    pub fn set_default_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+    ) -> Result<(), WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.backend.set_default_distribution(distro_guid)
+    }
+
+    /// Makes a distribution the default until the returned guard is dropped,
+    /// at which point the previous default is restored.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let debian = fake.add_distribution("Debian", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// {
+    ///     let _guard = wsl.set_default_distribution_scoped(debian).unwrap();
+    ///     assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+    /// }
+    /// assert_eq!(wsl.get_default_distribution().unwrap(), ubuntu);
+    /// ```
+    pub fn set_default_distribution_scoped(
+        &self,
+        distribution: impl Into<DistributionRef>,
+    ) -> Result<DefaultDistributionGuard<'_>, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let previous = match self.backend.get_default_distribution() {
+            Ok(previous) => Some(previous),
+            Err(e) if e.hresult() == wsl_com_api_sys::error::WSL_E_DEFAULT_DISTRO_NOT_FOUND => None,
+            Err(e) => return Err(e),
+        };
+        self.backend.set_default_distribution(distro_guid)?;
+        Ok(DefaultDistributionGuard {
+            wsl: self,
+            previous,
+        })
+    }
+
+    /// Resolves a distribution reference to the distribution's GUID. Names
+    /// that don't match a registered distribution fail with
+    /// [`WslErrorKind::DistributionNotFound`].
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DistributionRef, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// assert_eq!(wsl.resolve_distribution("ubuntu").unwrap(), ubuntu);
+    /// assert_eq!(wsl.resolve_distribution(DistributionRef::Default).unwrap(), ubuntu);
+    /// let err = wsl.resolve_distribution("Debian").unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    /// ```
+    pub fn resolve_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+    ) -> Result<Uuid, WslError> {
+        match distribution.into() {
+            DistributionRef::Default => self.backend.get_default_distribution(),
+            DistributionRef::Uuid(uuid) => Ok(uuid),
+            DistributionRef::Name(name) => match self.backend.get_distribution_id(&name, 0) {
+                Err(e) if e.hresult() == wsl_com_api_sys::error::WSL_E_DISTRO_NOT_FOUND => {
+                    Err(WslError::with_message(
+                        e.hresult(),
+                        format!("There is no distribution with the name '{}'", name),
+                    ))
+                }
+                res => res,
+            },
+        }
+    }
+
+    /// Starts a distribution without launching a process in it, so that
+    /// services such as systemd come up ahead of the first command. Starting
+    /// a running distribution does nothing.
+    ///
+    /// A WSL1 distribution whose filesystem is from an older version of WSL
+    /// fails with [`WslErrorKind::FilesystemUpgradeNeeded`] unless
+    /// [`CreateInstanceFlags::ALLOW_FS_UPGRADE`] is given. Note that WSL
+    /// stops a distribution that has been idle for a while.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, CreateInstanceFlags, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let legacy = fake.add_distribution("Legacy", Version::WSL1);
+    /// fake.require_fs_upgrade(legacy);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// let err = wsl.start_distribution(legacy, CreateInstanceFlags::empty()).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::FilesystemUpgradeNeeded));
+    /// wsl.start_distribution(legacy, CreateInstanceFlags::ALLOW_FS_UPGRADE).unwrap();
+    /// ```
+    pub fn start_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.backend.create_instance(distro_guid, flags)
+    }
+
+    /// Starts a distribution like [`Wsl2::start_distribution`] if it isn't
+    /// already running. Returns whether it had to be started.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, CreateInstanceFlags, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// assert!(wsl.ensure_running("Ubuntu", CreateInstanceFlags::empty()).unwrap());
+    /// assert!(!wsl.ensure_running("Ubuntu", CreateInstanceFlags::empty()).unwrap());
+    /// ```
+    pub fn ensure_running(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        flags: CreateInstanceFlags,
+    ) -> Result<bool, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let running = self
+            .backend
+            .enumerate_distributions()?
+            .into_iter()
+            .any(|distro| distro.uuid == distro_guid && distro.state == DistributionState::Running);
+        if running {
+            return Ok(false);
+        }
+        self.backend.create_instance(distro_guid, flags)?;
+        Ok(true)
+    }
+
+    /// Stops a distribution and every process running in it.
+    pub fn terminate_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+    ) -> Result<(), WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.backend.terminate_distribution(distro_guid)
+    }
+
+    /// Unregisters a distribution, permanently deleting its filesystem.
+    ///
+    /// As a guard against removing the wrong distribution, `expected_name`
+    /// must match the distribution's current name (ignoring case) or the
+    /// call fails with [`WslErrorKind::DistributionNameMismatch`]. The
+    /// default distribution is only removed when `force` is set, and
+    /// otherwise fails with [`WslErrorKind::DistributionIsDefault`].
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let scratch = fake.add_distribution("Scratch", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// let err = wsl.unregister_distribution(scratch, "Ubuntu", false).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionNameMismatch));
+    /// let err = wsl.unregister_distribution(ubuntu, "Ubuntu", false).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionIsDefault));
+    ///
+    /// wsl.unregister_distribution(scratch, "Scratch", false).unwrap();
+    /// ```
+    pub fn unregister_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        expected_name: &str,
+        force: bool,
+    ) -> Result<(), WslError> {
+        use wsl_com_api_sys::error::*;
+
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let distro = self
+            .backend
+            .enumerate_distributions()?
+            .into_iter()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WslError::from(WSL_E_DISTRO_NOT_FOUND))?;
+        if !distro.name.eq_ignore_ascii_case(expected_name) {
+            return Err(WslError::precondition(
+                WslErrorKind::DistributionNameMismatch,
+                WSL_E_INVALID_USAGE,
+                format!(
+                    "Distribution {{{}}} is named '{}', not '{}'",
+                    distro_guid, distro.name, expected_name
+                ),
+            ));
+        }
+
+        if !force {
+            match self.backend.get_default_distribution() {
+                Ok(default) if default == distro_guid => {
+                    return Err(WslError::precondition(
+                        WslErrorKind::DistributionIsDefault,
+                        WSL_E_INVALID_USAGE,
+                        format!(
+                            "'{}' is the default distribution and must be forced to be unregistered",
+                            distro.name
+                        ),
+                    ));
+                }
+                Err(e) if e.hresult() != WSL_E_DEFAULT_DISTRO_NOT_FOUND => return Err(e),
+                _ => {}
+            }
+        }
+
+        self.backend.unregister_distribution(distro_guid)
+    }
+
+    /// Moves a distribution's disk to `target_dir`, creating the directory if
+    /// it doesn't exist.
+    ///
+    /// The distribution must be stopped: a running distribution is
+    /// terminated first if `terminate` is set, and otherwise the call fails
+    /// with [`WslErrorKind::DistributionNotStopped`]. A target that isn't a
+    /// directory, or that already holds a distribution's disk, fails with
+    /// [`WslErrorKind::InvalidPath`].
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake.clone());
+    /// let target = std::env::temp_dir().join("wsl-api-move-example");
+    ///
+    /// wsl.launch(ubuntu, "/bin/true", &[], None, "root").unwrap().wait().unwrap();
+    /// let err = wsl.move_distribution(ubuntu, &target, false).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotStopped));
+    ///
+    /// wsl.move_distribution(ubuntu, &target, true).unwrap();
+    /// assert_eq!(fake.location(ubuntu), Some(target.clone()));
+    /// # std::fs::remove_dir_all(&target).unwrap();
+    /// ```
+    pub fn move_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        target_dir: impl AsRef<Path>,
+        terminate: bool,
+    ) -> Result<(), WslError> {
+        use wsl_com_api_sys::error::*;
+
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let distro = self
+            .backend
+            .enumerate_distributions()?
+            .into_iter()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WslError::from(WSL_E_DISTRO_NOT_FOUND))?;
+
+        // The service resolves relative paths against its own working
+        // directory, not ours
+        let target_dir = std::path::absolute(target_dir.as_ref())?;
+        if target_dir.exists() && !target_dir.is_dir() {
+            return Err(WslError::precondition(
+                WslErrorKind::InvalidPath,
+                ERROR_DIRECTORY.to_hresult(),
+                format!("{} is not a directory", target_dir.display()),
+            ));
+        }
+        if target_dir.join("ext4.vhdx").exists() {
+            return Err(WslError::precondition(
+                WslErrorKind::InvalidPath,
+                ERROR_FILE_EXISTS.to_hresult(),
+                format!(
+                    "{} already contains a distribution's disk",
+                    target_dir.display()
+                ),
+            ));
+        }
+
+        if distro.state != DistributionState::Stopped {
+            if !terminate {
+                return Err(WslError::with_message(
+                    WSL_E_DISTRO_NOT_STOPPED,
+                    format!(
+                        "'{}' is {} and must be stopped before it can be moved",
+                        distro.name,
+                        distro.state.to_string().to_lowercase()
+                    ),
+                ));
+            }
+            self.backend.terminate_distribution(distro_guid)?;
+        }
+
+        std::fs::create_dir_all(&target_dir)?;
+        self.backend.move_distribution(distro_guid, &target_dir)
+    }
+
+    /// Resizes a WSL2 distribution's virtual disk, passing each line of the
+    /// service's progress output to `progress` as it arrives.
+    ///
+    /// Before resizing, the space in use is measured by running `df -Pk /` in
+    /// the distribution as root, and sizes smaller than that fail with
+    /// [`WslErrorKind::DiskTooSmall`]. The distribution must be stopped to be
+    /// resized: it is stopped again after being measured, and a distribution
+    /// that was already running is only terminated if `terminate` is set,
+    /// failing with [`WslErrorKind::DistributionNotStopped`] otherwise. A
+    /// running distribution is measured as it is, and is left running if it
+    /// doesn't fit or can't be measured.
+    ///
+    /// Measuring boots a stopped distribution, starting the WSL2 VM if
+    /// needed and running whatever the distribution runs at boot, such as
+    /// systemd or the `[boot]` command of its `wsl.conf`. The resize can
+    /// therefore take several seconds longer than the service's own call.
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use wsl_api::{fake::FakeBackend, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// fake.on_launch(|mut process| {
+    ///     // 2GB in use
+    ///     writeln!(process.stdout, "Filesystem 1024-blocks Used Available Capacity Mounted on").unwrap();
+    ///     writeln!(process.stdout, "/dev/sdc 10485760 2097152 8388608 20% /").unwrap();
+    ///     0
+    /// });
+    /// let wsl = Wsl2::with_backend(fake.clone());
+    ///
+    /// let err = wsl
+    ///     .resize_distribution(ubuntu, "1GB".parse().unwrap(), false, |_| {})
+    ///     .unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DiskTooSmall));
+    ///
+    /// wsl.resize_distribution(ubuntu, "20GB".parse().unwrap(), false, |line| println!("{line}"))
+    ///     .unwrap();
+    /// assert_eq!(fake.disk_size(ubuntu), Some(20 << 30));
+    /// ```
+    pub fn resize_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        size: DiskSize,
+        terminate: bool,
+        progress: impl FnMut(&str) + Send,
+    ) -> Result<(), WslError> {
+        use wsl_com_api_sys::error::*;
+
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let distro = self
+            .backend
+            .enumerate_distributions()?
+            .into_iter()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WslError::from(WSL_E_DISTRO_NOT_FOUND))?;
+        if distro.version != Version::WSL2 {
+            return Err(WslError::with_message(
+                WSL_E_WSL2_NEEDED,
+                format!(
+                    "'{}' is a WSL1 distribution and has no virtual disk to resize",
+                    distro.name
+                ),
+            ));
+        }
+        if distro.state != DistributionState::Stopped && !terminate {
+            return Err(WslError::with_message(
+                WSL_E_DISTRO_NOT_STOPPED,
+                format!(
+                    "'{}' is {} and must be stopped before it can be resized",
+                    distro.name,
+                    distro.state.to_string().to_lowercase()
+                ),
+            ));
+        }
+
+        let fits = self.used_disk_space(distro_guid).and_then(|used| {
+            if size.bytes() < used {
+                return Err(WslError::precondition(
+                    WslErrorKind::DiskTooSmall,
+                    E_INVALIDARG,
+                    format!(
+                        "'{}' is using {}, which does not fit in {}",
+                        distro.name,
+                        DiskSize::from_bytes(used),
+                        size
+                    ),
+                ));
+            }
+            Ok(())
+        });
+        // Measuring boots a stopped distribution, so stop it whatever the
+        // outcome, but only terminate a running one once the size is known
+        // to fit
+        if distro.state == DistributionState::Stopped || fits.is_ok() {
+            self.backend.terminate_distribution(distro_guid)?;
+        }
+        fits?;
+
+        output::with_output_pipe(progress, |output| {
+            self.backend
+                .resize_distribution(distro_guid, output, size.bytes())
+        })
+    }
+
+    /// Turns sparse mode on or off for a WSL2 distribution's virtual disk,
+    /// which lets the host reclaim space the distribution has freed.
+    ///
+    /// Current releases of the service refuse to make a disk sparse unless
+    /// `allow_unsafe` is given, as sparse disks have been known to corrupt.
+    ///
+    /// Errors are classified as [`WslErrorKind::Wsl2Needed`] for WSL1
+    /// distributions, [`WslErrorKind::DistributionNotStopped`] for running
+    /// ones, and [`WslErrorKind::ServiceRefused`] for any other failure from
+    /// the service.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, AllowUnsafeSparse, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake.clone());
+    ///
+    /// let err = wsl.set_sparse(ubuntu, true, None).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::ServiceRefused));
+    ///
+    /// let allow_unsafe = AllowUnsafeSparse::accept_risk_of_data_corruption();
+    /// wsl.set_sparse(ubuntu, true, Some(allow_unsafe)).unwrap();
+    /// assert!(fake.is_sparse(ubuntu));
+    /// ```
+    pub fn set_sparse(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        sparse: bool,
+        allow_unsafe: Option<AllowUnsafeSparse>,
+    ) -> Result<(), WslError> {
+        use wsl_com_api_sys::error::*;
+
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let distro = self
+            .backend
+            .enumerate_distributions()?
+            .into_iter()
+            .find(|distro| distro.uuid == distro_guid)
+            .ok_or(WslError::from(WSL_E_DISTRO_NOT_FOUND))?;
+        if distro.version != Version::WSL2 {
+            return Err(WslError::with_message(
+                WSL_E_WSL2_NEEDED,
+                format!(
+                    "'{}' is a WSL1 distribution and has no virtual disk",
+                    distro.name
+                ),
+            ));
+        }
+
+        self.backend
+            .set_sparse(distro_guid, sparse, allow_unsafe.is_some())
+            .map_err(|e| match e.kind() {
+                Some(
+                    WslErrorKind::Wsl2Needed
+                    | WslErrorKind::DistributionNotStopped
+                    | WslErrorKind::DistributionNotFound,
+                ) => e,
+                _ => e.with_kind(WslErrorKind::ServiceRefused),
+            })
+    }
+
+    /// Asks the distribution how many bytes its root filesystem is using.
+    fn used_disk_space(&self, distro_guid: Uuid) -> Result<u64, WslError> {
+        use std::io::Read;
+
+        let mut process = self.backend.launch(
+            distro_guid,
+            "/bin/sh",
+            &["sh", "-c", "df -Pk /"],
+            None,
+            "root",
+        )?;
+        // Drain stderr alongside stdout, so that `df` can't block on a full
+        // pipe, and keep it to explain a failure
+        let stderr = process.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut errors = String::new();
+                _ = stderr.read_to_string(&mut errors);
+                errors
+            })
+        });
+        let mut output = String::new();
+        if let Some(mut stdout) = process.stdout.take() {
+            stdout.read_to_string(&mut output)?;
+        }
+        let status = process.wait()?;
+        let errors = stderr
+            .and_then(|stderr| stderr.join().ok())
+            .unwrap_or_default();
+
+        // POSIX output: a header, then the filesystem's line with the used
+        // space in kilobytes in the third column
+        let used = output
+            .lines()
+            .nth(1)
+            .and_then(|line| line.split_whitespace().nth(2))
+            .and_then(|used| used.parse::<u64>().ok());
+        match used {
+            Some(used) if status.success() => Ok(used * 1024),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Could not read the used disk space from `df` ({}): {}",
+                    status,
+                    [output.trim(), errors.trim()]
+                        .into_iter()
+                        .filter(|s| !s.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ),
+            )
+            .into()),
+        }
+    }
+
+    /// Launches a Linux process in the specified WSL distribution. The process
+    /// runs under the specified username and returns handles to
+    /// stdin/stdout/stderr for communication.
+    pub fn launch(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.backend
+            .launch(distro_guid, command, args, cwd, username)
+    }
+
+    /// Enumerates the distributions.
+    ///
+    /// The service doesn't report flags when enumerating, so
+    /// [`Distribution::flags`] is always `None` here; use
+    /// [`Wsl2::enumerate_distributions_with_flags`] to read them too.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// fake.add_distribution("Alpine", Version::WSL1);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// // Like `wsl -l -v`
+    /// for distro in wsl.enumerate_distributions().unwrap() {
+    ///     let marker = if distro.is_default { "*" } else { " " };
+    ///     println!("{} {:<10} {:<10} {:?}", marker, distro.name, distro.state, distro.version);
+    /// }
+    /// ```
+    pub fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
+        self.backend.enumerate_distributions()
+    }
+
+    /// Enumerates the distributions like [`Wsl2::enumerate_distributions`],
+    /// and reads each one's [`Distribution::flags`] from its configuration.
+    /// That takes another call to the service per distribution.
+    ///
+    /// A distribution that is being installed or removed, or that was
+    /// unregistered since it was enumerated, may have no configuration to
+    /// read, and is listed without its flags. Any other failure to read a
+    /// configuration is returned.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DistributionFlags, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// assert_eq!(wsl.enumerate_distributions().unwrap()[0].flags, None);
+    /// let flags = wsl.enumerate_distributions_with_flags().unwrap()[0].flags.unwrap();
+    /// assert!(flags.contains(DistributionFlags::VM_MODE));
+    /// ```
+    pub fn enumerate_distributions_with_flags(&self) -> Result<Vec<Distribution>, WslError> {
+        let mut distros = self.backend.enumerate_distributions()?;
+        for distro in &mut distros {
+            match self.backend.get_distribution_configuration(distro.uuid) {
+                Ok(configuration) => distro.flags = Some(configuration.flags),
+                Err(e) if e.hresult() == wsl_com_api_sys::error::WSL_E_DISTRO_NOT_FOUND => {}
+                Err(_)
+                    if matches!(
+                        distro.state,
+                        DistributionState::Installing | DistributionState::Uninstalling
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(distros)
+    }
+
+    /// Reads a distribution's name, version, default user, default
+    /// environment and flags.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DistributionFlags, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// let configuration = wsl.distribution_configuration("Ubuntu").unwrap();
+    /// assert_eq!(configuration.default_uid, 0);
+    /// assert_eq!(configuration.env("LANG"), Some("en_US.UTF-8"));
+    /// assert!(configuration.flags.contains(DistributionFlags::VM_MODE));
+    /// ```
+    pub fn distribution_configuration(
+        &self,
+        distribution: impl Into<DistributionRef>,
+    ) -> Result<DistributionConfiguration, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.backend.get_distribution_configuration(distro_guid)
+    }
+
+    /// Changes a distribution's default user and flags by applying `update`
+    /// to its current configuration, so that anything `update` doesn't touch
+    /// is left as it was. Only the default UID and the
+    /// [`DistributionFlags::CONFIGURABLE`] flags are written back. Returns
+    /// the configuration as written.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DistributionFlags, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// wsl.configure_distribution("Ubuntu", |configuration| {
+    ///     configuration.flags.remove(DistributionFlags::ENABLE_DRIVE_MOUNTING);
+    /// })
+    /// .unwrap();
+    ///
+    /// let configuration = wsl.distribution_configuration("Ubuntu").unwrap();
+    /// assert!(!configuration.flags.contains(DistributionFlags::ENABLE_DRIVE_MOUNTING));
+    /// assert!(configuration.flags.contains(DistributionFlags::ENABLE_INTEROP));
+    /// ```
+    pub fn configure_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        update: impl FnOnce(&mut DistributionConfiguration),
+    ) -> Result<DistributionConfiguration, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let current = self.backend.get_distribution_configuration(distro_guid)?;
+
+        let mut updated = current.clone();
+        update(&mut updated);
+        let flags = (updated.flags & DistributionFlags::CONFIGURABLE)
+            | (current.flags - DistributionFlags::CONFIGURABLE);
+
+        self.backend
+            .configure_distribution(distro_guid, updated.default_uid, flags)?;
+        Ok(DistributionConfiguration {
+            default_uid: updated.default_uid,
+            flags,
+            ..current
+        })
+    }
+
+    /// Turns the given [`DistributionFlags::CONFIGURABLE`] flags on or off,
+    /// leaving the others and the default user unchanged.
+    pub fn set_distribution_flags(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        flags: DistributionFlags,
+        enabled: bool,
+    ) -> Result<DistributionConfiguration, WslError> {
+        self.configure_distribution(distribution, |configuration| {
+            configuration.flags.set(flags, enabled)
+        })
+    }
+
+    /// Changes the UID that processes are launched as by default, leaving
+    /// the flags unchanged.
+    pub fn set_default_uid(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        default_uid: u32,
+    ) -> Result<DistributionConfiguration, WslError> {
+        self.configure_distribution(distribution, |configuration| {
+            configuration.default_uid = default_uid
+        })
+    }
+
+    /// Exports a distribution.
+    pub fn export_distribution(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        file: impl AsRawHandle,
+        stderr: impl AsRawHandle,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let res = self.backend.export_distribution(
+            distro_guid,
+            to_handle(&file),
+            to_handle(&stderr),
+            flags,
+        );
+
+        drop(file);
+        drop(stderr);
+        res
+    }
+
+    /// Exports a distribution like [`Wsl2::export_distribution`], reading
+    /// the service's progress and warnings itself and delivering them to
+    /// `progress` one line at a time. The lines are also returned with the
+    /// result, or attached to the error as its [`WslError::output`] if the
+    /// export fails.
+    pub fn export_distribution_with_progress(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        file: impl AsRawHandle,
+        flags: ExportFlags,
+        progress: impl FnMut(&str) + Send,
+    ) -> Result<Captured<()>, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        let res = output::capture_output(progress, |stderr| {
+            self.backend
+                .export_distribution(distro_guid, to_handle(&file), stderr, flags)
+        });
+
+        drop(file);
+        res
+    }
+
+    /// Exports a distribution into `sink`, which can be any stream: a hasher,
+    /// a compressor or an upload. Returns the number of bytes written to
+    /// `sink` once the export completes.
+    ///
+    /// The service writes the tarball into a pipe that is drained into
+    /// `sink` on a worker thread. If writing to `sink` fails, the export is
+    /// abandoned and that error is returned.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, ExportFlags, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    /// # let stderr = std::fs::File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).unwrap();
+    /// let mut tarball = vec![];
+    /// let written = wsl
+    ///     .export_distribution_to_writer("Ubuntu", &mut tarball, stderr, ExportFlags::empty())
+    ///     .unwrap();
+    /// assert_eq!(written, tarball.len() as u64);
+    /// assert!(wsl_api::tarball::inspect(tarball.as_slice()).is_ok());
+    /// ```
+    pub fn export_distribution_to_writer(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        sink: impl Write + Send,
+        stderr: impl AsRawHandle,
+        flags: ExportFlags,
+    ) -> Result<u64, WslError> {
+        let distro_guid = self.resolve_distribution(distribution)?;
+        self.export_to_writer(distro_guid, sink, to_handle(&stderr), flags)
+    }
+
+    /// [`Wsl2::export_distribution_to_writer`] with diagnostics written to a
+    /// raw handle.
+    pub(crate) fn export_to_writer(
+        &self,
+        distro_guid: Uuid,
+        sink: impl Write + Send,
+        stderr: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<u64, WslError> {
+        let (pipe_reader, pipe_writer) = std::io::pipe()?;
+        std::thread::scope(|scope| {
+            let pump = scope.spawn(move || {
+                let mut sink = sink;
+                let mut pipe_reader = pipe_reader;
+                let mut buf = vec![0; 64 * 1024];
+                let mut written = 0;
+                // The read end is dropped on a failed write, so the service
+                // sees a broken pipe rather than blocking on a full one
+                loop {
+                    let n = match pipe_reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    sink.write_all(&buf[..n])?;
+                    written += n as u64;
+                }
+                sink.flush()?;
+                Ok(written)
+            });
+
+            let res = self.backend.export_distribution_pipe(
+                distro_guid,
+                to_handle(&pipe_writer),
+                stderr,
+                flags,
+            );
+            // Our end must be closed for the pump to see the end of the tarball
+            drop(pipe_writer);
+            let pumped = pump.join().expect("export pump panicked");
+
+            match (res, pumped) {
+                (_, Err(e)) => Err(e.into()),
+                (Err(e), Ok(_)) => Err(e),
+                (Ok(()), Ok(written)) => Ok(written),
+            }
+        })
+    }
+
+    /// Registers a WSL distribution. Note that the distribution name must be
+    /// unique.
+    ///
+    /// `options` may be plain [`ImportFlags`] to install in the default
+    /// location, or [`RegisterOptions`] to choose where the distribution is
+    /// installed and how its disk is created. A fixed-size disk needs an
+    /// explicit size, and fails with [`WslErrorKind::InvalidSize`] otherwise.
+    ///
+    /// The service only checks the tarball once the import is under way.
+    /// With [`RegisterOptions::inspect`], the file is first checked with
+    /// [`tarball::inspect_file`], and a tarball that isn't a root filesystem
+    /// fails with its error before the service is called.
+    pub fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        file: impl AsRawHandle,
+        stderr: impl AsRawHandle,
+        options: impl Into<RegisterOptions>,
+    ) -> Result<(Uuid, String), WslError> {
+        let options = options.into().validated()?;
+        if options.inspect {
+            with_handle(to_handle(&file), tarball::inspect_file)?.architecture()?;
+        }
+        let res = self
+            .backend
+            .register_distribution(
+                name,
+                version,
+                to_handle(&file),
+                to_handle(&stderr),
+                &options,
+            )
+            .map_err(name_taken);
+
+        drop(file);
+        drop(stderr);
+        res
+    }
+
+    /// Registers a WSL distribution like [`Wsl2::register_distribution`],
+    /// reading the service's progress and warnings itself and delivering
+    /// them to `progress` one line at a time. The lines are also returned
+    /// with the result, or attached to the error as its
+    /// [`WslError::output`] if registration fails.
+    pub fn register_distribution_with_progress(
+        &self,
+        name: &str,
+        version: Version,
+        file: impl AsRawHandle,
+        options: impl Into<RegisterOptions>,
+        progress: impl FnMut(&str) + Send,
+    ) -> Result<Captured<(Uuid, String)>, WslError> {
+        let options = options.into().validated()?;
+        if options.inspect {
+            with_handle(to_handle(&file), tarball::inspect_file)?.architecture()?;
+        }
+        let res = output::capture_output(progress, |stderr| {
+            self.backend
+                .register_distribution(name, version, to_handle(&file), stderr, &options)
+                .map_err(name_taken)
+        });
+
+        drop(file);
+        res
+    }
+
+    /// Registers a WSL distribution from a tarball read from `source`, which
+    /// can be any stream: an in-memory buffer, a decompressor or a download.
+    /// Otherwise this behaves like [`Wsl2::register_distribution`].
+    ///
+    /// `source` is pumped into a pipe on a worker thread while the service
+    /// reads the other end. If reading `source` fails, a distribution
+    /// registered from it is unregistered again and the read error is
+    /// returned. If the service failed too, its error is returned instead,
+    /// with the read error added to its message.
+    ///
+    /// A stream can only be read once, so unlike a file it can't be
+    /// inspected before the import starts. With [`RegisterOptions::inspect`],
+    /// the stream is read by [`tarball::inspect`] while the service imports
+    /// it. A tarball it rejects fails with its error, and is unregistered
+    /// again if the service had already accepted it.
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use wsl_api::{fake::FakeBackend, ImportFlags, Version, Wsl2};
+    ///
+    /// let wsl = Wsl2::with_backend(FakeBackend::new());
+    /// # let stderr = std::fs::File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).unwrap();
+    /// # let mut builder = tar::Builder::new(vec![]);
+    /// # let mut header = tar::Header::new_gnu();
+    /// # header.set_size(0);
+    /// # builder.append_data(&mut header, "bin/sh", std::io::empty()).unwrap();
+    /// # let tarball = builder.into_inner().unwrap();
+    /// let (_, name) = wsl
+    ///     .register_distribution_from_reader("Alpine", Version::WSL2, tarball.as_slice(), stderr, ImportFlags::empty())
+    ///     .unwrap();
+    /// assert_eq!(name, "Alpine");
+    ///
+    /// // A download that fails
+    /// struct Dropped;
+    /// impl Read for Dropped {
+    ///     fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+    ///         Err(std::io::ErrorKind::ConnectionReset.into())
+    ///     }
+    /// }
+    ///
+    /// # let stderr = std::fs::File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).unwrap();
+    /// let err = wsl
+    ///     .register_distribution_from_reader("Debian", Version::WSL2, Dropped, stderr, ImportFlags::empty())
+    ///     .unwrap_err();
+    /// assert!(err.to_string().contains("connection reset"));
+    /// assert!(wsl.resolve_distribution("Debian").is_err());
+    /// ```
+    pub fn register_distribution_from_reader(
+        &self,
+        name: &str,
+        version: Version,
+        source: impl Read + Send,
+        stderr: impl AsRawHandle,
+        options: impl Into<RegisterOptions>,
+    ) -> Result<(Uuid, String), WslError> {
+        self.register_from_reader(name, version, source, to_handle(&stderr), options.into())
+    }
+
+    /// [`Wsl2::register_distribution_from_reader`] with diagnostics written
+    /// to a raw handle.
+    pub(crate) fn register_from_reader(
+        &self,
+        name: &str,
+        version: Version,
+        source: impl Read + Send,
+        stderr: HANDLE,
+        options: RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        let options = options.validated()?;
+        let (pipe_reader, mut pipe_writer) = std::io::pipe()?;
+        let (inspect_reader, mut inspect_writer) = match options.inspect {
+            true => std::io::pipe().map(|(reader, writer)| (Some(reader), Some(writer)))?,
+            false => (None, None),
+        };
+        std::thread::scope(|scope| {
+            let inspection = inspect_reader.map(|mut reader| {
+                scope.spawn(move || {
+                    let report = tarball::inspect(&mut reader)
+                        .and_then(|report| report.architecture().map(|_| report));
+                    // Drain the padding after the last entry, or whatever
+                    // is left of a bad tarball, so the pump never blocks
+                    _ = std::io::copy(&mut reader, &mut std::io::sink());
+                    report
+                })
+            });
+            // Whether the whole of `source` was pumped
+            let pump = scope.spawn(move || {
+                let mut source = source;
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let n = match source.read(&mut buf) {
+                        Ok(0) => return Ok(true),
+                        Ok(n) => n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if let Some(writer) = &mut inspect_writer {
+                        _ = writer.write_all(&buf[..n]);
+                    }
+                    // A failed write means the service stopped reading, and
+                    // its own result explains why
+                    if pipe_writer.write_all(&buf[..n]).is_err() {
+                        return Ok(false);
+                    }
+                }
+            });
+
+            let res = self
+                .backend
+                .register_distribution_pipe(
+                    name,
+                    version,
+                    to_handle(&pipe_reader),
+                    stderr,
+                    &options,
+                )
+                .map_err(name_taken);
+            // Closing our end unblocks the pump if the service gave up early
+            drop(pipe_reader);
+            let pumped = pump.join().expect("register pump panicked");
+            let inspected = inspection
+                .map(|inspection| inspection.join().expect("tarball inspection panicked"))
+                .transpose();
+
+            match (res, pumped, inspected) {
+                (Ok((uuid, _)), Err(e), _) => {
+                    _ = self.backend.unregister_distribution(uuid);
+                    Err(e.into())
+                }
+                (Ok((uuid, _)), Ok(_), Err(e)) => {
+                    _ = self.backend.unregister_distribution(uuid);
+                    Err(e)
+                }
+                (Err(e), Err(read), _) => {
+                    Err(e.with_context(format_args!("reading the tarball failed: {read}")))
+                }
+                // Only a tarball that was read to the end is worth blaming
+                (Err(_), Ok(true), Err(e)) => Err(e),
+                (res, Ok(_), _) => res,
+            }
+        })
+    }
+
+    /// Registers a copy of `source` as `new_name`, streaming an export of it
+    /// straight into the registration without a temporary file. The clone
+    /// has the same WSL version, default user and flags as `source`. Returns
+    /// the clone's GUID and name, like [`Wsl2::register_distribution`].
+    ///
+    /// The copy is made as a tarball, so [`ImportFlags::VHD`] in `options`
+    /// is ignored. If any step fails, a partly registered clone is
+    /// unregistered again before the error is returned.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, RegisterOptions, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    /// wsl.set_default_uid("Ubuntu", 1000).unwrap();
+    ///
+    /// let (_, name) = wsl.clone_distribution("Ubuntu", "Ubuntu-dev", RegisterOptions::new()).unwrap();
+    /// assert_eq!(wsl.distribution_configuration(name.as_str()).unwrap().default_uid, 1000);
+    ///
+    /// let err = wsl.clone_distribution("Ubuntu", "Ubuntu-dev", RegisterOptions::new()).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionAlreadyExists));
+    /// ```
+    pub fn clone_distribution(
+        &self,
+        source: impl Into<DistributionRef>,
+        new_name: &str,
+        options: impl Into<RegisterOptions>,
+    ) -> Result<(Uuid, String), WslError> {
+        let mut options = options.into().validated()?;
+        options.flags.remove(ImportFlags::VHD);
+        let source_guid = self.resolve_distribution(source)?;
+        let source_config = self.backend.get_distribution_configuration(source_guid)?;
+
+        let (pipe_reader, pipe_writer) = std::io::pipe()?;
+        let (registered, exported, export_failed_first) = output::with_output_pipe(
+            |_| {},
+            |stderr| {
+                let export_failed = AtomicBool::new(false);
+                std::thread::scope(|scope| {
+                    let export = scope.spawn(|| {
+                        let res = self.backend.export_distribution_pipe(
+                            source_guid,
+                            to_handle(&pipe_writer),
+                            stderr,
+                            ExportFlags::empty(),
+                        );
+                        // Recorded before our end is closed, so that a
+                        // registration failing on the truncated tarball
+                        // can be told apart from one that failed first
+                        if res.is_err() {
+                            export_failed.store(true, Ordering::SeqCst);
+                        }
+                        drop(pipe_writer);
+                        res
+                    });
+
+                    let registered = self
+                        .backend
+                        .register_distribution_pipe(
+                            new_name,
+                            source_config.version,
+                            to_handle(&pipe_reader),
+                            stderr,
+                            &options,
+                        )
+                        .map_err(name_taken);
+                    let export_failed_first = export_failed.load(Ordering::SeqCst);
+                    // Closing our end unblocks the export if the
+                    // registration gave up early
+                    drop(pipe_reader);
+                    let exported = export.join().expect("export thread panicked");
+                    Ok((registered, exported, export_failed_first))
+                })
+            },
+        )?;
+
+        let (guid, name) = match (registered, exported) {
+            (Ok((guid, _)), Err(e)) => {
+                _ = self.backend.unregister_distribution(guid);
+                return Err(e);
+            }
+            (Err(_), Err(e)) if export_failed_first => return Err(e),
+            (Err(e), _) => return Err(e),
+            (Ok(registered), Ok(())) => registered,
+        };
+
+        let copy_configuration = || {
+            let cloned_config = self.backend.get_distribution_configuration(guid)?;
+            let flags = (source_config.flags & DistributionFlags::CONFIGURABLE)
+                | (cloned_config.flags - DistributionFlags::CONFIGURABLE);
+            self.backend
+                .configure_distribution(guid, source_config.default_uid, flags)
+        };
+        if let Err(e) = copy_configuration() {
+            _ = self.backend.unregister_distribution(guid);
+            return Err(e);
+        }
+        Ok((guid, name))
+    }
+
+    /// Registers a WSL2 distribution that uses an existing `.vhdx` disk where
+    /// it is, rather than copying it. Returns the new distribution's GUID
+    /// and name, like [`Wsl2::register_distribution`].
+    ///
+    /// The disk must be an existing VHDX file, or the call fails with
+    /// [`WslErrorKind::InvalidPath`]. A name that is already registered fails
+    /// with [`WslErrorKind::DistributionAlreadyExists`].
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, WslErrorKind, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    /// let golden = std::env::temp_dir().join("wsl-api-golden.vhdx");
+    /// std::fs::write(&golden, b"vhdxfile").unwrap();
+    ///
+    /// let err = wsl.import_distribution_in_place("ubuntu", &golden).unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DistributionAlreadyExists));
+    ///
+    /// let (_, name) = wsl.import_distribution_in_place("Golden", &golden).unwrap();
+    /// assert_eq!(name, "Golden");
+    /// # std::fs::remove_file(&golden).unwrap();
+    /// ```
+    pub fn import_distribution_in_place(
+        &self,
+        name: &str,
+        vhd_path: impl AsRef<Path>,
+    ) -> Result<(Uuid, String), WslError> {
+        use wsl_com_api_sys::error::*;
+
+        // The service resolves relative paths against its own working
+        // directory, not ours
+        let vhd_path = std::path::absolute(vhd_path.as_ref())?;
+        validate_vhdx(&vhd_path)?;
+
+        match self.backend.get_distribution_id(name, 0) {
+            Ok(_) => {
+                return Err(WslError::precondition(
+                    WslErrorKind::DistributionAlreadyExists,
+                    ERROR_ALREADY_EXISTS.to_hresult(),
+                    format!("A distribution named '{}' is already registered", name),
+                ))
+            }
+            Err(e) if e.hresult() == WSL_E_DISTRO_NOT_FOUND => {}
+            Err(e) => return Err(e),
+        }
+
+        let uuid = self
+            .backend
+            .import_distribution_in_place(name, &vhd_path)
+            .map_err(name_taken)?;
+        Ok((uuid, name.to_string()))
+    }
+
+    pub fn set_version(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        version: Version,
+        stderr: impl AsRawHandle,
+    ) -> Result<(), WslError> {
+        let distribution = self.resolve_distribution(distribution)?;
+        let res = self
+            .backend
+            .set_version(distribution, version, to_handle(&stderr));
+
+        drop(stderr);
+        res
+    }
+
+    /// Converts a distribution like [`Wsl2::set_version`], reading the
+    /// service's progress and warnings itself and delivering them to
+    /// `progress` one line at a time. The lines are also returned with the
+    /// result, or attached to the error as its [`WslError::output`] if the
+    /// conversion fails.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, Version, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// fake.add_distribution("Ubuntu", Version::WSL2);
+    /// let wsl = Wsl2::with_backend(fake);
+    ///
+    /// let converted = wsl
+    ///     .set_version_with_progress("Ubuntu", Version::WSL1, |line| println!("{line}"))
+    ///     .unwrap();
+    /// assert!(converted.output.starts_with("Conversion in progress"));
+    /// ```
+    pub fn set_version_with_progress(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        version: Version,
+        progress: impl FnMut(&str) + Send,
+    ) -> Result<Captured<()>, WslError> {
+        let distribution = self.resolve_distribution(distribution)?;
+        output::capture_output(progress, |stderr| {
+            self.backend.set_version(distribution, version, stderr)
+        })
+    }
+
+    /// Attaches a disk to the WSL2 VM without mounting it, like
+    /// `wsl --mount --bare`. `disk` is a `.vhdx` file with
+    /// [`AttachFlags::VHD`], or a physical disk such as
+    /// `\\.\PHYSICALDRIVE2` with [`AttachFlags::PASS_THROUGH`].
+    ///
+    /// A disk that is already attached fails with
+    /// [`WslErrorKind::DiskAlreadyAttached`].
+    pub fn attach_disk(&self, disk: impl AsRef<Path>, flags: AttachFlags) -> Result<(), WslError> {
+        // The service resolves relative paths against its own working
+        // directory, not ours
+        let disk = std::path::absolute(disk)?;
+        self.backend.attach_disk(&disk, flags)
+    }
+
+    /// Attaches a disk to the WSL2 VM and mounts it under `/mnt/wsl`, like
+    /// `wsl --mount`. Returns the name of the mount, which is its directory
+    /// under `/mnt/wsl`.
+    ///
+    /// A failure inside the VM has kind [`WslErrorKind::DiskMountFailed`],
+    /// and [`WslError::disk_failure`] says which stage failed.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DiskStep, MountOptions, WslErrorKind, Wsl2};
+    ///
+    /// let wsl = Wsl2::with_backend(FakeBackend::new());
+    /// let disk = r"\\.\PHYSICALDRIVE2";
+    ///
+    /// let name = wsl.mount_disk(disk, MountOptions::new().partition(1).name("data")).unwrap();
+    /// assert_eq!(name, "data");
+    ///
+    /// let err = wsl
+    ///     .mount_disk(disk, MountOptions::new().partition(2).filesystem_type("zfs"))
+    ///     .unwrap_err();
+    /// assert_eq!(err.kind(), Some(WslErrorKind::DiskMountFailed));
+    /// assert_eq!(err.disk_failure().unwrap().step, DiskStep::Mount);
+    ///
+    /// wsl.detach_disk(disk).unwrap();
+    /// ```
+    pub fn mount_disk(
+        &self,
+        disk: impl AsRef<Path>,
+        options: impl Into<MountOptions>,
+    ) -> Result<String, WslError> {
+        let disk = std::path::absolute(disk)?;
+        self.backend.mount_disk(&disk, &options.into())
+    }
+
+    /// Unmounts a disk and detaches it from the WSL2 VM, like
+    /// `wsl --unmount`.
+    pub fn detach_disk(&self, disk: impl AsRef<Path>) -> Result<(), WslError> {
+        let disk = std::path::absolute(disk)?;
+        self.backend.detach_disk(Some(&disk))
+    }
+
+    /// Unmounts and detaches every disk attached to the WSL2 VM.
+    pub fn detach_all_disks(&self) -> Result<(), WslError> {
+        self.backend.detach_disk(None)
+    }
+
+    /// Mounts a disk like [`Wsl2::mount_disk`] until the returned guard is
+    /// dropped, at which point it is unmounted and detached again.
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, MountOptions, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let wsl = Wsl2::with_backend(fake.clone());
+    ///
+    /// {
+    ///     let guard = wsl.mount_disk_scoped(r"\\.\PHYSICALDRIVE2", MountOptions::new()).unwrap();
+    ///     assert_eq!(guard.mount_name(), "PHYSICALDRIVE2");
+    ///     assert_eq!(fake.attached_disks().len(), 1);
+    /// }
+    /// assert!(fake.attached_disks().is_empty());
+    /// ```
+    pub fn mount_disk_scoped(
+        &self,
+        disk: impl AsRef<Path>,
+        options: impl Into<MountOptions>,
+    ) -> Result<DiskMountGuard<'_>, WslError> {
+        let disk = std::path::absolute(disk)?;
+        let mount_name = self.backend.mount_disk(&disk, &options.into())?;
+        Ok(DiskMountGuard {
+            wsl: self,
+            disk,
+            mount_name,
+            ledger: None,
+            attached: true,
+        })
+    }
+
+    /// Mounts a disk like [`Wsl2::mount_disk_scoped`], recording it in
+    /// `ledger` until it is detached. If the program dies without dropping
+    /// the guard, the next run can detach the disk with
+    /// [`Wsl2::detach_stale_disks`].
+    ///
+    /// ```
+    /// use wsl_api::{fake::FakeBackend, DiskLedger, MountOptions, Wsl2};
+    ///
+    /// let fake = FakeBackend::new();
+    /// let wsl = Wsl2::with_backend(fake.clone());
+    /// # let path = std::env::temp_dir().join(format!("wsl-api-disks-{}.json", std::process::id()));
+    /// let ledger = DiskLedger::new(&path);
+    ///
+    /// // A run that crashes with the disk mounted
+    /// let guard = wsl.mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger).unwrap();
+    /// std::mem::forget(guard);
+    ///
+    /// // The next run cleans up before starting
+    /// assert_eq!(wsl.detach_stale_disks(&ledger).unwrap().detached.len(), 1);
+    /// assert!(fake.attached_disks().is_empty());
+    /// assert!(ledger.disks().unwrap().is_empty());
+    /// ```
+    pub fn mount_disk_tracked<'a>(
+        &'a self,
+        disk: impl AsRef<Path>,
+        options: impl Into<MountOptions>,
+        ledger: &'a DiskLedger,
+    ) -> Result<DiskMountGuard<'a>, WslError> {
+        let disk = std::path::absolute(disk)?;
+        // Recorded first, so that a crash while mounting leaves a trace too
+        let mut recorded = false;
+        ledger.update(|disks| {
+            recorded = disks.contains(&disk);
+            if !recorded {
+                disks.push(disk.clone());
+            }
+        })?;
+        match self.backend.mount_disk(&disk, &options.into()) {
+            Ok(mount_name) => Ok(DiskMountGuard {
+                wsl: self,
+                disk,
+                mount_name,
+                ledger: Some(ledger),
+                attached: true,
+            }),
+            // A disk that is already attached is left over from an earlier
+            // run only if that run recorded it; otherwise someone else
+            // attached it, and it isn't ours to detach
+            Err(e)
+                if recorded
+                    && matches!(
+                        e.kind(),
+                        Some(WslErrorKind::DiskAlreadyAttached | WslErrorKind::DiskAlreadyMounted)
+                    ) =>
+            {
+                Err(e)
+            }
+            Err(e) => {
+                _ = ledger.update(|disks| disks.retain(|recorded| *recorded != disk));
+                Err(e)
+            }
+        }
+    }
+
+    /// Detaches the disks recorded in `ledger` by an earlier run that didn't
+    /// detach them.
+    ///
+    /// Disks that are detached, or that are no longer attached, as happens
+    /// when WSL shuts down, are removed from the ledger. Disks that fail to
+    /// detach for another reason, such as a missing elevation, stay in the
+    /// ledger for the next attempt and are returned with their errors.
+    pub fn detach_stale_disks(&self, ledger: &DiskLedger) -> Result<StaleDisks, WslError> {
+        let mut stale = StaleDisks::default();
+        for disk in ledger.disks()? {
+            match self.backend.detach_disk(Some(&disk)) {
+                Ok(()) => stale.detached.push(disk),
+                Err(e) if e.hresult() == ERROR_FILE_NOT_FOUND.to_hresult() => {
+                    stale.not_attached.push(disk)
+                }
+                Err(e) => stale.failed.push((disk, e)),
+            }
+        }
+        ledger.update(|disks| {
+            disks.retain(|disk| stale.failed.iter().any(|(failed, _)| failed == disk))
+        })?;
+        Ok(stale)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Distribution {
+    pub name: String,
+    pub uuid: Uuid,
+    pub version: Version,
+    pub state: DistributionState,
+    /// The distribution's flags. Only filled in by
+    /// [`Wsl2::enumerate_distributions_with_flags`], and `None` when listed
+    /// by [`Wsl2::enumerate_distributions`], as the service's enumeration
+    /// doesn't report them.
+    pub flags: Option<DistributionFlags>,
+    pub is_default: bool,
+}
+
+impl From<&LXSS_ENUMERATE_INFO> for Distribution {
+    fn from(info: &LXSS_ENUMERATE_INFO) -> Self {
+        let name = unsafe {
+            PCWSTR::from_raw(info.DistroName.as_ptr())
+                .to_string()
+                .unwrap()
+        };
+        Self {
+            name: name.to_string(),
+            uuid: Uuid::from_u128(info.DistroGuid.to_u128()),
+            version: Version::from(info.Version),
+            state: DistributionState::from(info.State),
+            flags: None,
+            is_default: info.Flags & LXSS_ENUMERATE_FLAGS_DEFAULT != 0,
+        }
+    }
+}
+
+/// What a distribution is currently doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DistributionState {
+    Stopped,
+    Running,
+    Installing,
+    /// Being converted between WSL versions
+    Converting,
+    Uninstalling,
+    Unknown(u32),
+}
+
+impl From<u32> for DistributionState {
+    fn from(state: u32) -> Self {
+        match state {
+            LXSS_DISTRIBUTION_STATE_INSTALLED => DistributionState::Stopped,
+            LXSS_DISTRIBUTION_STATE_RUNNING => DistributionState::Running,
+            LXSS_DISTRIBUTION_STATE_INSTALLING => DistributionState::Installing,
+            LXSS_DISTRIBUTION_STATE_CONVERTING => DistributionState::Converting,
+            LXSS_DISTRIBUTION_STATE_UNINSTALLING => DistributionState::Uninstalling,
+            _ => DistributionState::Unknown(state),
+        }
+    }
+}
+
+impl std::fmt::Display for DistributionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DistributionState::Stopped => write!(f, "Stopped"),
+            DistributionState::Running => write!(f, "Running"),
+            DistributionState::Installing => write!(f, "Installing"),
+            DistributionState::Converting => write!(f, "Converting"),
+            DistributionState::Uninstalling => write!(f, "Uninstalling"),
+            DistributionState::Unknown(state) => write!(f, "Unknown ({})", state),
+        }
+    }
+}
+
+/// A distribution's configuration, as stored by the WSL service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DistributionConfiguration {
+    pub name: String,
+    pub version: Version,
+    /// The UID that processes are launched as when no user is given
+    pub default_uid: u32,
+    /// The environment processes start with, in the order the service
+    /// returned it
+    pub default_environment: Vec<(String, String)>,
+    pub flags: DistributionFlags,
+}
+
+impl DistributionConfiguration {
+    /// Looks up a variable in the default environment.
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.default_environment
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Splits `KEY=VALUE` strings from the service into pairs. Entries
+    /// without an `=` are kept as keys with an empty value.
+    pub(crate) fn parse_environment<S: AsRef<str>>(
+        entries: impl IntoIterator<Item = S>,
+    ) -> Vec<(String, String)> {
+        entries
+            .into_iter()
+            .map(|entry| match entry.as_ref().split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (entry.as_ref().to_string(), String::new()),
+            })
+            .collect()
+    }
+}
+
+/// Restores the previous default distribution when dropped. Created by
+/// [`Wsl2::set_default_distribution_scoped`].
+///
+/// If there was no default before, or the previous default has since been
+/// unregistered, the default is left as it is. A failure to restore it on
+/// drop is ignored; call [`restore`](Self::restore) to see it.
+#[must_use = "the previous default is restored as soon as the guard is dropped"]
+pub struct DefaultDistributionGuard<'a> {
+    wsl: &'a Wsl2,
+    previous: Option<Uuid>,
+}
 
-use wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_PARAMETERS;
+impl DefaultDistributionGuard<'_> {
+    /// The default distribution that will be restored.
+    pub fn previous(&self) -> Option<Uuid> {
+        self.previous
+    }
 
-mod error;
-pub use error::*;
-mod interop;
+    /// Restores the previous default now, reporting any failure.
+    pub fn restore(mut self) -> Result<(), WslError> {
+        self.restore_previous()
+    }
 
-// Allows this code to compile on both Windows and Unix
+    /// Keeps the new default in place.
+    pub fn keep(mut self) {
+        self.previous = None;
+    }
 
-#[cfg(windows)]
-use std::os::windows::io::AsRawHandle;
-#[cfg(windows)]
-fn to_handle(handle: &impl AsRawHandle) -> HANDLE {
-    HANDLE(handle.as_raw_handle() as isize)
+    fn restore_previous(&mut self) -> Result<(), WslError> {
+        let Some(previous) = self.previous.take() else {
+            return Ok(());
+        };
+        match self.wsl.backend.set_default_distribution(previous) {
+            Err(e) if e.hresult() == wsl_com_api_sys::error::WSL_E_DISTRO_NOT_FOUND => Ok(()),
+            res => res,
+        }
+    }
 }
-#[cfg(windows)]
-fn from_handle<T: From<std::os::windows::io::OwnedHandle>>(handle: HANDLE) -> T {
-    use std::os::windows::io::FromRawHandle;
-    unsafe {
-        T::from(std::os::windows::io::OwnedHandle::from_raw_handle(
-            handle.0 as _,
-        ))
+
+impl Drop for DefaultDistributionGuard<'_> {
+    fn drop(&mut self) {
+        _ = self.restore_previous();
     }
 }
 
-#[cfg(unix)]
-use std::os::fd::AsRawFd as AsRawHandle;
+/// Unmounts and detaches a disk when dropped. Created by
+/// [`Wsl2::mount_disk_scoped`] and [`Wsl2::mount_disk_tracked`].
+///
+/// A failure to detach the disk on drop is ignored, and a tracked disk then
+/// stays in its ledger; call [`detach`](Self::detach) to see the failure.
+#[must_use = "the disk is detached as soon as the guard is dropped"]
+pub struct DiskMountGuard<'a> {
+    wsl: &'a Wsl2,
+    disk: PathBuf,
+    mount_name: String,
+    ledger: Option<&'a DiskLedger>,
+    attached: bool,
+}
 
-use crate::interop::Interop;
-#[cfg(unix)]
-fn to_handle(_: &impl AsRawHandle) -> HANDLE {
-    unreachable!("This should never be called on Unix: we only support Windows");
+impl DiskMountGuard<'_> {
+    /// The disk that will be detached.
+    pub fn disk(&self) -> &Path {
+        &self.disk
+    }
+
+    /// The name of the mount, which is its directory under `/mnt/wsl`.
+    pub fn mount_name(&self) -> &str {
+        &self.mount_name
+    }
+
+    /// Detaches the disk now and removes it from its ledger, reporting any
+    /// failure to do either.
+    pub fn detach(mut self) -> Result<(), WslError> {
+        self.detach_disk()
+    }
+
+    /// Leaves the disk attached, and removes it from the ledger it was
+    /// recorded in.
+    pub fn keep(mut self) -> Result<(), WslError> {
+        self.attached = false;
+        self.forget()
+    }
+
+    fn detach_disk(&mut self) -> Result<(), WslError> {
+        if !std::mem::take(&mut self.attached) {
+            return Ok(());
+        }
+        self.wsl.backend.detach_disk(Some(&self.disk))?;
+        self.forget()
+    }
+
+    fn forget(&self) -> Result<(), WslError> {
+        match self.ledger {
+            Some(ledger) => ledger.update(|disks| disks.retain(|disk| *disk != self.disk)),
+            None => Ok(()),
+        }
+    }
 }
-#[cfg(unix)]
-fn from_handle<T>(_: HANDLE) -> T {
-    unreachable!("This should never be called on Unix: we only support Windows");
+
+impl Drop for DiskMountGuard<'_> {
+    fn drop(&mut self) {
+        _ = self.detach_disk();
+    }
 }
 
-/// WSL-specific process waiting function that uses LXBUS IOCTL
-unsafe fn wait_for_wsl_process(process_handle: HANDLE, timeout_ms: u32) -> Result<u32, WslError> {
-    let mut parameters = LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_PARAMETERS {
-        Input: wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_INPUT {
-            TimeoutMs: timeout_ms,
-        },
-    };
+/// What [`Wsl2::detach_stale_disks`] did with the disks in a [`DiskLedger`].
+#[derive(Debug, Default)]
+pub struct StaleDisks {
+    /// The disks that were still attached and have been detached
+    pub detached: Vec<PathBuf>,
+    /// The disks that were no longer attached
+    pub not_attached: Vec<PathBuf>,
+    /// The disks that couldn't be detached, which stay in the ledger
+    pub failed: Vec<(PathBuf, WslError)>,
+}
 
-    DeviceIoControl(
-        process_handle,
-        LXBUS_IPC_LX_PROCESS_IOCTL_WAIT_FOR_TERMINATION,
-        Some(&parameters.Input as *const _ as *const _),
-        std::mem::size_of::<wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_INPUT>(
-        ) as u32,
-        Some(&mut parameters.Output as *mut _ as *mut _),
-        std::mem::size_of::<
-            wsl_com_api_sys::interop::LXBUS_IPC_LX_PROCESS_WAIT_FOR_TERMINATION_OUTPUT,
-        >() as u32,
-        None,
-        None,
-    )?;
+/// A JSON file listing the disks mounted with [`Wsl2::mount_disk_tracked`]
+/// that haven't been detached yet.
+///
+/// The ledger can be shared by several programs. Changes are made under a
+/// lock file next to the ledger, and written to a temporary file that is
+/// renamed over the ledger, so that a crash never leaves it unreadable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskLedger {
+    path: PathBuf,
+}
 
-    // The exit status is stored shifted, with status in the lower 8 bits
-    Ok((parameters.Output.ExitStatus as u32) >> 8)
+impl DiskLedger {
+    /// Uses the ledger at `path`, which is created when a disk is first
+    /// recorded and deleted once it is empty.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DiskLedger { path: path.into() }
+    }
+
+    /// The disks recorded as attached.
+    pub fn disks(&self) -> Result<Vec<PathBuf>, WslError> {
+        match std::fs::read(&self.path) {
+            Ok(json) => Ok(serde_json::from_slice(&json).map_err(std::io::Error::from)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Applies `update` to the recorded disks while holding the ledger's
+    /// lock, so that programs sharing the ledger don't lose each other's
+    /// changes.
+    fn update(&self, update: impl FnOnce(&mut Vec<PathBuf>)) -> Result<(), WslError> {
+        let _lock = self.lock()?;
+        let mut disks = self.disks()?;
+        update(&mut disks);
+        if disks.is_empty() {
+            match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        } else {
+            let json = serde_json::to_vec_pretty(&disks).map_err(std::io::Error::from)?;
+            // Renamed into place, so that a crash never leaves half a ledger
+            let partial = self.sibling(".partial");
+            std::fs::write(&partial, json)?;
+            Ok(std::fs::rename(&partial, &self.path)?)
+        }
+    }
+
+    /// Takes the ledger's lock by creating a lock file next to it, waiting
+    /// for whoever holds it. A lock file older than
+    /// [`DiskLedger::STALE_LOCK`] was left by a program that died while
+    /// holding it, and is taken over.
+    fn lock(&self) -> Result<LedgerLock, WslError> {
+        let path = self.sibling(".lock");
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(LedgerLock { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .is_ok_and(|modified| {
+                            modified.elapsed().is_ok_and(|age| age > Self::STALE_LOCK)
+                        });
+                    if stale {
+                        _ = std::fs::remove_file(&path);
+                    } else {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// How long a lock is held before it is assumed to have been abandoned.
+    /// Updates take milliseconds, so this is only reached after a crash.
+    const STALE_LOCK: Duration = Duration::from_secs(10);
+
+    /// A file next to the ledger, named after it with `suffix` appended.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        path.into()
+    }
 }
 
-/// Validates that a file handle is of the expected type
-fn validate_file_handle(
-    name: &str,
-    handle: HANDLE,
-    expected_type: windows::Win32::Storage::FileSystem::FILE_TYPE,
-) -> Result<(), WslError> {
-    let file_type = unsafe { GetFileType(handle) };
-    if file_type == FILE_TYPE_UNKNOWN {
-        return Err(windows::core::Error::new(
-            wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
-            format!(
-                "{} ({:x}) is not a valid file handle: {:?}",
-                name,
-                handle.0,
-                unsafe { GetLastError() }
-            ),
-        )
-        .into());
+/// Holds a [`DiskLedger`]'s lock, releasing it when dropped.
+struct LedgerLock {
+    path: PathBuf,
+}
+
+impl Drop for LedgerLock {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
     }
-    let type_to_string = |file_type: windows::Win32::Storage::FileSystem::FILE_TYPE| match file_type
-    {
-        FILE_TYPE_DISK => "file",
-        FILE_TYPE_PIPE => "pipe",
-        FILE_TYPE_CHAR => "character device",
-        FILE_TYPE_REMOTE => "remote file",
-        FILE_TYPE_UNKNOWN => "unknown type",
-        _ => "invalid type",
-    };
+}
 
-    if file_type != expected_type {
-        let expected_type_name = type_to_string(expected_type);
-        return Err(windows::core::Error::new(
-            wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
-            format!(
-                "{} ({:x}) must be a {} (got a {})",
-                name,
-                handle.0,
-                expected_type_name,
-                type_to_string(file_type)
-            ),
-        )
-        .into());
+/// Classifies the bare `ERROR_ALREADY_EXISTS` a registration fails with
+/// when the name is taken. Other calls use the same HRESULT for files that
+/// already exist, so it isn't classified in general.
+fn name_taken(e: WslError) -> WslError {
+    if e.hresult() == ERROR_ALREADY_EXISTS.to_hresult() {
+        e.with_kind(WslErrorKind::DistributionAlreadyExists)
+    } else {
+        e
     }
-    Ok(())
 }
 
-struct CoMultithreadedInterface<T: Interface>(T);
+/// Checks that `path` is an existing VHDX file by its extension and
+/// signature.
+fn validate_vhdx(path: &Path) -> Result<(), WslError> {
+    use std::io::Read;
 
-unsafe impl<T: Interface> Send for CoMultithreadedInterface<T> {}
+    let invalid = |reason: &str| {
+        WslError::precondition(
+            WslErrorKind::InvalidPath,
+            E_INVALIDARG,
+            format!("{} {}", path.display(), reason),
+        )
+    };
 
-/// A higher-level API for interacting with WSL2 through COM
-pub struct Wsl2 {
-    /// Channel sender for communicating with the background COM thread
-    sender: Sender<Box<dyn FnOnce(&ILxssUserSession) + Send>>,
-    /// The WSL session object (thread-safe)
-    session: CoMultithreadedInterface<ILxssUserSession>,
-    /// Handle to the background thread
-    _background_thread: JoinHandle<()>,
+    if !path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("vhdx"))
+    {
+        return Err(invalid("does not have a .vhdx extension"));
+    }
+    // Checked before opening, as Windows refuses to open a directory
+    match std::fs::metadata(path) {
+        Ok(metadata) if !metadata.is_file() => return Err(invalid("is not a file")),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(invalid("does not exist"))
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let mut file = std::fs::File::open(path)?;
+    // Every VHDX starts with this file type identifier
+    let mut signature = [0; 8];
+    if file.read_exact(&mut signature).is_err() || &signature != b"vhdxfile" {
+        return Err(invalid("is not a VHDX disk"));
+    }
+    Ok(())
 }
 
-impl Wsl2 {
-    /// Creates a new WSL API instance with a background COM thread
-    pub fn new() -> Result<Self, WslError> {
-        let (sender, receiver) = mpsc::channel();
-        let (tx_init, rx_init) = mpsc::channel();
+/// How [`Wsl2::mount_disk`] attaches and mounts a disk.
+///
+/// ```
+/// use wsl_api::{AttachFlags, MountOptions};
+///
+/// let options = MountOptions::new()
+///     .flags(AttachFlags::VHD)
+///     .partition(1)
+///     .filesystem_type("ext4")
+///     .options("ro");
+/// assert_eq!(options.partition, Some(1));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    pub flags: AttachFlags,
+    /// The partition to mount, counting from 1, rather than the whole disk
+    pub partition: Option<u32>,
+    /// The directory under `/mnt/wsl` to mount on, rather than one named
+    /// after the disk
+    pub name: Option<String>,
+    /// The filesystem type, rather than `ext4`
+    pub filesystem_type: Option<String>,
+    /// Options passed to `mount -o`
+    pub options: Option<String>,
+}
 
-        let background_thread = thread::spawn(move || {
-            Self::com_thread_worker(receiver, tx_init);
-        });
+impl MountOptions {
+    /// Mounts the whole disk as `ext4`.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        let session = rx_init
-            .recv()
-            .expect("thread died (init)?")
-            .map_err(WslError::from)?;
+    /// Adds to the flags already set.
+    pub fn flags(mut self, flags: AttachFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
 
-        Ok(Wsl2 {
-            sender,
-            session,
-            _background_thread: background_thread,
-        })
+    pub fn partition(mut self, partition: u32) -> Self {
+        self.partition = Some(partition);
+        self
     }
 
-    /// Background thread worker that initializes COM and handles requests
-    fn com_thread_worker(
-        receiver: Receiver<Box<dyn FnOnce(&ILxssUserSession) + Send>>,
-        initialized: Sender<windows::core::Result<CoMultithreadedInterface<ILxssUserSession>>>,
-    ) {
-        unsafe {
-            // Initialize Winsock: this is required (unsure what requires it,
-            // but we get 8007276D otherwise)
-            // "Either the application has not called WSAStartup, or WSAStartup failed"
-            let mut wsa_data = std::mem::zeroed();
-            let result = WSAStartup(0x0202, &mut wsa_data);
-            if result != 0 {
-                initialized
-                    .send(Err(windows::core::Error::new(
-                        wsl_com_api_sys::error::WSL_E_INVALID_USAGE,
-                        format!("WSAStartup failed: 0x{:x}", result),
-                    )
-                    .into()))
-                    .expect("thread died (init tx)?");
-                return;
-            }
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
 
-            // Initialize COM with apartment threading
-            let result = CoInitializeEx(None, COINIT_MULTITHREADED);
-            if result.is_err() {
-                initialized
-                    .send(Err(result.into()))
-                    .expect("thread died (init tx)?");
-                return;
-            }
+    pub fn filesystem_type(mut self, filesystem_type: impl Into<String>) -> Self {
+        self.filesystem_type = Some(filesystem_type.into());
+        self
+    }
 
-            let result = CoInitializeSecurity(
-                None,
-                -1,
-                None,
-                None,
-                RPC_C_AUTHN_LEVEL_CONNECT,
-                RPC_C_IMP_LEVEL_IDENTIFY,
-                None,
-                EOAC_STATIC_CLOAKING,
-                None,
-            );
-            if let Err(e) = result {
-                CoUninitialize();
-                initialized.send(Err(e)).expect("thread died (init tx)?");
-                return;
-            }
+    pub fn options(mut self, options: impl Into<String>) -> Self {
+        self.options = Some(options.into());
+        self
+    }
+}
+
+impl From<AttachFlags> for MountOptions {
+    fn from(flags: AttachFlags) -> Self {
+        MountOptions {
+            flags,
+            ..Default::default()
         }
+    }
+}
 
-        // Get the WSL user session
-        let session = match unsafe { get_lxss_user_session() } {
-            Ok(session) => session,
-            Err(e) => {
-                unsafe {
-                    CoUninitialize();
-                }
-                initialized
-                    .send(Err(e.into()))
-                    .expect("thread died (init tx)?");
-                return;
-            }
-        };
+/// How and where [`Wsl2::register_distribution`] installs a distribution.
+///
+/// ```
+/// use wsl_api::{ImportFlags, RegisterOptions};
+///
+/// let options = RegisterOptions::new()
+///     .target_directory(r"D:\WSL\Ubuntu")
+///     .vhd_size("64GB".parse().unwrap())
+///     .fixed_vhd(true)
+///     .flags(ImportFlags::NO_OOBE);
+/// assert!(options.flags.contains(ImportFlags::FIXED_VHD | ImportFlags::NO_OOBE));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterOptions {
+    /// The directory the distribution's files are installed in, rather than
+    /// the service's default
+    pub target_directory: Option<PathBuf>,
+    /// The maximum size of a WSL2 distribution's disk, rather than the
+    /// service's default
+    pub vhd_size: Option<DiskSize>,
+    pub flags: ImportFlags,
+    /// The package family of the app that installed the distribution
+    pub package_family_name: Option<String>,
+    /// Whether the tarball is checked with [`tarball::inspect`] before a
+    /// file is registered, or while a stream is imported
+    pub inspect: bool,
+}
 
-        let result = Self::set_session_blanket(&session);
-        if let Err(e) = result {
-            unsafe {
-                CoUninitialize();
-            }
-            initialized.send(Err(e)).expect("thread died (init tx)?");
-            return;
-        }
-
-        initialized
-            .send(Ok(CoMultithreadedInterface(session.clone())))
-            .expect("thread died (init tx)?");
-
-        // Process requests from the main thread
-        for request in receiver {
-            request(&session);
-        }
-
-        // Cleanup COM
-        unsafe {
-            CoUninitialize();
-        }
-    }
-
-    /// Enables the WSL session to impersonate the calling user's identity when
-    /// making requests to the WSL service. This allows the session to access
-    /// user-specific resources and run processes with the correct permissions
-    /// without requiring elevated privileges for the entire session.
-    ///
-    /// See
-    /// https://learn.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iclientsecurity-setblanket
-    /// for details on COM security blankets.
-    fn set_session_blanket(session: &ILxssUserSession) -> windows::core::Result<()> {
-        let client_security: IClientSecurity = session.cast()?;
-
-        let mut authn_svc = 0;
-        let mut authz_svc = 0;
-        let mut authn_lvl: RPC_C_AUTHN_LEVEL = RPC_C_AUTHN_LEVEL(0);
-        let mut imp_lvl: RPC_C_IMP_LEVEL = RPC_C_IMP_LEVEL(0);
-        let mut capabilities: EOLE_AUTHENTICATION_CAPABILITIES =
-            EOLE_AUTHENTICATION_CAPABILITIES(0);
-        unsafe {
-            client_security.QueryBlanket::<&IUnknown>(
-                &session.0,
-                std::ptr::from_mut(&mut authn_svc),
-                Some(std::ptr::from_mut(&mut authz_svc)),
-                std::ptr::null_mut(),
-                Some(std::ptr::from_mut(&mut authn_lvl)),
-                Some(std::ptr::from_mut(&mut imp_lvl)),
-                std::ptr::null_mut(),
-                Some(std::ptr::from_mut(&mut capabilities.0) as _),
-            )?;
-        }
-
-        capabilities.0 &= !EOAC_STATIC_CLOAKING.0;
-        capabilities.0 |= EOAC_DYNAMIC_CLOAKING.0;
-
-        unsafe {
-            client_security.SetBlanket::<&IUnknown, PCWSTR>(
-                &session.0,
-                authn_svc,
-                authz_svc,
-                PCWSTR::null(),
-                authn_lvl,
-                RPC_C_IMP_LEVEL_IMPERSONATE,
-                None,
-                capabilities,
-            )?;
-        }
-
-        Ok(())
-    }
-
-    /// Executes a function on the COM thread
-    fn execute_thread<F, T>(&self, f: F) -> Result<T, WslError>
-    where
-        F: FnOnce(&ILxssUserSession) -> Result<T, WslError> + Send + 'static,
-        T: Send + 'static,
-    {
-        let (tx, rx) = mpsc::channel();
-        self.sender
-            .send(Box::new(move |session| {
-                let result = f(session);
-                _ = tx.send(result);
-            }))
-            .expect("thread died (tx)?");
-        rx.recv().expect("thread died (rx)?")
-    }
-
-    /// Executes a function on the current thread
-    fn execute<F, T>(&self, f: F) -> Result<T, WslError>
-    where
-        F: FnOnce(&ILxssUserSession) -> Result<T, WslError> + Send + 'static,
-        T: Send + 'static,
-    {
-        f(&self.session.0)
+impl RegisterOptions {
+    /// Installs in the default location with a default-sized dynamic disk.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// Shuts down WSL and closes this handle.
-    pub fn shutdown(self, force: bool) -> Result<(), WslError> {
-        self.execute_thread(move |session| unsafe {
-            session.Shutdown(force as i32)?;
-            Ok(())
-        })
+    pub fn target_directory(mut self, target_directory: impl Into<PathBuf>) -> Self {
+        self.target_directory = Some(target_directory.into());
+        self
     }
 
-    /// Gets the default distribution.
-    pub fn get_default_distribution(&self) -> Result<Uuid, WslError> {
-        self.execute(|session| unsafe {
-            Ok(session
-                .GetDefaultDistribution()
-                .map(|guid| Uuid::from_u128(guid.to_u128()))?)
-        })
+    pub fn vhd_size(mut self, vhd_size: DiskSize) -> Self {
+        self.vhd_size = Some(vhd_size);
+        self
     }
 
-    /// Launches a Linux process in the specified WSL distribution. The process
-    /// runs under the specified username and returns handles to
-    /// stdin/stdout/stderr for communication.
-    pub fn launch(
-        &self,
-        distro_guid: Uuid,
-        command: &str,
-        args: &[&str],
-        cwd: Option<&str>,
-        username: &str,
-    ) -> Result<WslProcess, WslError> {
-        let username = widestring::U16CString::from_str_truncate(username);
-        let command = CString::new(command).unwrap();
-        let cwd = cwd.map(|cwd| widestring::U16CString::from_str_truncate(cwd));
-        let nt_path = widestring::U16CString::from_str_truncate(
-            std::env::current_dir()
-                .unwrap_or_default()
-                .to_str()
-                .unwrap(),
-        );
-        let args = args
-            .iter()
-            .map(|arg| CString::new(*arg).unwrap())
-            .collect::<Vec<_>>();
+    /// Allocates the whole disk up front instead of growing it as needed.
+    /// Requires [`RegisterOptions::vhd_size`], and registering fails with
+    /// [`WslErrorKind::InvalidSize`] without one.
+    pub fn fixed_vhd(mut self, fixed: bool) -> Self {
+        self.flags.set(ImportFlags::FIXED_VHD, fixed);
+        self
+    }
 
-        let (stdin_r, stdin_w) = std::io::pipe().unwrap();
-        let (stdout_r, stdout_w) = std::io::pipe().unwrap();
-        let (stderr_r, stderr_w) = std::io::pipe().unwrap();
+    /// Adds to the flags already set.
+    pub fn flags(mut self, flags: ImportFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
 
-        let pipe = (
-            to_handle(&stdin_r),
-            to_handle(&stdout_w),
-            to_handle(&stderr_w),
-        );
+    pub fn package_family_name(mut self, package_family_name: impl Into<String>) -> Self {
+        self.package_family_name = Some(package_family_name.into());
+        self
+    }
 
-        let handles = LXSS_STD_HANDLES {
-            StdIn: LXSS_HANDLE {
-                Handle: pipe.0 .0 as _,
-                HandleType: LxssHandleType::LxssHandleInput,
-            },
-            StdOut: LXSS_HANDLE {
-                Handle: pipe.1 .0 as _,
-                HandleType: LxssHandleType::LxssHandleOutput,
-            },
-            StdErr: LXSS_HANDLE {
-                Handle: pipe.2 .0 as _,
-                HandleType: LxssHandleType::LxssHandleOutput,
-            },
-        };
+    /// Checks the tarball with [`tarball::inspect`], so that one that isn't
+    /// a root filesystem fails with the inspector's explanation rather than
+    /// the service's, as does one whose binaries are built for more than one
+    /// [`tarball::Architecture`]. A file given to
+    /// [`Wsl2::register_distribution`] is checked before the service is
+    /// called, while a stream given to
+    /// [`Wsl2::register_distribution_from_reader`] is checked as the service
+    /// imports it, and unregistered again if it fails the check.
+    pub fn inspect(mut self, inspect: bool) -> Self {
+        self.inspect = inspect;
+        self
+    }
 
-        std::mem::forget(stderr_w);
-        std::mem::forget(stdout_w);
-        std::mem::forget(stdin_r);
-
-        self.execute(move |session| unsafe {
-            let arg_ptrs = args
-                .iter()
-                .map(|arg| arg.to_bytes_with_nul().as_ptr())
-                .collect::<Vec<_>>();
-            let result = session.CreateLxProcess(
-                GUID::from_u128(distro_guid.as_u128()),
-                PCSTR::from_raw(command.as_ptr() as *const u8),
-                args.len() as u32,
-                arg_ptrs.as_ptr() as *const PCSTR,
-                PCWSTR::from_raw(cwd.map(|cwd| cwd.as_ptr()).unwrap_or(std::ptr::null())),
-                PCWSTR::from_raw(nt_path.as_ptr()),
-                std::ptr::null_mut(), // todo
-                0,                    // todo
-                PCWSTR::from_raw(username.as_ptr()),
-                80,
-                25,
-                0,
-                std::ptr::from_ref(&handles),
-                CreateInstanceFlags::empty().bits(),
-            )?;
-
-            #[allow(unreachable_code)]
-            let process = if result.ProcessHandle.is_invalid() {
-                // This is harder to mock on unix, so just bail
-                #[cfg(unix)]
-                #[allow(unused)]
-                let tcp = { unreachable!("Unsupported platform") };
-
-                #[cfg(windows)]
-                let tcp = {
-                    use std::net::TcpStream;
-                    use std::os::windows::io::FromRawSocket;
-                    TcpStream::from_raw_socket(result.InteropSocket.0 as _)
-                };
-
-                WslProcess {
-                    stdin: Some(from_handle(result.StandardIn)),
-                    stdout: Some(from_handle(result.StandardOut)),
-                    stderr: Some(from_handle(result.StandardErr)),
-                    pipe,
-                    handle: WslProcessInner::WSL2(Interop::new(tcp), result.CommunicationChannel),
-                }
-            } else {
-                let process = WslProcess {
-                    stdin: Some(from_handle(to_handle(&stdin_w))),
-                    stdout: Some(from_handle(to_handle(&stdout_r))),
-                    stderr: Some(from_handle(to_handle(&stderr_r))),
-                    pipe,
-                    handle: WslProcessInner::WSL1(result.ProcessHandle),
-                };
-
-                // Close the server handle
-                _ = CloseHandle(result.ServerHandle);
-
-                std::mem::forget(stdin_w);
-                std::mem::forget(stdout_r);
-                std::mem::forget(stderr_r);
-
-                process
-            };
-
-            Ok(process)
-        })
+    /// Checks the options before they are handed to the service.
+    fn validated(mut self) -> Result<Self, WslError> {
+        if self.flags.contains(ImportFlags::FIXED_VHD) && self.vhd_size.is_none() {
+            return Err(WslError::precondition(
+                WslErrorKind::InvalidSize,
+                E_INVALIDARG,
+                "A fixed size VHD needs a size",
+            ));
+        }
+        // The service resolves relative paths against its own working
+        // directory, not ours
+        if let Some(target_directory) = &self.target_directory {
+            self.target_directory = Some(std::path::absolute(target_directory)?);
+        }
+        Ok(self)
     }
+}
 
-    /// Enumerates the distributions.
-    pub fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
-        self.execute(|session| unsafe {
-            let (count, distros) = session.EnumerateDistributions()?;
-            let distros_copy = {
-                let slice = std::slice::from_raw_parts(distros, count as usize);
-                slice
-                    .iter()
-                    .map(|distro| Distribution::from(distro))
-                    .collect()
-            };
-            CoTaskMemFree(Some(distros as _));
-            Ok(distros_copy)
-        })
+impl From<ImportFlags> for RegisterOptions {
+    fn from(flags: ImportFlags) -> Self {
+        RegisterOptions {
+            flags,
+            ..Default::default()
+        }
     }
+}
 
-    /// Exports a distribution.
-    pub fn export_distribution(
-        &self,
-        distro_guid: Uuid,
-        file: impl AsRawHandle,
-        stderr: impl AsRawHandle,
-        flags: ExportFlags,
-    ) -> Result<(), WslError> {
-        let file_handle = to_handle(&file);
-        let stderr_handle = to_handle(&stderr);
-
-        let res = self.execute(move |session| unsafe {
-            // Validate handles in the COM thread to ensure they're still valid
-            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
-            validate_file_handle("file_handle", file_handle, FILE_TYPE_DISK)?;
-
-            session.ExportDistribution(
-                GUID::from_u128(distro_guid.as_u128()),
-                file_handle,
-                stderr_handle,
-                flags.bits(),
-            )?;
-            Ok(())
-        });
+/// Opts in to making a virtual disk sparse despite the risk of corrupting it.
+/// See [`Wsl2::set_sparse`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllowUnsafeSparse(());
 
-        drop(file);
-        drop(stderr);
-        res
+impl AllowUnsafeSparse {
+    /// Acknowledges that a sparse disk may be corrupted, and that the
+    /// distribution should be backed up first.
+    pub fn accept_risk_of_data_corruption() -> Self {
+        AllowUnsafeSparse(())
     }
+}
 
-    /// Registers a WSL distribution in the default location. Note that the
-    /// distribution name must be unique.
-    pub fn register_distribution(
-        &self,
-        name: &str,
-        version: Version,
-        file: impl AsRawHandle,
-        stderr: impl AsRawHandle,
-        flags: ImportFlags,
-    ) -> Result<(Uuid, String), WslError> {
-        let file_handle = to_handle(&file);
-        let stderr_handle = to_handle(&stderr);
-        let wide_name = widestring::U16CString::from_str_truncate(name);
-
-        let res = self.execute(move |session| unsafe {
-            // Validate handles in the COM thread to ensure they're still valid
-            validate_file_handle("stderr_handle", stderr_handle, FILE_TYPE_PIPE)?;
-            validate_file_handle("file_handle", file_handle, FILE_TYPE_DISK)?;
-
-            let result = session.RegisterDistribution(
-                PCWSTR::from_raw(wide_name.as_ptr()),
-                version.into(),
-                file_handle,
-                stderr_handle,
-                PCWSTR::null(),
-                flags.bits(),
-                0,
-                PCWSTR::null(),
-            )?;
-            let name = result.InstalledName.to_string().unwrap_or_default();
-            CoTaskMemFree(Some(result.InstalledName.0 as _));
-            Ok((Uuid::from_u128(result.Guid.to_u128()), name))
-        });
+/// Identifies a distribution by name, by GUID, or as whichever distribution
+/// is currently the default. Names are matched case-insensitively by the
+/// service.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DistributionRef {
+    Default,
+    Name(String),
+    Uuid(Uuid),
+}
 
-        drop(file);
-        drop(stderr);
-        res
+impl From<Uuid> for DistributionRef {
+    fn from(uuid: Uuid) -> Self {
+        DistributionRef::Uuid(uuid)
     }
+}
 
-    pub fn set_version(
-        &self,
-        distribution: Uuid,
-        version: Version,
-        stderr: impl AsRawHandle,
-    ) -> Result<(), WslError> {
-        let handle = to_handle(&stderr);
-        let res = self.execute(move |session| unsafe {
-            session.SetVersion(
-                GUID::from_u128(distribution.as_u128()),
-                version.into(),
-                handle,
-            )?;
-            Ok(())
-        });
+impl From<&Uuid> for DistributionRef {
+    fn from(uuid: &Uuid) -> Self {
+        DistributionRef::Uuid(*uuid)
+    }
+}
 
-        drop(stderr);
-        res
+impl From<&str> for DistributionRef {
+    fn from(name: &str) -> Self {
+        DistributionRef::Name(name.to_string())
     }
 }
 
-impl Drop for Wsl2 {
-    fn drop(&mut self) {
-        // The background thread will be joined when the JoinHandle is dropped
-        // COM will be cleaned up in the background thread
+impl From<String> for DistributionRef {
+    fn from(name: String) -> Self {
+        DistributionRef::Name(name)
     }
 }
 
-#[derive(Debug)]
-pub struct Distribution {
-    pub name: String,
-    pub uuid: Uuid,
-    pub version: Version,
+impl From<&Distribution> for DistributionRef {
+    fn from(distribution: &Distribution) -> Self {
+        DistributionRef::Uuid(distribution.uuid)
+    }
 }
 
-impl From<&LXSS_ENUMERATE_INFO> for Distribution {
-    fn from(info: &LXSS_ENUMERATE_INFO) -> Self {
-        let name = unsafe {
-            PCWSTR::from_raw(info.DistroName.as_ptr())
-                .to_string()
-                .unwrap()
-        };
-        Self {
-            name: name.to_string(),
-            uuid: Uuid::from_u128(info.DistroGuid.to_u128()),
-            version: match info.Version {
-                1 => Version::WSL1,
-                2 => Version::WSL2,
-                _ => Version::Unknown(info.Version),
-            },
+impl std::fmt::Display for DistributionRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DistributionRef::Default => write!(f, "default distribution"),
+            DistributionRef::Name(name) => write!(f, "{}", name),
+            DistributionRef::Uuid(uuid) => write!(f, "{{{}}}", uuid),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Version {
     Legacy,
     WSL1,
@@ -614,6 +2176,17 @@ pub enum Version {
     Unknown(u32),
 }
 
+impl From<u32> for Version {
+    fn from(version: u32) -> Self {
+        match version {
+            0 => Version::Legacy,
+            1 => Version::WSL1,
+            2 => Version::WSL2,
+            _ => Version::Unknown(version),
+        }
+    }
+}
+
 impl Into<u32> for Version {
     fn into(self) -> u32 {
         match self {
@@ -626,6 +2199,20 @@ impl Into<u32> for Version {
 }
 
 bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    pub struct DistributionFlags: u32 {
+        /// Allow Windows processes to be launched from the distribution
+        const ENABLE_INTEROP = LXSS_DISTRO_FLAGS_ENABLE_INTEROP;
+        /// Add the Windows `PATH` to `$PATH`
+        const APPEND_NT_PATH = LXSS_DISTRO_FLAGS_APPEND_NT_PATH;
+        /// Mount Windows drives under `/mnt`
+        const ENABLE_DRIVE_MOUNTING = LXSS_DISTRO_FLAGS_ENABLE_DRIVE_MOUNTING;
+        /// The distribution runs in the WSL2 VM (set by the service)
+        const VM_MODE = LXSS_DISTRO_FLAGS_VM_MODE;
+        /// Set by the service
+        const WSLCORE_MODE = LXSS_DISTRO_FLAGS_WSLCORE_MODE;
+    }
+
     #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct ExportFlags: u32 {
         const VHD = LXSS_EXPORT_DISTRO_FLAGS_VHD;
@@ -634,7 +2221,7 @@ bitflags! {
         const VERBOSE = LXSS_EXPORT_DISTRO_FLAGS_VERBOSE;
     }
 
-    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct ImportFlags: u32 {
         const VHD = LXSS_IMPORT_DISTRO_FLAGS_VHD;
         const CREATE_SHORTCUT = LXSS_IMPORT_DISTRO_FLAGS_CREATE_SHORTCUT;
@@ -643,6 +2230,14 @@ bitflags! {
         const FIXED_VHD = LXSS_IMPORT_DISTRO_FLAGS_FIXED_VHD;
     }
 
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct AttachFlags: u32 {
+        /// The disk is a physical disk, passed through to the VM
+        const PASS_THROUGH = LXSS_ATTACH_MOUNT_FLAGS_PASS_THROUGH;
+        /// The disk is a `.vhdx` file rather than a physical disk
+        const VHD = LXSS_ATTACH_MOUNT_FLAGS_VHD;
+    }
+
     #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct CreateInstanceFlags: u32 {
         const ALLOW_FS_UPGRADE = LXSS_CREATE_INSTANCE_FLAGS_ALLOW_FS_UPGRADE;
@@ -653,12 +2248,22 @@ bitflags! {
     }
 }
 
+impl DistributionFlags {
+    /// The flags that can be changed with
+    /// [`Wsl2::configure_distribution`]. The others follow from the
+    /// distribution's version and are managed by the service.
+    pub const CONFIGURABLE: Self = Self::ENABLE_INTEROP
+        .union(Self::APPEND_NT_PATH)
+        .union(Self::ENABLE_DRIVE_MOUNTING);
+}
+
 #[derive(Debug)]
 pub struct WslProcess {
     pub stdin: Option<ChildStdin>,
     pub stdout: Option<ChildStdout>,
     pub stderr: Option<ChildStderr>,
-    pipe: (HANDLE, HANDLE, HANDLE),
+    /// Our copies of the handles given to the service as the process' stdio
+    pipe: Option<(HANDLE, HANDLE, HANDLE)>,
     handle: WslProcessInner,
 }
 
@@ -669,38 +2274,64 @@ fn u32_to_exit_status(exit_code: u32) -> ExitStatus {
     #[cfg(windows)]
     use std::os::windows::process::ExitStatusExt;
 
+    // Unix expects a wait status, which keeps the exit code in bits 8-15
+    #[cfg(unix)]
+    let exit_code = (exit_code & 0xff) << 8;
+
     ExitStatusExt::from_raw(exit_code as _)
 }
 
 impl WslProcess {
-    pub fn wait(self) -> Result<ExitStatus, WslError> {
-        match &self.handle {
+    pub fn wait(mut self) -> Result<ExitStatus, WslError> {
+        match &mut self.handle {
             WslProcessInner::WSL1(handle) => {
                 // Use WSL-specific waiting mechanism instead of WaitForSingleObject
                 let exit_code = unsafe { wait_for_wsl_process(*handle, u32::MAX)? };
                 Ok(u32_to_exit_status(exit_code))
             }
             WslProcessInner::WSL2(interop, _) => {
-                let exit = interop.recv_exit_code();
-                Ok(exit.map(u32_to_exit_status).unwrap_or_default())
+                let exit_code = interop.recv_exit_code(None)?.expect("no timeout was given");
+                Ok(u32_to_exit_status(exit_code))
             }
         }
     }
+
+    /// Waits up to `timeout` for the process to exit, returning `None` if it
+    /// is still running.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<ExitStatus>, WslError> {
+        match &mut self.handle {
+            WslProcessInner::WSL1(handle) => {
+                // u32::MAX means "wait forever" to the IOCTL
+                let timeout_ms = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+                match unsafe { wait_for_wsl_process(*handle, timeout_ms) } {
+                    Ok(exit_code) => Ok(Some(u32_to_exit_status(exit_code))),
+                    Err(e) if e.hresult() == ERROR_TIMEOUT.to_hresult() => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            WslProcessInner::WSL2(interop, _) => Ok(interop
+                .recv_exit_code(Some(timeout))?
+                .map(u32_to_exit_status)),
+        }
+    }
 }
 
 impl Drop for WslProcess {
     fn drop(&mut self) {
         match self.handle {
-            WslProcessInner::WSL2(_, handle) => unsafe { _ = CloseHandle(handle) },
+            WslProcessInner::WSL2(_, Some(handle)) => unsafe { close_handle(handle) },
+            WslProcessInner::WSL2(_, None) => {}
             WslProcessInner::WSL1(handle) => unsafe {
-                _ = CloseHandle(handle);
+                close_handle(handle);
             },
         }
 
-        unsafe {
-            _ = CloseHandle(self.pipe.0);
-            _ = CloseHandle(self.pipe.1);
-            _ = CloseHandle(self.pipe.2);
+        if let Some(pipe) = self.pipe {
+            unsafe {
+                close_handle(pipe.0);
+                close_handle(pipe.1);
+                close_handle(pipe.2);
+            }
         }
     }
 }
@@ -708,5 +2339,7 @@ impl Drop for WslProcess {
 #[derive(Debug)]
 enum WslProcessInner {
     WSL1(HANDLE),
-    WSL2(Interop, HANDLE),
+    /// The exit status arrives over the interop socket. The communication
+    /// channel is absent for processes that don't come from the service.
+    WSL2(Interop, Option<HANDLE>),
 }
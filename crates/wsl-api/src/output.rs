@@ -0,0 +1,81 @@
+use std::io::Read;
+
+use windows::Win32::Foundation::HANDLE;
+
+use crate::{to_handle, WslError};
+
+/// The result of an operation along with the progress and warnings the
+/// service wrote while performing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured<T> {
+    pub value: T,
+    /// Every line the service wrote, each followed by a newline
+    pub output: String,
+}
+
+/// Calls `f` with the write end of a pipe, delivering whatever the service
+/// writes to it to `on_line` one line at a time as it arrives.
+///
+/// The pipe is read on a scoped thread so the service never blocks on a full
+/// pipe, and every line has been delivered by the time this returns. If `f`
+/// fails, the lines are also attached to the error as its
+/// [`WslError::output`].
+pub(crate) fn with_output_pipe<T>(
+    on_line: impl FnMut(&str) + Send,
+    f: impl FnOnce(HANDLE) -> Result<T, WslError>,
+) -> Result<T, WslError> {
+    capture_output(on_line, f).map(|captured| captured.value)
+}
+
+/// [`with_output_pipe`], returning the lines along with the result.
+pub(crate) fn capture_output<T>(
+    mut on_line: impl FnMut(&str) + Send,
+    f: impl FnOnce(HANDLE) -> Result<T, WslError>,
+) -> Result<Captured<T>, WslError> {
+    let (reader, writer) = std::io::pipe()?;
+    std::thread::scope(|scope| {
+        let pump = scope.spawn(move || {
+            let mut collected = String::new();
+            let mut deliver = |line: &[u8]| {
+                let line = String::from_utf8_lossy(line);
+                let line = line.trim_matches('\0');
+                if !line.trim().is_empty() {
+                    on_line(line);
+                    collected.push_str(line);
+                    collected.push('\n');
+                }
+            };
+
+            // Progress is often redrawn in place with a carriage return, so
+            // that ends a line too
+            let mut reader = reader;
+            let mut pending = vec![];
+            let mut buf = [0; 4096];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                for &byte in &buf[..n] {
+                    if byte == b'\n' || byte == b'\r' {
+                        deliver(&pending);
+                        pending.clear();
+                    } else {
+                        pending.push(byte);
+                    }
+                }
+            }
+            deliver(&pending);
+            collected
+        });
+
+        let result = f(to_handle(&writer));
+        // Our end must be closed for the reader to see the end of the output
+        drop(writer);
+        let collected = pump.join().expect("output pump panicked");
+        match result {
+            Ok(value) => Ok(Captured {
+                value,
+                output: collected,
+            }),
+            Err(e) if !collected.is_empty() => Err(e.with_output(collected)),
+            Err(e) => Err(e),
+        }
+    })
+}
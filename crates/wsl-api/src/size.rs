@@ -0,0 +1,113 @@
+use windows::Win32::Foundation::E_INVALIDARG;
+
+use crate::{WslError, WslErrorKind};
+
+/// A disk size in bytes, which can be parsed from strings like `512MB`,
+/// `1.5TB` or `20G`.
+///
+/// Like `wsl --manage --resize`, units are binary: `1GB` is 1024³ bytes.
+///
+/// ```
+/// use wsl_api::DiskSize;
+///
+/// let size: DiskSize = "1.5GB".parse().unwrap();
+/// assert_eq!(size.bytes(), 1536 * 1024 * 1024);
+/// assert_eq!(size.to_string(), "1.5GB");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DiskSize(u64);
+
+const UNITS: [(&str, u64); 5] = [
+    ("TB", 1 << 40),
+    ("GB", 1 << 30),
+    ("MB", 1 << 20),
+    ("KB", 1 << 10),
+    ("B", 1),
+];
+
+impl DiskSize {
+    pub const fn from_bytes(bytes: u64) -> Self {
+        DiskSize(bytes)
+    }
+
+    pub const fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for DiskSize {
+    fn from(bytes: u64) -> Self {
+        DiskSize(bytes)
+    }
+}
+
+impl std::str::FromStr for DiskSize {
+    type Err = WslError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            WslError::precondition(
+                WslErrorKind::InvalidSize,
+                E_INVALIDARG,
+                format!("'{}' is not a valid size", s),
+            )
+        };
+
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        // Accept `G`, `GB` and `GiB` alike
+        let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            _ => return Err(invalid()),
+        };
+
+        let bytes = match number.split_once('.') {
+            None => number
+                .parse::<u64>()
+                .ok()
+                .and_then(|n| n.checked_mul(multiplier)),
+            // Fractions are only meaningful with a unit, and are rounded
+            // down to whole bytes
+            Some((whole, fraction)) if !fraction.is_empty() && multiplier > 1 => {
+                let whole = if whole.is_empty() {
+                    Some(0)
+                } else {
+                    whole.parse::<u64>().ok()
+                };
+                let fraction = format!("0.{}", fraction).parse::<f64>().ok();
+                whole.zip(fraction).and_then(|(whole, fraction)| {
+                    whole
+                        .checked_mul(multiplier)?
+                        .checked_add((fraction * multiplier as f64) as u64)
+                })
+            }
+            Some(_) => None,
+        };
+        bytes.map(DiskSize).ok_or_else(invalid)
+    }
+}
+
+impl std::fmt::Display for DiskSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (unit, multiplier) = UNITS
+            .iter()
+            .find(|(_, multiplier)| self.0 >= *multiplier)
+            .unwrap_or(&("B", 1));
+        if self.0.is_multiple_of(*multiplier) {
+            write!(f, "{}{}", self.0 / multiplier, unit)
+        } else {
+            let value = self.0 as f64 / *multiplier as f64;
+            // Two decimal places, without trailing zeros
+            let value = format!("{:.2}", value);
+            let value = value.trim_end_matches('0').trim_end_matches('.');
+            write!(f, "{}{}", value, unit)
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! Named snapshots of distributions, kept in a directory on the host.
+//!
+//! A snapshot is a tarball exported from a distribution, stored next to a JSON
+//! [`SnapshotManifest`] that records where it came from and a SHA-256 of the
+//! tarball. Restoring a snapshot registers the tarball again with the source's
+//! name, WSL version, default user and flags.
+//!
+//! ```
+//! use wsl_api::{fake::FakeBackend, snapshot::SnapshotStore, Version, Wsl2};
+//!
+//! let fake = FakeBackend::new();
+//! fake.add_distribution("Ubuntu", Version::WSL2);
+//! let wsl = Wsl2::with_backend(fake);
+//! # let dir = std::env::temp_dir().join(format!("wsl-api-snapshots-{}", std::process::id()));
+//! let store = SnapshotStore::open(&wsl, &dir)?;
+//!
+//! store.snapshot("Ubuntu", "before-upgrade")?;
+//! assert_eq!(store.list()?.len(), 1);
+//!
+//! // Put Ubuntu back the way it was
+//! store.restore("before-upgrade", true)?;
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! # Ok::<(), wsl_api::WslError>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use windows::Win32::Foundation::{
+    ERROR_ALREADY_EXISTS, ERROR_FILE_CORRUPT, ERROR_FILE_NOT_FOUND, E_INVALIDARG,
+};
+
+use crate::{
+    output, DistributionConfiguration, DistributionRef, ExportFlags, RegisterOptions, Version,
+    Wsl2, WslError, WslErrorKind,
+};
+
+/// What a snapshot was taken from, stored as JSON next to its tarball.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub label: String,
+    /// The GUID of the distribution the snapshot was taken from.
+    pub source_guid: Uuid,
+    /// The name of the distribution the snapshot was taken from.
+    pub name: String,
+    pub version: Version,
+    pub configuration: DistributionConfiguration,
+    /// When the snapshot was taken.
+    pub created: SystemTime,
+    /// The size of the tarball in bytes.
+    pub size: u64,
+    /// The SHA-256 of the tarball, as lowercase hex.
+    pub sha256: String,
+}
+
+/// A directory of snapshots, taken from and restored to the distributions of
+/// a [`Wsl2`].
+///
+/// Each snapshot is stored as `<label>.tar` with its manifest in
+/// `<label>.json`. The manifest is written last, so a snapshot that was
+/// interrupted is never listed.
+pub struct SnapshotStore<'a> {
+    wsl: &'a Wsl2,
+    dir: PathBuf,
+}
+
+impl<'a> SnapshotStore<'a> {
+    /// Opens the store in `dir`, creating the directory if needed.
+    pub fn open(wsl: &'a Wsl2, dir: impl AsRef<Path>) -> Result<Self, WslError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { wsl, dir })
+    }
+
+    /// The directory the snapshots are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Exports `distribution` into the store as `label`, returning the
+    /// manifest written alongside it.
+    ///
+    /// Labels are used as file names, so they may only contain ASCII
+    /// letters, digits, `-`, `_` and `.`, and fail with
+    /// [`WslErrorKind::InvalidPath`] otherwise. A label that is already in
+    /// use fails with [`WslErrorKind::SnapshotAlreadyExists`].
+    pub fn snapshot(
+        &self,
+        distribution: impl Into<DistributionRef>,
+        label: &str,
+    ) -> Result<SnapshotManifest, WslError> {
+        check_label(label)?;
+        if self.manifest_path(label).exists() {
+            return Err(WslError::precondition(
+                WslErrorKind::SnapshotAlreadyExists,
+                ERROR_ALREADY_EXISTS.to_hresult(),
+                format!("There is already a snapshot labelled '{label}'"),
+            ));
+        }
+        let distro_guid = self.wsl.resolve_distribution(distribution)?;
+        let configuration = self.wsl.distribution_configuration(distro_guid)?;
+
+        let partial = self.dir.join(format!("{label}.tar.partial"));
+        let exported = File::create(&partial)
+            .map_err(WslError::from)
+            .and_then(|file| {
+                let mut sink = HashingWriter::new(BufWriter::new(file));
+                output::with_output_pipe(
+                    |_| {},
+                    |stderr| {
+                        self.wsl.export_to_writer(
+                            distro_guid,
+                            &mut sink,
+                            stderr,
+                            ExportFlags::empty(),
+                        )
+                    },
+                )?;
+                Ok(sink.finish())
+            });
+        let (size, sha256) = match exported {
+            Ok(exported) => exported,
+            Err(e) => {
+                _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        std::fs::rename(&partial, self.tarball_path(label))?;
+
+        let manifest = SnapshotManifest {
+            label: label.to_string(),
+            source_guid: distro_guid,
+            name: configuration.name.clone(),
+            version: configuration.version,
+            configuration,
+            created: SystemTime::now(),
+            size,
+            sha256,
+        };
+        let json = serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::from)?;
+        // Renamed into place, so that a crash never leaves half a manifest
+        let partial = self.dir.join(format!("{label}.json.partial"));
+        std::fs::write(&partial, json)?;
+        std::fs::rename(&partial, self.manifest_path(label))?;
+        Ok(manifest)
+    }
+
+    /// Registers the snapshot `label` again under the name it was taken
+    /// from. See [`SnapshotStore::restore_as`].
+    pub fn restore(&self, label: &str, replace: bool) -> Result<(Uuid, String), WslError> {
+        let manifest = self.manifest(label)?;
+        self.restore_as(label, &manifest.name, replace)
+    }
+
+    /// Registers the snapshot `label` as `name`, with the WSL version,
+    /// default user and flags it was taken with. Returns the new
+    /// distribution's GUID and name.
+    ///
+    /// The snapshot is verified first. If `name` is already registered, it
+    /// is replaced when `replace` is set, keeping its place as the default
+    /// distribution, and otherwise the call fails with
+    /// [`WslErrorKind::DistributionAlreadyExists`].
+    ///
+    /// The service can't rename a distribution, so a replacement can only
+    /// be registered as `name` once the existing distribution has been
+    /// unregistered. If registering it then fails, the existing distribution
+    /// is gone, and the error points at the snapshot, which is left intact
+    /// for another attempt. Once the snapshot is registered as `name`, the
+    /// restore has succeeded: failing to make it the default again isn't
+    /// reported.
+    pub fn restore_as(
+        &self,
+        label: &str,
+        name: &str,
+        replace: bool,
+    ) -> Result<(Uuid, String), WslError> {
+        let manifest = self.verify(label)?;
+
+        let existing = match self.wsl.resolve_distribution(name) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == Some(WslErrorKind::DistributionNotFound) => {
+                return self.register(label, &manifest, name)
+            }
+            Err(e) => return Err(e),
+        };
+        if !replace {
+            return Err(WslError::precondition(
+                WslErrorKind::DistributionAlreadyExists,
+                ERROR_ALREADY_EXISTS.to_hresult(),
+                format!("'{name}' is already registered"),
+            ));
+        }
+
+        let was_default = self.wsl.get_default_distribution().ok() == Some(existing);
+        self.wsl.unregister_distribution(existing, name, true)?;
+        let restored = self.register(label, &manifest, name).map_err(|e| {
+            e.with_context(format_args!(
+                "'{name}' was unregistered, and can be restored again from the snapshot '{label}' in {}",
+                self.dir.display()
+            ))
+        })?;
+        if was_default {
+            _ = self.wsl.set_default_distribution(restored.0);
+        }
+        Ok(restored)
+    }
+
+    /// Registers the snapshot `label` as `name` and configures it like the
+    /// distribution it was taken from, unregistering it again if that fails.
+    fn register(
+        &self,
+        label: &str,
+        manifest: &SnapshotManifest,
+        name: &str,
+    ) -> Result<(Uuid, String), WslError> {
+        let tarball = BufReader::new(File::open(self.tarball_path(label))?);
+        let (guid, installed_name) = output::with_output_pipe(
+            |_| {},
+            |stderr| {
+                self.wsl.register_from_reader(
+                    name,
+                    manifest.version,
+                    tarball,
+                    stderr,
+                    RegisterOptions::new(),
+                )
+            },
+        )?;
+        let configured = self.wsl.configure_distribution(guid, |configuration| {
+            configuration.default_uid = manifest.configuration.default_uid;
+            configuration.flags = manifest.configuration.flags;
+        });
+        if let Err(e) = configured {
+            _ = self.wsl.backend.unregister_distribution(guid);
+            return Err(e);
+        }
+        Ok((guid, installed_name))
+    }
+
+    /// Reads the manifest of the snapshot `label`, failing with
+    /// [`WslErrorKind::SnapshotNotFound`] if there is no such snapshot.
+    pub fn manifest(&self, label: &str) -> Result<SnapshotManifest, WslError> {
+        check_label(label)?;
+        let json = match std::fs::read(self.manifest_path(label)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(WslError::precondition(
+                    WslErrorKind::SnapshotNotFound,
+                    ERROR_FILE_NOT_FOUND.to_hresult(),
+                    format!("There is no snapshot labelled '{label}'"),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&json).map_err(|e| {
+            WslError::precondition(
+                WslErrorKind::SnapshotCorrupted,
+                ERROR_FILE_CORRUPT.to_hresult(),
+                format!("The manifest of snapshot '{label}' is unreadable: {e}"),
+            )
+        })
+    }
+
+    /// Lists the snapshots in the store, oldest first. JSON files that
+    /// aren't readable snapshot manifests are skipped.
+    pub fn list(&self) -> Result<Vec<SnapshotManifest>, WslError> {
+        let mut manifests = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let label = path.file_stem().and_then(|stem| stem.to_str());
+                if let Some(Ok(manifest)) = label.map(|label| self.manifest(label)) {
+                    manifests.push(manifest);
+                }
+            }
+        }
+        manifests.sort_by(|a, b| (a.created, &a.label).cmp(&(b.created, &b.label)));
+        Ok(manifests)
+    }
+
+    /// Checks that the tarball of the snapshot `label` is the one its
+    /// manifest describes, failing with [`WslErrorKind::SnapshotCorrupted`]
+    /// if it is missing or has changed. Returns the manifest.
+    pub fn verify(&self, label: &str) -> Result<SnapshotManifest, WslError> {
+        let manifest = self.manifest(label)?;
+        let corrupted = |problem: &str| {
+            WslError::precondition(
+                WslErrorKind::SnapshotCorrupted,
+                ERROR_FILE_CORRUPT.to_hresult(),
+                format!("The tarball of snapshot '{label}' {problem}"),
+            )
+        };
+
+        let mut tarball = match File::open(self.tarball_path(label)) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(corrupted("is missing"))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut hasher = HashingWriter::new(std::io::sink());
+        std::io::copy(&mut tarball, &mut hasher)?;
+        let (size, sha256) = hasher.finish();
+        if size != manifest.size {
+            return Err(corrupted(&format!(
+                "is {size} bytes, not {} bytes",
+                manifest.size
+            )));
+        }
+        if sha256 != manifest.sha256 {
+            return Err(corrupted("doesn't match its SHA-256"));
+        }
+        Ok(manifest)
+    }
+
+    /// Deletes the snapshot `label`.
+    pub fn remove(&self, label: &str) -> Result<(), WslError> {
+        self.manifest(label)?;
+        // Without its manifest the snapshot is no longer listed, even if
+        // deleting the tarball fails
+        std::fs::remove_file(self.manifest_path(label))?;
+        match std::fs::remove_file(self.tarball_path(label)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes all but the newest `keep` snapshots of each distribution,
+    /// returning the manifests of the snapshots that were deleted.
+    pub fn prune(&self, keep: usize) -> Result<Vec<SnapshotManifest>, WslError> {
+        let mut by_source: HashMap<Uuid, Vec<SnapshotManifest>> = HashMap::new();
+        for manifest in self.list()? {
+            by_source
+                .entry(manifest.source_guid)
+                .or_default()
+                .push(manifest);
+        }
+
+        let mut pruned = vec![];
+        for mut manifests in by_source.into_values() {
+            let excess = manifests.len().saturating_sub(keep);
+            for manifest in manifests.drain(..excess) {
+                self.remove(&manifest.label)?;
+                pruned.push(manifest);
+            }
+        }
+        pruned.sort_by(|a, b| (a.created, &a.label).cmp(&(b.created, &b.label)));
+        Ok(pruned)
+    }
+
+    fn tarball_path(&self, label: &str) -> PathBuf {
+        self.dir.join(format!("{label}.tar"))
+    }
+
+    fn manifest_path(&self, label: &str) -> PathBuf {
+        self.dir.join(format!("{label}.json"))
+    }
+}
+
+fn check_label(label: &str) -> Result<(), WslError> {
+    let valid = !label.is_empty()
+        && !label.starts_with('.')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(WslError::precondition(
+            WslErrorKind::InvalidPath,
+            E_INVALIDARG,
+            format!("'{label}' is not a valid snapshot label"),
+        ))
+    }
+}
+
+/// Passes writes through to `inner`, keeping a count and a SHA-256 of
+/// everything written.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the number of bytes written and their SHA-256 as hex.
+    fn finish(self) -> (u64, String) {
+        (self.size, format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,407 @@
+//! Checking a root filesystem tarball before registering it.
+//!
+//! The service only rejects a bad tarball once it has started importing it,
+//! with a bare `WSL_E_NOT_A_LINUX_DISTRO` or `WSL_E_IMPORT_FAILED`. [`inspect`]
+//! reads the tarball up front, entirely in Rust, and either describes it in a
+//! [`TarballReport`] or explains what is wrong with it.
+//! [`RegisterOptions::inspect`](crate::RegisterOptions::inspect) does the same
+//! for a tarball as it is streamed to the service.
+//!
+//! ```
+//! use wsl_api::{tarball::{self, Architecture, Compression}, WslErrorKind};
+//! # fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+//! #     let mut builder = tar::Builder::new(vec![]);
+//! #     for (path, contents) in files {
+//! #         let mut header = tar::Header::new_gnu();
+//! #         header.set_size(contents.len() as u64);
+//! #         header.set_mode(0o755);
+//! #         builder.append_data(&mut header, path, *contents).unwrap();
+//! #     }
+//! #     builder.into_inner().unwrap()
+//! # }
+//! # let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+//! # elf.resize(18, 0);
+//! # elf.extend_from_slice(&62u16.to_le_bytes());
+//!
+//! let rootfs = tarball(&[
+//!     ("bin/sh", &elf),
+//!     ("etc/os-release", b"NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.20.0\n"),
+//! ]);
+//! let report = tarball::inspect(rootfs.as_slice())?;
+//! assert_eq!(report.compression, Compression::None);
+//! assert_eq!(report.os_release.id(), "alpine");
+//! assert_eq!(report.os_release.version_id(), Some("3.20.0"));
+//! assert_eq!(report.architecture()?, Some(Architecture::X86_64));
+//!
+//! let empty = tarball(&[("README", b"hello")]);
+//! let err = tarball::inspect(empty.as_slice()).unwrap_err();
+//! assert_eq!(err.kind(), Some(WslErrorKind::NotALinuxDistribution));
+//! # Ok::<(), wsl_api::WslError>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path};
+
+use wsl_com_api_sys::error::{WSL_E_IMPORT_FAILED, WSL_E_NOT_A_LINUX_DISTRO};
+
+use crate::{WslError, WslErrorKind};
+
+/// How a tarball is compressed, as the service recognizes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Recognizes the compression from the first bytes of a tarball.
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// The machine a distribution's binaries are built for, from their ELF
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Architecture {
+    X86,
+    X86_64,
+    Arm,
+    Aarch64,
+    RiscV,
+    /// Another ELF `e_machine` value
+    Other(u16),
+}
+
+impl From<u16> for Architecture {
+    fn from(machine: u16) -> Self {
+        match machine {
+            3 => Architecture::X86,
+            40 => Architecture::Arm,
+            62 => Architecture::X86_64,
+            183 => Architecture::Aarch64,
+            243 => Architecture::RiscV,
+            other => Architecture::Other(other),
+        }
+    }
+}
+
+/// A distribution's identity, read from its `os-release` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OsRelease {
+    /// The fields in the order they appear in the file, with quoting
+    /// removed
+    pub fields: Vec<(String, String)>,
+}
+
+impl OsRelease {
+    /// Parses the `KEY=value` lines of an `os-release` file, skipping
+    /// comments and lines that aren't assignments.
+    pub fn parse(contents: &str) -> Self {
+        let fields = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), unquote(value.trim())))
+            .collect();
+        OsRelease { fields }
+    }
+
+    /// Looks up a field.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The lowercase identifier of the distribution, such as `ubuntu`,
+    /// which is `linux` if the file doesn't give one.
+    pub fn id(&self) -> &str {
+        self.get("ID").unwrap_or("linux")
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.get("NAME")
+    }
+
+    pub fn version_id(&self) -> Option<&str> {
+        self.get("VERSION_ID")
+    }
+
+    pub fn pretty_name(&self) -> Option<&str> {
+        self.get("PRETTY_NAME")
+    }
+}
+
+/// Removes shell quoting from an `os-release` value.
+fn unquote(value: &str) -> String {
+    let quoted = |quote| value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote);
+    if quoted('\'') {
+        return value[1..value.len() - 1].to_string();
+    }
+    let value = if quoted('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    };
+    let mut unquoted = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// What [`inspect`] found in a tarball.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarballReport {
+    pub compression: Compression,
+    pub os_release: OsRelease,
+    /// The architectures of the ELF binaries in `bin/`, `sbin/`, `usr/bin/`
+    /// and `usr/sbin/`, by the first binary found for each.
+    pub architectures: BTreeMap<Architecture, String>,
+    /// The number of entries in the tarball.
+    pub entries: u64,
+}
+
+impl TarballReport {
+    /// The architecture the distribution is built for, or `None` if it has
+    /// no ELF binaries.
+    ///
+    /// A root filesystem whose binaries are built for more than one
+    /// architecture fails with [`WslErrorKind::MixedArchitectures`], naming
+    /// a binary of each.
+    pub fn architecture(&self) -> Result<Option<Architecture>, WslError> {
+        if self.architectures.len() > 1 {
+            let found: Vec<_> = self
+                .architectures
+                .iter()
+                .map(|(architecture, path)| format!("{:?} (/{})", architecture, path))
+                .collect();
+            return Err(WslError::precondition(
+                WslErrorKind::MixedArchitectures,
+                WSL_E_IMPORT_FAILED,
+                format!(
+                    "The tarball's binaries are built for more than one architecture: {}",
+                    found.join(", ")
+                ),
+            ));
+        }
+        Ok(self.architectures.keys().next().copied())
+    }
+}
+
+/// The `os-release` files a distribution may have, in order of precedence.
+const OS_RELEASE: [&str; 2] = ["etc/os-release", "usr/lib/os-release"];
+
+/// The directories whose ELF headers are read.
+const BINARY_DIRS: [&str; 4] = ["bin/", "sbin/", "usr/bin/", "usr/sbin/"];
+
+/// Reads a tarball from `source` to check that it is a plausible root
+/// filesystem, without registering it.
+///
+/// The tarball may be compressed with gzip, xz or zstd. It must contain
+/// `/bin/sh` and an `os-release` file, either of which may be a symbolic
+/// link, as in distributions where `/bin` links to `/usr/bin`.
+///
+/// A stream that can't be decompressed or isn't a tarball fails with
+/// [`WslErrorKind::InvalidTarball`], and a tarball that doesn't look like a
+/// root filesystem with [`WslErrorKind::NotALinuxDistribution`]. Every
+/// architecture its binaries are built for is reported, so that
+/// [`TarballReport::architecture`] can reject a mix of them.
+pub fn inspect(source: impl Read) -> Result<TarballReport, WslError> {
+    let mut source = source;
+    let mut magic = Vec::with_capacity(6);
+    (&mut source).take(6).read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    let source = Cursor::new(magic).chain(source);
+
+    let invalid = |e: &dyn std::fmt::Display| {
+        WslError::precondition(
+            WslErrorKind::InvalidTarball,
+            WSL_E_IMPORT_FAILED,
+            format!("The tarball could not be read: {}", e),
+        )
+    };
+    let scan = match compression {
+        Compression::None => Scan::archive(source),
+        Compression::Gzip => Scan::archive(flate2::read::MultiGzDecoder::new(source)),
+        Compression::Xz => Scan::archive(lzma_rust2::XzReader::new(source, true)),
+        Compression::Zstd => {
+            Scan::archive(ruzstd::decoding::StreamingDecoder::new(source).map_err(|e| invalid(&e))?)
+        }
+    }
+    .map_err(|e| invalid(&e))?;
+    if scan.entries == 0 {
+        return Err(invalid(&"it has no entries"));
+    }
+
+    let not_a_distro = |missing: &str| {
+        WslError::precondition(
+            WslErrorKind::NotALinuxDistribution,
+            WSL_E_NOT_A_LINUX_DISTRO,
+            format!(
+                "The tarball is not a root filesystem: it has no {}",
+                missing
+            ),
+        )
+    };
+    if !scan.exists("bin/sh") {
+        return Err(not_a_distro("/bin/sh"));
+    }
+    let os_release = OS_RELEASE
+        .iter()
+        .find_map(|path| scan.os_release.get(&scan.resolve(path)))
+        .ok_or_else(|| not_a_distro("/etc/os-release"))?;
+
+    Ok(TarballReport {
+        compression,
+        os_release: OsRelease::parse(os_release),
+        architectures: scan.architectures,
+        entries: scan.entries,
+    })
+}
+
+/// Inspects a tarball like [`inspect`], then rewinds `file` so that it can
+/// be passed on to [`crate::Wsl2::register_distribution`].
+pub fn inspect_file(file: &mut File) -> Result<TarballReport, WslError> {
+    file.seek(SeekFrom::Start(0))?;
+    let report = inspect(&mut *file);
+    file.seek(SeekFrom::Start(0))?;
+    report
+}
+
+/// What has been seen of a tarball so far.
+#[derive(Default)]
+struct Scan {
+    entries: u64,
+    /// Every path in the tarball, relative to its root
+    paths: HashSet<String>,
+    /// Symbolic links, by path, to their targets
+    symlinks: HashMap<String, String>,
+    /// The contents of regular `os-release` files, by path
+    os_release: HashMap<String, String>,
+    /// The architectures of the ELF binaries in [`BINARY_DIRS`], by the
+    /// first binary found for each
+    architectures: BTreeMap<Architecture, String>,
+}
+
+impl Scan {
+    /// Reads every entry of a decompressed tarball.
+    fn archive(tarball: impl Read) -> std::io::Result<Self> {
+        let mut scan = Scan::default();
+        for entry in tar::Archive::new(tarball).entries()? {
+            scan.add(entry?)?;
+        }
+        Ok(scan)
+    }
+
+    fn add(&mut self, mut entry: tar::Entry<'_, impl Read>) -> std::io::Result<()> {
+        self.entries += 1;
+        let path = normalize(&entry.path()?);
+        let kind = entry.header().entry_type();
+
+        if kind.is_symlink() {
+            if let Some(target) = entry.link_name()? {
+                let target = target.to_string_lossy();
+                let target = if target.starts_with('/') {
+                    normalize(Path::new(&*target))
+                } else {
+                    let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                    normalize(&Path::new(parent).join(&*target))
+                };
+                self.symlinks.insert(path.clone(), target);
+            }
+        } else if kind.is_file() {
+            if OS_RELEASE.contains(&path.as_str()) {
+                let mut contents = String::new();
+                entry
+                    .by_ref()
+                    .take(64 * 1024)
+                    .read_to_string(&mut contents)?;
+                self.os_release.insert(path.clone(), contents);
+            } else if BINARY_DIRS.iter().any(|dir| path.starts_with(dir)) {
+                if let Some(architecture) = elf_architecture(&mut entry)? {
+                    self.architectures
+                        .entry(architecture)
+                        .or_insert_with(|| path.clone());
+                }
+            }
+        }
+
+        self.paths.insert(path);
+        Ok(())
+    }
+
+    /// Follows the symbolic links along `path`, giving up after a few to
+    /// avoid loops.
+    fn resolve(&self, path: &str) -> String {
+        let mut path = path.to_string();
+        'follow: for _ in 0..16 {
+            let mut prefix = String::new();
+            for component in path.split('/') {
+                if !prefix.is_empty() {
+                    prefix.push('/');
+                }
+                prefix.push_str(component);
+                if let Some(target) = self.symlinks.get(&prefix) {
+                    path = format!("{}{}", target, &path[prefix.len()..]);
+                    continue 'follow;
+                }
+            }
+            break;
+        }
+        path
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.paths.contains(&self.resolve(path))
+    }
+}
+
+/// Turns a path from a tarball into one relative to its root, without `.`,
+/// `..` or a leading `/`.
+fn normalize(path: &Path) -> String {
+    let mut components: Vec<_> = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy()),
+            Component::ParentDir => _ = components.pop(),
+            _ => {}
+        }
+    }
+    components.join("/")
+}
+
+/// Reads the machine from an ELF header, or `None` if `file` isn't ELF.
+fn elf_architecture(file: &mut impl Read) -> std::io::Result<Option<Architecture>> {
+    let mut header = Vec::with_capacity(20);
+    file.take(20).read_to_end(&mut header)?;
+    if header.len() < 20 || !header.starts_with(b"\x7fELF") {
+        return Ok(None);
+    }
+    let machine = [header[18], header[19]];
+    // EI_DATA gives the byte order of the rest of the header
+    let machine = match header[5] {
+        2 => u16::from_be_bytes(machine),
+        _ => u16::from_le_bytes(machine),
+    };
+    Ok(Some(machine.into()))
+}
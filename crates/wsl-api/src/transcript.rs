@@ -0,0 +1,1168 @@
+//! Recording and replay of the calls [`Wsl2`](crate::Wsl2) makes to the WSL
+//! service.
+//!
+//! Wrapping the production backend in a [`RecordingBackend`] captures every
+//! `ILxssUserSession` call, its arguments, the returned HRESULT and any error
+//! message from the service as a JSON [`Transcript`]. A [`ReplayBackend`]
+//! serves a transcript back, so a failure seen on one Windows build can be
+//! reproduced deterministically anywhere, including on Linux.
+//!
+//! ```no_run
+//! use wsl_api::transcript::RecordingBackend;
+//! use wsl_api::{ComBackend, Wsl2};
+//!
+//! let recording = RecordingBackend::with_file(ComBackend::new()?, "wsl-transcript.json");
+//! let wsl = Wsl2::with_backend(recording.clone());
+//! wsl.enumerate_distributions()?;
+//! recording.finish()?;
+//! # Ok::<(), wsl_api::WslError>(())
+//! ```
+//!
+//! Handles are not recorded: replayed imports and exports neither read nor
+//! write their files. A launched process' output and exit code are recorded
+//! once it exits, and a replayed process writes the same output and exits
+//! with the same code. A process that was still running when the transcript
+//! was written can't be replayed.
+//!
+//! Only the calls to the service are replayed. [`Wsl2`](crate::Wsl2) still
+//! checks its own preconditions, so replaying a session that moved a
+//! distribution, imported a `.vhdx` in place or attached a disk needs the
+//! same paths to exist, and relative paths are made absolute against the
+//! current directory before they are compared with the transcript.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use windows::core::HRESULT;
+use windows::Win32::Foundation::HANDLE;
+
+use crate::fake::FakeProcess;
+use crate::{
+    AttachFlags, CreateInstanceFlags, DiskFailure, Distribution, DistributionConfiguration,
+    DistributionFlags, ExportFlags, MountOptions, RegisterOptions, Version, WslBackend, WslError,
+    WslProcess,
+};
+
+/// A call to the WSL service and its arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "call")]
+pub enum Call {
+    Shutdown {
+        force: bool,
+    },
+    GetDefaultDistribution,
+    SetDefaultDistribution {
+        distro_guid: Uuid,
+    },
+    GetDistributionId {
+        name: String,
+        flags: u32,
+    },
+    CreateInstance {
+        distro_guid: Uuid,
+        flags: u32,
+    },
+    TerminateDistribution {
+        distro_guid: Uuid,
+    },
+    UnregisterDistribution {
+        distro_guid: Uuid,
+    },
+    MoveDistribution {
+        distro_guid: Uuid,
+        location: PathBuf,
+    },
+    ResizeDistribution {
+        distro_guid: Uuid,
+        new_size: u64,
+    },
+    SetSparse {
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    },
+    CreateLxProcess {
+        distro_guid: Uuid,
+        command: String,
+        args: Vec<String>,
+        cwd: Option<String>,
+        username: String,
+    },
+    EnumerateDistributions,
+    GetDistributionConfiguration {
+        distro_guid: Uuid,
+    },
+    ConfigureDistribution {
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: u32,
+    },
+    ExportDistribution {
+        distro_guid: Uuid,
+        flags: u32,
+    },
+    ExportDistributionPipe {
+        distro_guid: Uuid,
+        flags: u32,
+    },
+    RegisterDistribution {
+        name: String,
+        version: Version,
+        flags: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_directory: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vhd_size: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        package_family_name: Option<String>,
+    },
+    RegisterDistributionPipe {
+        name: String,
+        version: Version,
+        flags: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_directory: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vhd_size: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        package_family_name: Option<String>,
+    },
+    ImportDistributionInplace {
+        name: String,
+        vhd_path: PathBuf,
+    },
+    SetVersion {
+        distro_guid: Uuid,
+        version: Version,
+    },
+    AttachDisk {
+        disk: PathBuf,
+        flags: u32,
+    },
+    MountDisk {
+        disk: PathBuf,
+        flags: u32,
+        partition_index: u32,
+        name: Option<String>,
+        filesystem_type: Option<String>,
+        options: Option<String>,
+    },
+    DetachDisk {
+        disk: Option<PathBuf>,
+    },
+}
+
+impl Call {
+    fn register_distribution(name: &str, version: Version, options: &RegisterOptions) -> Self {
+        Call::RegisterDistribution {
+            name: name.to_string(),
+            version,
+            flags: options.flags.bits(),
+            target_directory: options.target_directory.clone(),
+            vhd_size: options.vhd_size.map(|size| size.bytes()),
+            package_family_name: options.package_family_name.clone(),
+        }
+    }
+
+    fn mount_disk(disk: &Path, options: &MountOptions) -> Self {
+        Call::MountDisk {
+            disk: disk.to_path_buf(),
+            flags: options.flags.bits(),
+            partition_index: options.partition.unwrap_or(0),
+            name: options.name.clone(),
+            filesystem_type: options.filesystem_type.clone(),
+            options: options.options.clone(),
+        }
+    }
+
+    fn register_distribution_pipe(name: &str, version: Version, options: &RegisterOptions) -> Self {
+        Call::RegisterDistributionPipe {
+            name: name.to_string(),
+            version,
+            flags: options.flags.bits(),
+            target_directory: options.target_directory.clone(),
+            vhd_size: options.vhd_size.map(|size| size.bytes()),
+            package_family_name: options.package_family_name.clone(),
+        }
+    }
+}
+
+/// The value returned by a successful call, for calls that return one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Returned {
+    Guid(Uuid),
+    Distributions(Vec<Distribution>),
+    Configuration(DistributionConfiguration),
+    Registered {
+        guid: Uuid,
+        installed_name: String,
+    },
+    MountName(String),
+    /// What a launched process wrote, as text, and the code it exited with
+    Process {
+        exit_code: u32,
+        #[serde(default)]
+        stdout: String,
+        #[serde(default)]
+        stderr: String,
+    },
+}
+
+/// A single recorded call and its outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    #[serde(flatten)]
+    pub call: Call,
+    #[serde(with = "hresult_hex")]
+    pub hresult: HRESULT,
+    /// The `LXSS_ERROR_INFO` message returned with a failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub returned: Option<Returned>,
+    /// Where a failed disk operation stopped inside the WSL2 VM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_failure: Option<DiskFailure>,
+    /// The group of calls this one was in flight with, if any. Calls in the
+    /// same group may be replayed in any order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrent: Option<u64>,
+}
+
+/// An ordered record of the calls made to the WSL service.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Reads a transcript from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Writes the transcript to a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// HRESULTs are stored as hex strings (eg: `"0x80040302"`) so that
+/// transcripts can be matched against the `WSL_E_*` constants by eye.
+mod hresult_hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use windows::core::HRESULT;
+
+    pub fn serialize<S: Serializer>(hresult: &HRESULT, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:08x}", hresult.0))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HRESULT, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let hex = s
+            .strip_prefix("0x")
+            .ok_or_else(|| D::Error::custom(format!("expected a hex HRESULT, got {s:?}")))?;
+        let value = u32::from_str_radix(hex, 16).map_err(D::Error::custom)?;
+        Ok(HRESULT(value as i32))
+    }
+}
+
+/// A [`WslBackend`] that forwards every call to another backend and records
+/// it in a [`Transcript`].
+///
+/// Clones share the same transcript, so the caller can keep one clone to
+/// finish the recording while [`Wsl2`](crate::Wsl2) owns another.
+pub struct RecordingBackend<B> {
+    inner: Arc<B>,
+    recording: Arc<Mutex<Recording>>,
+    path: Option<PathBuf>,
+}
+
+/// The calls recorded so far, and which of them were made concurrently.
+#[derive(Default)]
+struct Recording {
+    entries: Vec<TranscriptEntry>,
+    /// The group each entry was made in. Calls made while another call was
+    /// in flight share its group.
+    groups: Vec<u64>,
+    /// The number of calls in flight
+    in_flight: usize,
+    /// The group of the calls in flight
+    group: u64,
+}
+
+impl<B> Clone for RecordingBackend<B> {
+    fn clone(&self) -> Self {
+        RecordingBackend {
+            inner: self.inner.clone(),
+            recording: self.recording.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<B: WslBackend> RecordingBackend<B> {
+    /// Records calls in memory. See [`RecordingBackend::transcript`].
+    pub fn new(inner: B) -> Self {
+        RecordingBackend {
+            inner: Arc::new(inner),
+            recording: Arc::default(),
+            path: None,
+        }
+    }
+
+    /// Records calls, to be written to `path` by
+    /// [`RecordingBackend::finish`].
+    pub fn with_file(inner: B, path: impl Into<PathBuf>) -> Self {
+        RecordingBackend {
+            path: Some(path.into()),
+            ..Self::new(inner)
+        }
+    }
+
+    /// Returns a copy of the calls recorded so far. Calls that were in
+    /// flight at the same time are marked with the same
+    /// [`TranscriptEntry::concurrent`] group.
+    pub fn transcript(&self) -> Transcript {
+        let recording = self.lock();
+        let mut sizes = HashMap::<u64, usize>::new();
+        for group in &recording.groups {
+            *sizes.entry(*group).or_default() += 1;
+        }
+        let mut entries = recording.entries.clone();
+        for (entry, group) in entries.iter_mut().zip(&recording.groups) {
+            if sizes[group] > 1 {
+                entry.concurrent = Some(*group);
+            }
+        }
+        Transcript { entries }
+    }
+
+    /// Writes the calls recorded so far to the file given to
+    /// [`RecordingBackend::with_file`], if any, and returns them. Recording
+    /// carries on, so this can also be called at checkpoints.
+    pub fn finish(&self) -> std::io::Result<Transcript> {
+        let transcript = self.transcript();
+        if let Some(path) = &self.path {
+            transcript.save(path)?;
+        }
+        Ok(transcript)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Recording> {
+        self.recording.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record<T>(
+        &self,
+        call: Call,
+        f: impl FnOnce(&B) -> Result<T, WslError>,
+        returned: impl FnOnce(&T) -> Option<Returned>,
+    ) -> Result<T, WslError> {
+        self.record_entry(call, f, returned).0
+    }
+
+    /// Makes a call and records it, returning the result and the index of
+    /// its entry.
+    fn record_entry<T>(
+        &self,
+        call: Call,
+        f: impl FnOnce(&B) -> Result<T, WslError>,
+        returned: impl FnOnce(&T) -> Option<Returned>,
+    ) -> (Result<T, WslError>, usize) {
+        let group = {
+            let mut recording = self.lock();
+            if recording.in_flight == 0 {
+                recording.group += 1;
+            }
+            recording.in_flight += 1;
+            recording.group
+        };
+
+        let result = f(&self.inner);
+        let entry = match &result {
+            Ok(value) => TranscriptEntry {
+                call,
+                hresult: HRESULT(0),
+                message: None,
+                returned: returned(value),
+                disk_failure: None,
+                concurrent: None,
+            },
+            Err(e) => TranscriptEntry {
+                call,
+                hresult: e.hresult(),
+                message: e.message(),
+                returned: None,
+                disk_failure: e.disk_failure(),
+                concurrent: None,
+            },
+        };
+
+        let mut recording = self.lock();
+        recording.in_flight -= 1;
+        recording.entries.push(entry);
+        recording.groups.push(group);
+        (result, recording.entries.len() - 1)
+    }
+}
+
+/// Plays the part of a launched process in the [`WslProcess`] returned to
+/// the caller, passing its stdio through and recording its output and exit
+/// code in the entry at `index` once it exits.
+fn proxy_process(
+    mut process: WslProcess,
+    recording: Arc<Mutex<Recording>>,
+    index: usize,
+) -> impl FnOnce(FakeProcess) -> Option<u32> + Send + 'static {
+    let (stdin, stdout, stderr) = (
+        process.stdin.take(),
+        process.stdout.take(),
+        process.stderr.take(),
+    );
+    move |proxy| {
+        // The caller may never close stdin, so it is forwarded on a thread
+        // that isn't waited for
+        if let Some(mut stdin) = stdin {
+            let mut proxy_stdin = proxy.stdin;
+            std::thread::spawn(move || _ = std::io::copy(&mut proxy_stdin, &mut stdin));
+        }
+        let (stdout, stderr, status) = std::thread::scope(|scope| {
+            let stdout = scope.spawn(move || tee(stdout, proxy.stdout));
+            let stderr = scope.spawn(move || tee(stderr, proxy.stderr));
+            let status = process.wait();
+            (stdout.join(), stderr.join(), status)
+        });
+        // A process that can't be waited for is left unrecorded, and the
+        // caller's wait fails too
+        let exit_code = status.ok()?.code()? as u32;
+        recording.lock().unwrap_or_else(|e| e.into_inner()).entries[index].returned =
+            Some(Returned::Process {
+                exit_code,
+                stdout: stdout.unwrap_or_default(),
+                stderr: stderr.unwrap_or_default(),
+            });
+        Some(exit_code)
+    }
+}
+
+/// Copies `source` to `sink` until it ends, returning what was copied as
+/// text. Copying carries on if `sink` is closed, so that `source` never
+/// blocks on a full pipe.
+fn tee(source: Option<impl Read>, mut sink: impl Write) -> String {
+    let mut captured = vec![];
+    if let Some(mut source) = source {
+        let mut buf = [0; 8192];
+        loop {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            captured.extend_from_slice(&buf[..n]);
+            _ = sink.write_all(&buf[..n]);
+        }
+    }
+    String::from_utf8_lossy(&captured).into_owned()
+}
+
+impl<B: WslBackend> WslBackend for RecordingBackend<B> {
+    fn shutdown(&self, force: bool) -> Result<(), WslError> {
+        self.record(
+            Call::Shutdown { force },
+            |inner| inner.shutdown(force),
+            |_| None,
+        )
+    }
+
+    fn get_default_distribution(&self) -> Result<Uuid, WslError> {
+        self.record(
+            Call::GetDefaultDistribution,
+            |inner| inner.get_default_distribution(),
+            |guid| Some(Returned::Guid(*guid)),
+        )
+    }
+
+    fn set_default_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.record(
+            Call::SetDefaultDistribution { distro_guid },
+            |inner| inner.set_default_distribution(distro_guid),
+            |_| None,
+        )
+    }
+
+    fn get_distribution_id(&self, name: &str, flags: u32) -> Result<Uuid, WslError> {
+        let call = Call::GetDistributionId {
+            name: name.to_string(),
+            flags,
+        };
+        self.record(
+            call,
+            |inner| inner.get_distribution_id(name, flags),
+            |guid| Some(Returned::Guid(*guid)),
+        )
+    }
+
+    fn create_instance(
+        &self,
+        distro_guid: Uuid,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError> {
+        let call = Call::CreateInstance {
+            distro_guid,
+            flags: flags.bits(),
+        };
+        self.record(
+            call,
+            |inner| inner.create_instance(distro_guid, flags),
+            |_| None,
+        )
+    }
+
+    fn terminate_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.record(
+            Call::TerminateDistribution { distro_guid },
+            |inner| inner.terminate_distribution(distro_guid),
+            |_| None,
+        )
+    }
+
+    fn unregister_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.record(
+            Call::UnregisterDistribution { distro_guid },
+            |inner| inner.unregister_distribution(distro_guid),
+            |_| None,
+        )
+    }
+
+    fn resize_distribution(
+        &self,
+        distro_guid: Uuid,
+        output_handle: HANDLE,
+        new_size: u64,
+    ) -> Result<(), WslError> {
+        let call = Call::ResizeDistribution {
+            distro_guid,
+            new_size,
+        };
+        self.record(
+            call,
+            |inner| inner.resize_distribution(distro_guid, output_handle, new_size),
+            |_| None,
+        )
+    }
+
+    fn set_sparse(
+        &self,
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    ) -> Result<(), WslError> {
+        let call = Call::SetSparse {
+            distro_guid,
+            sparse,
+            allow_unsafe,
+        };
+        self.record(
+            call,
+            |inner| inner.set_sparse(distro_guid, sparse, allow_unsafe),
+            |_| None,
+        )
+    }
+
+    fn move_distribution(&self, distro_guid: Uuid, location: &Path) -> Result<(), WslError> {
+        let call = Call::MoveDistribution {
+            distro_guid,
+            location: location.to_path_buf(),
+        };
+        self.record(
+            call,
+            |inner| inner.move_distribution(distro_guid, location),
+            |_| None,
+        )
+    }
+
+    fn launch(
+        &self,
+        distro_guid: Uuid,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError> {
+        let call = Call::CreateLxProcess {
+            distro_guid,
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: cwd.map(str::to_string),
+            username: username.to_string(),
+        };
+        // The process' output and exit code are filled in once it exits
+        let (result, index) = self.record_entry(
+            call,
+            |inner| inner.launch(distro_guid, command, args, cwd, username),
+            |_| None,
+        );
+        let proxy = proxy_process(result?, self.recording.clone(), index);
+        Ok(crate::fake::spawn_process(
+            distro_guid,
+            command,
+            args,
+            cwd,
+            username,
+            proxy,
+        ))
+    }
+
+    fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
+        self.record(
+            Call::EnumerateDistributions,
+            |inner| inner.enumerate_distributions(),
+            |distros| Some(Returned::Distributions(distros.clone())),
+        )
+    }
+
+    fn get_distribution_configuration(
+        &self,
+        distro_guid: Uuid,
+    ) -> Result<DistributionConfiguration, WslError> {
+        self.record(
+            Call::GetDistributionConfiguration { distro_guid },
+            |inner| inner.get_distribution_configuration(distro_guid),
+            |configuration| Some(Returned::Configuration(configuration.clone())),
+        )
+    }
+
+    fn configure_distribution(
+        &self,
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: DistributionFlags,
+    ) -> Result<(), WslError> {
+        let call = Call::ConfigureDistribution {
+            distro_guid,
+            default_uid,
+            flags: flags.bits(),
+        };
+        self.record(
+            call,
+            |inner| inner.configure_distribution(distro_guid, default_uid, flags),
+            |_| None,
+        )
+    }
+
+    fn export_distribution(
+        &self,
+        distro_guid: Uuid,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        let call = Call::ExportDistribution {
+            distro_guid,
+            flags: flags.bits(),
+        };
+        self.record(
+            call,
+            |inner| inner.export_distribution(distro_guid, file_handle, stderr_handle, flags),
+            |_| None,
+        )
+    }
+
+    fn export_distribution_pipe(
+        &self,
+        distro_guid: Uuid,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        let call = Call::ExportDistributionPipe {
+            distro_guid,
+            flags: flags.bits(),
+        };
+        self.record(
+            call,
+            |inner| inner.export_distribution_pipe(distro_guid, pipe_handle, stderr_handle, flags),
+            |_| None,
+        )
+    }
+
+    fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        file_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        self.record(
+            Call::register_distribution(name, version, options),
+            |inner| inner.register_distribution(name, version, file_handle, stderr_handle, options),
+            |(guid, installed_name)| {
+                Some(Returned::Registered {
+                    guid: *guid,
+                    installed_name: installed_name.clone(),
+                })
+            },
+        )
+    }
+
+    fn register_distribution_pipe(
+        &self,
+        name: &str,
+        version: Version,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        self.record(
+            Call::register_distribution_pipe(name, version, options),
+            |inner| {
+                inner.register_distribution_pipe(name, version, pipe_handle, stderr_handle, options)
+            },
+            |(guid, installed_name)| {
+                Some(Returned::Registered {
+                    guid: *guid,
+                    installed_name: installed_name.clone(),
+                })
+            },
+        )
+    }
+
+    fn import_distribution_in_place(&self, name: &str, vhd_path: &Path) -> Result<Uuid, WslError> {
+        let call = Call::ImportDistributionInplace {
+            name: name.to_string(),
+            vhd_path: vhd_path.to_path_buf(),
+        };
+        self.record(
+            call,
+            |inner| inner.import_distribution_in_place(name, vhd_path),
+            |guid| Some(Returned::Guid(*guid)),
+        )
+    }
+
+    fn set_version(
+        &self,
+        distribution: Uuid,
+        version: Version,
+        stderr_handle: HANDLE,
+    ) -> Result<(), WslError> {
+        let call = Call::SetVersion {
+            distro_guid: distribution,
+            version,
+        };
+        self.record(
+            call,
+            |inner| inner.set_version(distribution, version, stderr_handle),
+            |_| None,
+        )
+    }
+
+    fn attach_disk(&self, disk: &Path, flags: AttachFlags) -> Result<(), WslError> {
+        let call = Call::AttachDisk {
+            disk: disk.to_path_buf(),
+            flags: flags.bits(),
+        };
+        self.record(call, |inner| inner.attach_disk(disk, flags), |_| None)
+    }
+
+    fn mount_disk(&self, disk: &Path, options: &MountOptions) -> Result<String, WslError> {
+        self.record(
+            Call::mount_disk(disk, options),
+            |inner| inner.mount_disk(disk, options),
+            |name| Some(Returned::MountName(name.clone())),
+        )
+    }
+
+    fn detach_disk(&self, disk: Option<&Path>) -> Result<(), WslError> {
+        let call = Call::DetachDisk {
+            disk: disk.map(Path::to_path_buf),
+        };
+        self.record(call, |inner| inner.detach_disk(disk), |_| None)
+    }
+}
+
+/// A [`WslBackend`] that answers calls from a [`Transcript`].
+///
+/// Calls must arrive in the order they were recorded. The only exception
+/// is a [`TranscriptEntry::concurrent`] group, such as the export and
+/// registration of
+/// [`Wsl2::clone_distribution`](crate::Wsl2::clone_distribution), whose
+/// calls may arrive in any order once the group is reached. A call that
+/// doesn't match panics, as it means the code under test has diverged from
+/// the recorded session.
+///
+/// Clones share the same transcript, so the caller can keep one clone to
+/// [`finish`](ReplayBackend::finish) the replay while
+/// [`Wsl2`](crate::Wsl2) owns another. If the last clone is dropped without
+/// finishing while recorded calls remain, it panics.
+#[derive(Clone)]
+pub struct ReplayBackend {
+    state: Arc<ReplayState>,
+}
+
+struct ReplayState {
+    entries: Mutex<VecDeque<TranscriptEntry>>,
+    finished: AtomicBool,
+}
+
+impl Drop for ReplayState {
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::SeqCst) || std::thread::panicking() {
+            return;
+        }
+        let entries = self.entries.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(next) = entries.front() {
+            panic!("{}", unreplayed(entries.len(), &next.call));
+        }
+    }
+}
+
+fn unreplayed(count: usize, next: &Call) -> String {
+    format!("{count} recorded calls were not replayed, starting with {next:?}")
+}
+
+impl ReplayBackend {
+    /// Replays the given transcript.
+    pub fn new(transcript: Transcript) -> Self {
+        ReplayBackend {
+            state: Arc::new(ReplayState {
+                entries: Mutex::new(transcript.entries.into()),
+                finished: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Replays a transcript from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(Transcript::load(path)?))
+    }
+
+    /// The number of recorded calls that have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Ends the replay, failing if any recorded calls have not been
+    /// replayed.
+    pub fn finish(&self) -> std::io::Result<()> {
+        self.state.finished.store(true, Ordering::SeqCst);
+        let entries = self.lock();
+        match entries.front() {
+            Some(next) => Err(std::io::Error::other(unreplayed(entries.len(), &next.call))),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<TranscriptEntry>> {
+        self.state.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn replay(&self, call: Call) -> Result<Option<Returned>, WslError> {
+        let entry = {
+            let mut entries = self.lock();
+            let index = match entries.front().and_then(|next| next.concurrent) {
+                Some(group) => entries
+                    .iter()
+                    .take_while(|entry| entry.concurrent == Some(group))
+                    .position(|entry| entry.call == call),
+                None => entries.front().filter(|next| next.call == call).map(|_| 0),
+            };
+            match index {
+                Some(index) => entries.remove(index).unwrap(),
+                None => panic!(
+                    "Call does not match the transcript: got {call:?}, expected {:?}",
+                    entries.front().map(|entry| &entry.call)
+                ),
+            }
+        };
+
+        if entry.hresult.is_err() {
+            Err(match (entry.disk_failure, entry.message) {
+                (Some(failure), message) => WslError::disk(entry.hresult, failure, message),
+                (None, Some(message)) => WslError::with_message(entry.hresult, message),
+                (None, None) => entry.hresult.into(),
+            })
+        } else {
+            Ok(entry.returned)
+        }
+    }
+}
+
+impl WslBackend for ReplayBackend {
+    fn shutdown(&self, force: bool) -> Result<(), WslError> {
+        self.replay(Call::Shutdown { force })?;
+        Ok(())
+    }
+
+    fn get_default_distribution(&self) -> Result<Uuid, WslError> {
+        match self.replay(Call::GetDefaultDistribution)? {
+            Some(Returned::Guid(guid)) => Ok(guid),
+            other => panic!("Transcript has {other:?} for GetDefaultDistribution"),
+        }
+    }
+
+    fn set_default_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.replay(Call::SetDefaultDistribution { distro_guid })?;
+        Ok(())
+    }
+
+    fn get_distribution_id(&self, name: &str, flags: u32) -> Result<Uuid, WslError> {
+        let call = Call::GetDistributionId {
+            name: name.to_string(),
+            flags,
+        };
+        match self.replay(call)? {
+            Some(Returned::Guid(guid)) => Ok(guid),
+            other => panic!("Transcript has {other:?} for GetDistributionId"),
+        }
+    }
+
+    fn create_instance(
+        &self,
+        distro_guid: Uuid,
+        flags: CreateInstanceFlags,
+    ) -> Result<(), WslError> {
+        self.replay(Call::CreateInstance {
+            distro_guid,
+            flags: flags.bits(),
+        })?;
+        Ok(())
+    }
+
+    fn terminate_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.replay(Call::TerminateDistribution { distro_guid })?;
+        Ok(())
+    }
+
+    fn unregister_distribution(&self, distro_guid: Uuid) -> Result<(), WslError> {
+        self.replay(Call::UnregisterDistribution { distro_guid })?;
+        Ok(())
+    }
+
+    fn resize_distribution(
+        &self,
+        distro_guid: Uuid,
+        _output_handle: HANDLE,
+        new_size: u64,
+    ) -> Result<(), WslError> {
+        self.replay(Call::ResizeDistribution {
+            distro_guid,
+            new_size,
+        })?;
+        Ok(())
+    }
+
+    fn set_sparse(
+        &self,
+        distro_guid: Uuid,
+        sparse: bool,
+        allow_unsafe: bool,
+    ) -> Result<(), WslError> {
+        self.replay(Call::SetSparse {
+            distro_guid,
+            sparse,
+            allow_unsafe,
+        })?;
+        Ok(())
+    }
+
+    fn move_distribution(&self, distro_guid: Uuid, location: &Path) -> Result<(), WslError> {
+        self.replay(Call::MoveDistribution {
+            distro_guid,
+            location: location.to_path_buf(),
+        })?;
+        Ok(())
+    }
+
+    fn launch(
+        &self,
+        distro_guid: Uuid,
+        command: &str,
+        args: &[&str],
+        cwd: Option<&str>,
+        username: &str,
+    ) -> Result<WslProcess, WslError> {
+        let call = Call::CreateLxProcess {
+            distro_guid,
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: cwd.map(str::to_string),
+            username: username.to_string(),
+        };
+        let (exit_code, stdout, stderr) = match self.replay(call)? {
+            Some(Returned::Process {
+                exit_code,
+                stdout,
+                stderr,
+            }) => (exit_code, stdout, stderr),
+            other => panic!("Transcript has {other:?} for CreateLxProcess"),
+        };
+        Ok(crate::fake::spawn_process(
+            distro_guid,
+            command,
+            args,
+            cwd,
+            username,
+            move |mut process| {
+                // Written side by side, so that the caller can read them in
+                // either order
+                std::thread::scope(|scope| {
+                    scope.spawn(|| _ = process.stdout.write_all(stdout.as_bytes()));
+                    scope.spawn(|| _ = process.stderr.write_all(stderr.as_bytes()));
+                });
+                Some(exit_code)
+            },
+        ))
+    }
+
+    fn enumerate_distributions(&self) -> Result<Vec<Distribution>, WslError> {
+        match self.replay(Call::EnumerateDistributions)? {
+            Some(Returned::Distributions(distros)) => Ok(distros),
+            other => panic!("Transcript has {other:?} for EnumerateDistributions"),
+        }
+    }
+
+    fn get_distribution_configuration(
+        &self,
+        distro_guid: Uuid,
+    ) -> Result<DistributionConfiguration, WslError> {
+        match self.replay(Call::GetDistributionConfiguration { distro_guid })? {
+            Some(Returned::Configuration(configuration)) => Ok(configuration),
+            other => panic!("Transcript has {other:?} for GetDistributionConfiguration"),
+        }
+    }
+
+    fn configure_distribution(
+        &self,
+        distro_guid: Uuid,
+        default_uid: u32,
+        flags: DistributionFlags,
+    ) -> Result<(), WslError> {
+        self.replay(Call::ConfigureDistribution {
+            distro_guid,
+            default_uid,
+            flags: flags.bits(),
+        })?;
+        Ok(())
+    }
+
+    fn export_distribution(
+        &self,
+        distro_guid: Uuid,
+        _file_handle: HANDLE,
+        _stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        self.replay(Call::ExportDistribution {
+            distro_guid,
+            flags: flags.bits(),
+        })?;
+        Ok(())
+    }
+
+    fn export_distribution_pipe(
+        &self,
+        distro_guid: Uuid,
+        _pipe_handle: HANDLE,
+        _stderr_handle: HANDLE,
+        flags: ExportFlags,
+    ) -> Result<(), WslError> {
+        self.replay(Call::ExportDistributionPipe {
+            distro_guid,
+            flags: flags.bits(),
+        })?;
+        Ok(())
+    }
+
+    fn register_distribution(
+        &self,
+        name: &str,
+        version: Version,
+        _file_handle: HANDLE,
+        _stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        let call = Call::register_distribution(name, version, options);
+        match self.replay(call)? {
+            Some(Returned::Registered {
+                guid,
+                installed_name,
+            }) => Ok((guid, installed_name)),
+            other => panic!("Transcript has {other:?} for RegisterDistribution"),
+        }
+    }
+
+    fn register_distribution_pipe(
+        &self,
+        name: &str,
+        version: Version,
+        _pipe_handle: HANDLE,
+        _stderr_handle: HANDLE,
+        options: &RegisterOptions,
+    ) -> Result<(Uuid, String), WslError> {
+        let call = Call::register_distribution_pipe(name, version, options);
+        match self.replay(call)? {
+            Some(Returned::Registered {
+                guid,
+                installed_name,
+            }) => Ok((guid, installed_name)),
+            other => panic!("Transcript has {other:?} for RegisterDistributionPipe"),
+        }
+    }
+
+    fn import_distribution_in_place(&self, name: &str, vhd_path: &Path) -> Result<Uuid, WslError> {
+        let call = Call::ImportDistributionInplace {
+            name: name.to_string(),
+            vhd_path: vhd_path.to_path_buf(),
+        };
+        match self.replay(call)? {
+            Some(Returned::Guid(guid)) => Ok(guid),
+            other => panic!("Transcript has {other:?} for ImportDistributionInplace"),
+        }
+    }
+
+    fn set_version(
+        &self,
+        distribution: Uuid,
+        version: Version,
+        _stderr_handle: HANDLE,
+    ) -> Result<(), WslError> {
+        self.replay(Call::SetVersion {
+            distro_guid: distribution,
+            version,
+        })?;
+        Ok(())
+    }
+
+    fn attach_disk(&self, disk: &Path, flags: AttachFlags) -> Result<(), WslError> {
+        self.replay(Call::AttachDisk {
+            disk: disk.to_path_buf(),
+            flags: flags.bits(),
+        })?;
+        Ok(())
+    }
+
+    fn mount_disk(&self, disk: &Path, options: &MountOptions) -> Result<String, WslError> {
+        match self.replay(Call::mount_disk(disk, options))? {
+            Some(Returned::MountName(name)) => Ok(name),
+            other => panic!("Transcript has {other:?} for MountDisk"),
+        }
+    }
+
+    fn detach_disk(&self, disk: Option<&Path>) -> Result<(), WslError> {
+        self.replay(Call::DetachDisk {
+            disk: disk.map(Path::to_path_buf),
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! Watching distributions for lifecycle changes.
+//!
+//! A [`DistributionWatcher`] enumerates the distributions on a background
+//! thread at a fixed interval and reports the differences between successive
+//! enumerations as [`DistributionEvent`]s, either to a callback or over a
+//! channel. A failed enumeration is reported in their place, and polling
+//! carries on. [`diff`] does the comparison on its own, for callers that
+//! already poll.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use wsl_api::{fake::FakeBackend, watch::{DistributionEvent, DistributionWatcher}, Version, Wsl2};
+//!
+//! let fake = FakeBackend::new();
+//! fake.add_distribution("Ubuntu", Version::WSL2);
+//! let wsl = Arc::new(Wsl2::with_backend(fake.clone()));
+//!
+//! let (watcher, events) = DistributionWatcher::channel(wsl, Duration::from_millis(10))?;
+//! fake.add_distribution("Alpine", Version::WSL1);
+//!
+//! match events.recv_timeout(Duration::from_secs(5)).unwrap()? {
+//!     DistributionEvent::Registered(distro) => assert_eq!(distro.name, "Alpine"),
+//!     event => panic!("unexpected event: {event:?}"),
+//! }
+//! watcher.stop();
+//! # Ok::<(), wsl_api::WslError>(())
+//! ```
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::{Distribution, DistributionState, Version, Wsl2, WslError};
+
+/// A change between two enumerations of the distributions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DistributionEvent {
+    /// A distribution appeared. It is reported as it was first seen, so it
+    /// may already be running.
+    Registered(Distribution),
+    /// A distribution went away, reported as it was last seen.
+    Unregistered(Distribution),
+    /// A distribution started running.
+    Started(Distribution),
+    /// A running distribution stopped, or moved to another state such as
+    /// [`DistributionState::Converting`].
+    Stopped(Distribution),
+    /// A different distribution, or none, became the default.
+    DefaultChanged {
+        previous: Option<Uuid>,
+        current: Option<Uuid>,
+    },
+    /// A distribution was converted to another WSL version.
+    VersionChanged {
+        distribution: Distribution,
+        previous: Version,
+    },
+}
+
+/// Compares two enumerations of the distributions and returns what changed
+/// between them.
+///
+/// Unregistrations come first, then registrations, then changes to the
+/// distributions present in both, and finally a change of default.
+pub fn diff(previous: &[Distribution], current: &[Distribution]) -> Vec<DistributionEvent> {
+    let mut events = Vec::new();
+    let find = |list: &[Distribution], uuid: Uuid| list.iter().find(|d| d.uuid == uuid).cloned();
+
+    for distro in previous {
+        if find(current, distro.uuid).is_none() {
+            events.push(DistributionEvent::Unregistered(distro.clone()));
+        }
+    }
+    for distro in current {
+        if find(previous, distro.uuid).is_none() {
+            events.push(DistributionEvent::Registered(distro.clone()));
+        }
+    }
+    for distro in current {
+        let Some(before) = find(previous, distro.uuid) else {
+            continue;
+        };
+        if before.version != distro.version {
+            events.push(DistributionEvent::VersionChanged {
+                distribution: distro.clone(),
+                previous: before.version,
+            });
+        }
+        let was_running = before.state == DistributionState::Running;
+        let is_running = distro.state == DistributionState::Running;
+        if !was_running && is_running {
+            events.push(DistributionEvent::Started(distro.clone()));
+        } else if was_running && !is_running {
+            events.push(DistributionEvent::Stopped(distro.clone()));
+        }
+    }
+
+    let default = |list: &[Distribution]| list.iter().find(|d| d.is_default).map(|d| d.uuid);
+    let (previous_default, current_default) = (default(previous), default(current));
+    if previous_default != current_default {
+        events.push(DistributionEvent::DefaultChanged {
+            previous: previous_default,
+            current: current_default,
+        });
+    }
+
+    events
+}
+
+/// Polls the distributions on a background thread and reports what changes.
+///
+/// The first enumeration happens before the watcher is returned and only sets
+/// the baseline: distributions that already exist are not reported. A later
+/// enumeration that fails is delivered as an error, and the next one is
+/// compared against the last that succeeded. Polling ends when the watcher
+/// is stopped or dropped.
+pub struct DistributionWatcher {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DistributionWatcher {
+    /// Starts polling every `interval`, calling `on_event` on the background
+    /// thread for each change or failed enumeration.
+    pub fn spawn(
+        wsl: Arc<Wsl2>,
+        interval: Duration,
+        mut on_event: impl FnMut(Result<DistributionEvent, WslError>) + Send + 'static,
+    ) -> Result<Self, WslError> {
+        let mut previous = wsl.enumerate_distributions()?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            // Both an explicit stop and a dropped sender end the loop
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                match wsl.enumerate_distributions() {
+                    Ok(current) => {
+                        for event in diff(&previous, &current) {
+                            on_event(Ok(event));
+                        }
+                        previous = current;
+                    }
+                    Err(e) => on_event(Err(e)),
+                }
+            }
+        });
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Starts polling every `interval`, sending each change or failed
+    /// enumeration to the returned receiver. The receiver disconnects once
+    /// polling ends.
+    pub fn channel(
+        wsl: Arc<Wsl2>,
+        interval: Duration,
+    ) -> Result<(Self, Receiver<Result<DistributionEvent, WslError>>), WslError> {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = Self::spawn(wsl, interval, move |event| {
+            // Nobody is listening any more; the owner stops the watcher
+            _ = sender.send(event);
+        })?;
+        Ok((watcher, receiver))
+    }
+
+    /// Returns whether polling has ended because the callback panicked.
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    /// Stops polling and waits for the background thread to exit. A panic
+    /// in the callback is propagated.
+    pub fn stop(mut self) {
+        self.stop.take();
+        if let Some(Err(panic)) = self.thread.take().map(JoinHandle::join) {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+impl Drop for DistributionWatcher {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
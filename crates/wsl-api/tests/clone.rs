@@ -0,0 +1,39 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{CreateInstanceFlags, RegisterOptions, Version, Wsl2};
+use wsl_com_api_sys::error::{WSL_E_DISTRO_NOT_STOPPED, WSL_E_IMPORT_FAILED};
+
+#[test]
+fn clones_carry_the_exported_tarball() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    wsl.clone_distribution("Ubuntu", "Ubuntu-copy", RegisterOptions::new())
+        .unwrap();
+    wsl.resolve_distribution("Ubuntu-copy").unwrap();
+
+    // A registration that gives up without reading the stream is blamed,
+    // not the export it cut short
+    fake.fail_next_registration(WSL_E_IMPORT_FAILED);
+    let err = wsl
+        .clone_distribution("Ubuntu", "Ubuntu-other", RegisterOptions::new())
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(wsl.resolve_distribution("Ubuntu-other").is_err());
+}
+
+#[test]
+fn failed_export_is_blamed_for_a_failed_clone() {
+    let fake = FakeBackend::new();
+    let legacy = fake.add_distribution("Legacy", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake);
+    wsl.start_distribution(legacy, CreateInstanceFlags::empty())
+        .unwrap();
+
+    // A running WSL1 distribution can't be exported, which leaves the
+    // registration with an empty stream
+    let err = wsl
+        .clone_distribution("Legacy", "Legacy-copy", RegisterOptions::new())
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_DISTRO_NOT_STOPPED);
+    assert!(wsl.resolve_distribution("Legacy-copy").is_err());
+}
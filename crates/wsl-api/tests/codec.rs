@@ -0,0 +1,172 @@
+use proptest::prelude::*;
+use uuid::Uuid;
+use wsl_api::codec::*;
+
+fn string() -> impl Strategy<Value = String> {
+    "[^\0]{0,32}"
+}
+
+fn common() -> impl Strategy<Value = CreateNtProcessCommon> {
+    (
+        string(),
+        prop::collection::vec(string(), 0..8),
+        string(),
+        string(),
+        any::<u32>(),
+        any::<u32>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(filename, command_line, cwd, environment, rows, columns, create_pseudoconsole)| {
+                CreateNtProcessCommon {
+                    filename,
+                    command_line,
+                    current_working_directory: cwd,
+                    environment,
+                    rows,
+                    columns,
+                    create_pseudoconsole,
+                }
+            },
+        )
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        (common(), any::<[u32; 3]>()).prop_map(|(common, std_fd_ids)| {
+            Message::CreateNtProcess(CreateNtProcess { common, std_fd_ids })
+        }),
+        (common(), any::<u128>(), any::<u32>()).prop_map(|(common, vm_id, port)| {
+            Message::CreateNtProcessUtilityVm(CreateNtProcessUtilityVm {
+                common,
+                vm_id: Uuid::from_u128(vm_id),
+                port,
+            })
+        }),
+        (any::<i32>(), any::<u32>(), any::<u32>()).prop_map(|(result, flags, signal_pipe_id)| {
+            Message::CreateProcessResponse(CreateProcessResponse {
+                result,
+                flags,
+                signal_pipe_id,
+            })
+        }),
+        any::<u32>().prop_map(|exit_code| Message::ExitStatus { exit_code }),
+        (any::<u32>(), any::<u32>())
+            .prop_map(|(rows, columns)| Message::WindowSizeChanged { rows, columns }),
+        (0x100u32.., prop::collection::vec(any::<u8>(), 0..64)).prop_map(
+            |(message_type, payload)| Message::Unknown {
+                message_type,
+                payload
+            }
+        ),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn round_trip(message in message()) {
+        let encoded = message.encode().unwrap();
+        let (decoded, used) = Message::decode(&encoded).unwrap();
+        prop_assert_eq!(used, encoded.len());
+        prop_assert_eq!(&decoded, &message);
+
+        let read = Message::read_from(&mut encoded.as_slice()).unwrap();
+        prop_assert_eq!(read, message);
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected(message in message(), cut in any::<prop::sample::Index>()) {
+        let encoded = message.encode().unwrap();
+        let cut = cut.index(encoded.len());
+        prop_assert!(Message::decode(&encoded[..cut]).is_err());
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = Message::decode(&bytes);
+    }
+}
+
+#[test]
+fn exit_status_wire_format() {
+    let encoded = Message::ExitStatus { exit_code: 42 }.encode().unwrap();
+    assert_eq!(encoded, [9, 0, 0, 0, 12, 0, 0, 0, 42, 0, 0, 0]);
+}
+
+#[test]
+fn size_smaller_than_header_is_rejected() {
+    let bytes = [9, 0, 0, 0, 4, 0, 0, 0];
+    assert_eq!(
+        Message::decode(&bytes),
+        Err(CodecError::InvalidSize {
+            message_type: 9,
+            size: 4
+        })
+    );
+}
+
+#[test]
+fn size_too_large_for_fixed_message_is_rejected() {
+    let mut encoded = Message::ExitStatus { exit_code: 1 }.encode().unwrap();
+    encoded[4] = 16;
+    encoded.extend_from_slice(&[0; 4]);
+    assert!(matches!(
+        Message::decode(&encoded),
+        Err(CodecError::InvalidSize { size: 16, .. })
+    ));
+}
+
+#[test]
+fn oversized_message_is_rejected_before_reading_body() {
+    let mut bytes = vec![0xff, 0, 0, 0];
+    bytes.extend_from_slice(&(MAX_MESSAGE_SIZE + 1).to_le_bytes());
+    let err = Message::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn string_offset_out_of_bounds_is_rejected() {
+    let message = Message::CreateNtProcess(CreateNtProcess {
+        common: CreateNtProcessCommon {
+            filename: "cmd.exe".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let mut encoded = message.encode().unwrap();
+    // FilenameOffset is the first field after the header
+    encoded[8..12].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(
+        Message::decode(&encoded),
+        Err(CodecError::OffsetOutOfBounds {
+            field: "Filename",
+            offset: 1000
+        })
+    );
+}
+
+#[test]
+fn unterminated_string_is_rejected() {
+    let message = Message::CreateNtProcess(CreateNtProcess::default());
+    let mut encoded = message.encode().unwrap();
+    // The environment is the last string: drop its terminator
+    let len = encoded.len() - 1;
+    encoded.truncate(len);
+    encoded[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+    assert!(Message::decode(&encoded).is_err());
+}
+
+#[test]
+fn interior_nul_cannot_be_encoded() {
+    let message = Message::CreateNtProcess(CreateNtProcess {
+        common: CreateNtProcessCommon {
+            filename: "cmd\0.exe".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    assert_eq!(
+        message.encode(),
+        Err(CodecError::InteriorNul { field: "Filename" })
+    );
+}
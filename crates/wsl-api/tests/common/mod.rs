@@ -0,0 +1,53 @@
+//! Helpers shared by the integration tests.
+
+// Each test binary compiles this module, and few use all of it
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A directory of the test's own, removed with everything in it when
+/// dropped, so that a failing test leaves nothing behind either.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `wsl-api-<name>-<pid>` under the temporary directory.
+    pub fn new(name: &str) -> Self {
+        Self::new_in(&std::env::temp_dir(), name)
+    }
+
+    /// Creates `wsl-api-<name>-<pid>` under `parent`, replacing whatever an
+    /// earlier run left there.
+    pub fn new_in(parent: &Path, name: &str) -> Self {
+        let dir = parent.join(format!("wsl-api-{}-{}", name, std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The null device, for the service's diagnostics.
+pub fn stderr() -> File {
+    File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).unwrap()
+}
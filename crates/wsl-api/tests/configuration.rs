@@ -0,0 +1,129 @@
+use windows::Win32::Foundation::E_UNEXPECTED;
+use wsl_api::fake::FakeBackend;
+use wsl_api::{DistributionConfiguration, DistributionFlags, Version, Wsl2, WslErrorKind};
+
+#[test]
+fn configuration_reflects_the_version() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    fake.add_distribution("Legacy", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake);
+
+    let ubuntu = wsl.distribution_configuration("ubuntu").unwrap();
+    assert_eq!(ubuntu.name, "Ubuntu");
+    assert_eq!(ubuntu.version, Version::WSL2);
+    assert!(ubuntu.flags.contains(DistributionFlags::VM_MODE));
+
+    let legacy = wsl.distribution_configuration("Legacy").unwrap();
+    assert_eq!(legacy.version, Version::WSL1);
+    assert!(!legacy.flags.contains(DistributionFlags::VM_MODE));
+    assert!(legacy.flags.contains(DistributionFlags::CONFIGURABLE));
+}
+
+#[test]
+fn environment_is_kept_in_order() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let configuration = wsl.distribution_configuration("Ubuntu").unwrap();
+    let keys: Vec<_> = configuration
+        .default_environment
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect();
+    assert_eq!(keys, ["HOSTTYPE", "LANG", "PATH", "TERM"]);
+    assert!(configuration.env("PATH").unwrap().contains("/usr/bin"));
+    assert_eq!(configuration.env("HOME"), None);
+}
+
+#[test]
+fn configuration_round_trips_through_json() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let configuration = wsl.distribution_configuration("Ubuntu").unwrap();
+    let json = serde_json::to_string(&configuration).unwrap();
+    let parsed: DistributionConfiguration = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, configuration);
+}
+
+#[test]
+fn failures_reading_the_configuration_are_returned() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl.distribution_configuration("Debian").unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+
+    fake.fail_next_configuration(E_UNEXPECTED);
+    let err = wsl.distribution_configuration("Ubuntu").unwrap_err();
+    assert_eq!(err.hresult(), E_UNEXPECTED);
+    wsl.distribution_configuration("Ubuntu").unwrap();
+}
+
+#[test]
+fn updates_keep_what_they_dont_touch() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let written = wsl
+        .set_distribution_flags("Ubuntu", DistributionFlags::APPEND_NT_PATH, false)
+        .unwrap();
+    assert_eq!(
+        written.flags,
+        DistributionFlags::ENABLE_INTEROP
+            | DistributionFlags::ENABLE_DRIVE_MOUNTING
+            | DistributionFlags::VM_MODE
+    );
+
+    let written = wsl.set_default_uid("Ubuntu", 1000).unwrap();
+    assert_eq!(written.default_uid, 1000);
+    assert!(!written.flags.contains(DistributionFlags::APPEND_NT_PATH));
+    assert_eq!(wsl.distribution_configuration("Ubuntu").unwrap(), written);
+}
+
+#[test]
+fn service_managed_flags_cant_be_changed() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    fake.add_distribution("Legacy", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake);
+
+    let written = wsl
+        .configure_distribution("Ubuntu", |configuration| {
+            configuration.flags = DistributionFlags::empty();
+            configuration.name = "Renamed".to_string();
+        })
+        .unwrap();
+    assert_eq!(written.flags, DistributionFlags::VM_MODE);
+    assert_eq!(written.name, "Ubuntu");
+
+    let written = wsl
+        .configure_distribution("Legacy", |configuration| {
+            configuration.flags |= DistributionFlags::VM_MODE;
+        })
+        .unwrap();
+    assert!(!written.flags.contains(DistributionFlags::VM_MODE));
+    assert_eq!(wsl.distribution_configuration("Legacy").unwrap(), written);
+}
+
+#[test]
+fn nothing_is_written_when_the_configuration_cant_be_read() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    fake.fail_next_configuration(E_UNEXPECTED);
+    let err = wsl.set_default_uid("Ubuntu", 1000).unwrap_err();
+    assert_eq!(err.hresult(), E_UNEXPECTED);
+    assert_eq!(
+        wsl.distribution_configuration("Ubuntu")
+            .unwrap()
+            .default_uid,
+        0
+    );
+}
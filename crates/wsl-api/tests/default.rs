@@ -0,0 +1,62 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{Version, Wsl2, WslErrorKind};
+
+#[test]
+fn default_is_set_by_reference() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    assert_eq!(wsl.get_default_distribution().unwrap(), ubuntu);
+
+    wsl.set_default_distribution("debian").unwrap();
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+
+    let err = wsl.set_default_distribution("Fedora").unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+}
+
+#[test]
+fn guards_restore_or_keep_the_previous_default() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let guard = wsl.set_default_distribution_scoped(debian).unwrap();
+    assert_eq!(guard.previous(), Some(ubuntu));
+    guard.restore().unwrap();
+    assert_eq!(wsl.get_default_distribution().unwrap(), ubuntu);
+
+    wsl.set_default_distribution_scoped(debian).unwrap().keep();
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+
+    // A failed switch leaves no guard behind
+    let err = wsl.set_default_distribution_scoped("Fedora").err().unwrap();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+}
+
+#[test]
+fn unregistered_previous_default_is_not_restored() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    let alpine = fake.add_distribution("Alpine", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let guard = wsl.set_default_distribution_scoped(debian).unwrap();
+    wsl.unregister_distribution(ubuntu, "Ubuntu", false)
+        .unwrap();
+    guard.restore().unwrap();
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+
+    // Dropping the guard is just as quiet about it
+    {
+        let _guard = wsl.set_default_distribution_scoped(alpine).unwrap();
+        wsl.unregister_distribution(debian, "Debian", false)
+            .unwrap();
+    }
+    assert_eq!(wsl.get_default_distribution().unwrap(), alpine);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{DiskLedger, MountOptions, Wsl2, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_ELEVATION_NEEDED_TO_MOUNT_DISK;
+
+use common::TempDir;
+
+#[test]
+fn stale_disks_that_fail_to_detach_stay_in_the_ledger() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("disk-ledger");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    let first_guard = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger)
+        .unwrap();
+    let second_guard = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE3", MountOptions::new(), &ledger)
+        .unwrap();
+    // A run that crashes with both disks mounted
+    let (first, second) = (
+        first_guard.disk().to_path_buf(),
+        second_guard.disk().to_path_buf(),
+    );
+    std::mem::forget((first_guard, second_guard));
+    // WSL shut down and took the second disk with it
+    wsl.detach_disk(&second).unwrap();
+
+    fake.fail_next_detach(WSL_E_ELEVATION_NEEDED_TO_MOUNT_DISK);
+    let stale = wsl.detach_stale_disks(&ledger).unwrap();
+    assert!(stale.detached.is_empty());
+    assert_eq!(stale.not_attached, [second]);
+    assert_eq!(stale.failed.len(), 1);
+    assert_eq!(stale.failed[0].0, first);
+    assert_eq!(
+        stale.failed[0].1.kind(),
+        Some(WslErrorKind::ElevationNeeded)
+    );
+    assert_eq!(ledger.disks().unwrap(), std::slice::from_ref(&first));
+
+    let stale = wsl.detach_stale_disks(&ledger).unwrap();
+    assert_eq!(stale.detached, [first]);
+    assert!(ledger.disks().unwrap().is_empty());
+    assert!(fake.attached_disks().is_empty());
+}
+
+#[test]
+fn failed_detach_is_reported_and_stays_tracked() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("disk-guard");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    let guard = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger)
+        .unwrap();
+    let disk = guard.disk().to_path_buf();
+    fake.fail_next_detach(WSL_E_ELEVATION_NEEDED_TO_MOUNT_DISK);
+    let err = guard.detach().unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::ElevationNeeded));
+    assert_eq!(ledger.disks().unwrap(), std::slice::from_ref(&disk));
+
+    let guard = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE3", MountOptions::new(), &ledger)
+        .unwrap();
+    guard.keep().unwrap();
+    assert_eq!(ledger.disks().unwrap(), [disk]);
+    assert_eq!(fake.attached_disks().len(), 2);
+}
+
+#[test]
+fn disks_attached_by_someone_else_are_not_tracked() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("disk-foreign");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    // Another program's mount of the same disk
+    wsl.mount_disk(r"\\.\PHYSICALDRIVE2", MountOptions::new().name("theirs"))
+        .unwrap();
+    let err = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), Some(WslErrorKind::DiskAlreadyMounted));
+    assert!(ledger.disks().unwrap().is_empty());
+
+    let stale = wsl.detach_stale_disks(&ledger).unwrap();
+    assert!(stale.detached.is_empty());
+    assert_eq!(fake.attached_disks().len(), 1);
+}
+
+#[test]
+fn disks_left_by_an_earlier_run_stay_tracked() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("disk-leftover");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    let guard = wsl
+        .mount_disk_tracked(
+            r"\\.\PHYSICALDRIVE2",
+            MountOptions::new().name("ours"),
+            &ledger,
+        )
+        .unwrap();
+    let disk = guard.disk().to_path_buf();
+    std::mem::forget(guard);
+
+    let err = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), Some(WslErrorKind::DiskAlreadyMounted));
+    assert_eq!(ledger.disks().unwrap(), std::slice::from_ref(&disk));
+
+    assert_eq!(wsl.detach_stale_disks(&ledger).unwrap().detached, [disk]);
+    assert!(fake.attached_disks().is_empty());
+}
+
+#[test]
+fn concurrent_mounts_are_all_recorded() {
+    let fake = FakeBackend::new();
+    let dir = TempDir::new("disk-shared");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    // Each thread stands in for another program sharing the ledger
+    std::thread::scope(|scope| {
+        for drive in 0..8 {
+            let (fake, ledger) = (fake.clone(), &ledger);
+            scope.spawn(move || {
+                let wsl = Wsl2::with_backend(fake);
+                let disk = format!(r"\\.\PHYSICALDRIVE{drive}");
+                let guard = wsl
+                    .mount_disk_tracked(disk, MountOptions::new(), ledger)
+                    .unwrap();
+                std::mem::forget(guard);
+            });
+        }
+    });
+    assert_eq!(ledger.disks().unwrap().len(), 8);
+
+    let wsl = Wsl2::with_backend(fake.clone());
+    assert_eq!(wsl.detach_stale_disks(&ledger).unwrap().detached.len(), 8);
+    assert!(!path.exists());
+}
+
+#[test]
+fn abandoned_locks_are_taken_over() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("disk-abandoned");
+    let path = dir.join("disks.json");
+    let ledger = DiskLedger::new(&path);
+
+    // A run that died while updating the ledger
+    let lock = std::fs::File::create(format!("{}.lock", path.display())).unwrap();
+    lock.set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(60))
+        .unwrap();
+    drop(lock);
+
+    let guard = wsl
+        .mount_disk_tracked(r"\\.\PHYSICALDRIVE2", MountOptions::new(), &ledger)
+        .unwrap();
+    assert_eq!(ledger.disks().unwrap().len(), 1);
+    guard.detach().unwrap();
+    assert!(!path.exists());
+}
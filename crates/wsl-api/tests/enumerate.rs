@@ -0,0 +1,50 @@
+use windows::Win32::Foundation::E_ACCESSDENIED;
+use wsl_api::fake::FakeBackend;
+use wsl_api::{DistributionFlags, Version, Wsl2};
+use wsl_com_api_sys::error::WSL_E_DISTRO_NOT_FOUND;
+
+#[test]
+fn flags_are_read_for_every_distribution() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    fake.add_distribution("Alpine", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake);
+
+    assert!(wsl
+        .enumerate_distributions()
+        .unwrap()
+        .iter()
+        .all(|distro| distro.flags.is_none()));
+    let distros = wsl.enumerate_distributions_with_flags().unwrap();
+    assert!(distros[0]
+        .flags
+        .unwrap()
+        .contains(DistributionFlags::VM_MODE));
+    assert!(!distros[1]
+        .flags
+        .unwrap()
+        .contains(DistributionFlags::VM_MODE));
+}
+
+#[test]
+fn distributions_removed_since_enumerating_are_listed_without_flags() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    fake.fail_next_configuration(WSL_E_DISTRO_NOT_FOUND);
+    let distros = wsl.enumerate_distributions_with_flags().unwrap();
+    assert_eq!(distros.len(), 1);
+    assert_eq!(distros[0].flags, None);
+}
+
+#[test]
+fn failed_configuration_reads_are_returned() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    fake.fail_next_configuration(E_ACCESSDENIED);
+    let err = wsl.enumerate_distributions_with_flags().unwrap_err();
+    assert_eq!(err.hresult(), E_ACCESSDENIED);
+}
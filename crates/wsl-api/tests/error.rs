@@ -0,0 +1,45 @@
+mod common;
+
+use windows::Win32::Foundation::{ERROR_ALREADY_EXISTS, E_FAIL};
+use wsl_api::fake::FakeBackend;
+use wsl_api::{ImportFlags, Version, Wsl2, WslError, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_DISTRO_NOT_FOUND;
+
+use common::stderr;
+
+#[test]
+fn unknown_errors_have_no_kind() {
+    assert_eq!(WslError::from(E_FAIL).kind(), None);
+    let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+    assert_eq!(WslError::from(io).kind(), None);
+}
+
+#[test]
+fn service_errors_are_classified() {
+    assert_eq!(
+        WslError::from(WSL_E_DISTRO_NOT_FOUND).kind(),
+        Some(WslErrorKind::DistributionNotFound)
+    );
+}
+
+#[test]
+fn existing_names_are_classified_where_they_are_taken() {
+    // The same HRESULT reports files that already exist
+    let exists = WslError::from(ERROR_ALREADY_EXISTS.to_hresult());
+    assert_eq!(exists.kind(), None);
+
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let err = wsl
+        .register_distribution(
+            "ubuntu",
+            Version::WSL2,
+            stderr(),
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionAlreadyExists));
+    assert_eq!(err.hresult(), ERROR_ALREADY_EXISTS.to_hresult());
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::io::Write;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{tarball, CreateInstanceFlags, ExportFlags, Version, Wsl2, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_DISTRO_NOT_STOPPED;
+
+use common::stderr;
+
+/// Accepts `.1` bytes, then fails every write.
+struct Full(Vec<u8>, usize);
+
+impl Write for Full {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let room = self.1 - self.0.len();
+        if room == 0 {
+            return Err(std::io::Error::other("the disk is full"));
+        }
+        let n = buf.len().min(room);
+        self.0.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn exported_tarballs_are_counted_and_readable() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let mut sink = Full(vec![], usize::MAX);
+    let written = wsl
+        .export_distribution_to_writer("Ubuntu", &mut sink, stderr(), ExportFlags::empty())
+        .unwrap();
+    assert_eq!(written, sink.0.len() as u64);
+    let report = tarball::inspect(sink.0.as_slice()).unwrap();
+    assert_eq!(report.os_release.name(), Some("Ubuntu"));
+}
+
+#[test]
+fn writers_failing_partway_abandon_the_export() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let mut sink = Full(vec![], 100);
+    let err = wsl
+        .export_distribution_to_writer("Ubuntu", &mut sink, stderr(), ExportFlags::empty())
+        .unwrap_err();
+    assert!(err.to_string().contains("the disk is full"));
+    assert_eq!(sink.0.len(), 100);
+}
+
+#[test]
+fn service_failures_are_returned() {
+    let fake = FakeBackend::new();
+    let legacy = fake.add_distribution("Legacy", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake);
+    wsl.start_distribution(legacy, CreateInstanceFlags::empty())
+        .unwrap();
+
+    let mut tarball = vec![];
+    let err = wsl
+        .export_distribution_to_writer(legacy, &mut tarball, stderr(), ExportFlags::empty())
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_DISTRO_NOT_STOPPED);
+    assert!(tarball.is_empty());
+
+    let err = wsl
+        .export_distribution_to_writer("Debian", &mut tarball, stderr(), ExportFlags::empty())
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{Version, Wsl2, WslErrorKind};
+
+use common::TempDir;
+
+#[test]
+fn only_existing_vhdx_disks_are_imported() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("import-invalid");
+
+    let tarball = dir.join("rootfs.tar");
+    std::fs::write(&tarball, b"vhdxfile").unwrap();
+    let not_vhdx = dir.join("rootfs.vhdx");
+    std::fs::write(&not_vhdx, b"not a disk").unwrap();
+    let directory = dir.join("disk.vhdx");
+    std::fs::create_dir(&directory).unwrap();
+
+    for (path, reason) in [
+        (&tarball, "extension"),
+        (&dir.join("missing.vhdx"), "does not exist"),
+        (&directory, "not a file"),
+        (&not_vhdx, "not a VHDX"),
+    ] {
+        let err = wsl
+            .import_distribution_in_place("Golden", path)
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(WslErrorKind::InvalidPath), "{path:?}");
+        assert!(err.to_string().contains(reason), "{err}");
+    }
+    assert!(wsl.enumerate_distributions().unwrap().is_empty());
+}
+
+#[test]
+fn disks_are_registered_where_they_are() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("import-in-place");
+    let disk = dir.join("golden.VHDX");
+    std::fs::write(&disk, b"vhdxfile and the rest of the disk").unwrap();
+
+    let (uuid, name) = wsl.import_distribution_in_place("Golden", &disk).unwrap();
+    assert_eq!(name, "Golden");
+    assert_eq!(fake.location(uuid), Some(dir.to_path_buf()));
+    let imported = wsl.distribution_configuration(uuid).unwrap();
+    assert_eq!(imported.version, Version::WSL2);
+
+    let err = wsl
+        .import_distribution_in_place("GOLDEN", &disk)
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionAlreadyExists));
+    assert_eq!(wsl.enumerate_distributions().unwrap().len(), 1);
+}
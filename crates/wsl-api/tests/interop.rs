@@ -0,0 +1,112 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use wsl_api::codec::{Message, MAX_MESSAGE_SIZE};
+use wsl_api::fake::InteropPeer;
+
+fn io_error_kind(error: &wsl_api::WslError) -> ErrorKind {
+    std::error::Error::source(error)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .map(|e| e.kind())
+        .expect("expected an I/O error")
+}
+
+#[test]
+fn exit_status_is_delivered() {
+    let process = InteropPeer::new().exit(42).into_process();
+    assert_eq!(process.wait().unwrap().code(), Some(42));
+}
+
+#[test]
+fn unknown_messages_are_skipped() {
+    let process = InteropPeer::new()
+        .send(&Message::Unknown {
+            message_type: 0x1234,
+            payload: vec![0xaa; 2000],
+        })
+        .send(&Message::WindowSizeChanged {
+            rows: 25,
+            columns: 80,
+        })
+        .exit(1)
+        .into_process();
+    assert_eq!(process.wait().unwrap().code(), Some(1));
+}
+
+#[test]
+fn frames_split_across_writes_are_reassembled() {
+    let frame = Message::ExitStatus { exit_code: 7 }.encode().unwrap();
+    let process = InteropPeer::new()
+        .send_raw(&frame[..3])
+        .delay(Duration::from_millis(20))
+        .send_raw(&frame[3..10])
+        .delay(Duration::from_millis(20))
+        .send_raw(&frame[10..])
+        .into_process();
+    assert_eq!(process.wait().unwrap().code(), Some(7));
+}
+
+#[test]
+fn wait_timeout_expires_while_process_runs() {
+    let mut process = InteropPeer::new()
+        .delay(Duration::from_millis(200))
+        .exit(5)
+        .into_process();
+    assert!(process
+        .wait_timeout(Duration::from_millis(10))
+        .unwrap()
+        .is_none());
+    let status = process.wait_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(status.and_then(|status| status.code()), Some(5));
+    // The exit status is remembered once it has been received
+    assert_eq!(process.wait().unwrap().code(), Some(5));
+}
+
+#[test]
+fn wait_timeout_on_silent_peer() {
+    let mut process = InteropPeer::new().wait_for_close().into_process();
+    assert!(process
+        .wait_timeout(Duration::from_millis(50))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn early_close_is_an_error() {
+    let process = InteropPeer::new().into_process();
+    let error = process.wait().unwrap_err();
+    assert_eq!(io_error_kind(&error), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn truncated_frame_is_an_error() {
+    let frame = Message::ExitStatus { exit_code: 7 }.encode().unwrap();
+    let process = InteropPeer::new().send_raw(&frame[..10]).into_process();
+    let error = process.wait().unwrap_err();
+    assert_eq!(io_error_kind(&error), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn oversized_frame_is_an_error() {
+    let mut header = vec![0x55, 0, 0, 0];
+    header.extend_from_slice(&(MAX_MESSAGE_SIZE + 1).to_le_bytes());
+    let process = InteropPeer::new()
+        .send_raw(header)
+        .wait_for_close()
+        .into_process();
+    let error = process.wait().unwrap_err();
+    assert_eq!(io_error_kind(&error), ErrorKind::InvalidData);
+}
+
+#[test]
+fn malformed_exit_status_is_an_error() {
+    // An exit status message with room for two exit codes
+    let mut frame = vec![9, 0, 0, 0, 16, 0, 0, 0];
+    frame.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0]);
+    let process = InteropPeer::new()
+        .send_raw(frame)
+        .wait_for_close()
+        .into_process();
+    let error = process.wait().unwrap_err();
+    assert_eq!(io_error_kind(&error), ErrorKind::InvalidData);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{CreateInstanceFlags, Version, Wsl2, WslErrorKind};
+
+use common::TempDir;
+
+#[test]
+fn running_distributions_are_only_moved_when_terminated() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("move-running");
+    let target = dir.join("ubuntu");
+
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+    let err = wsl.move_distribution(ubuntu, &target, false).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotStopped));
+    assert!(err.to_string().contains("running"));
+    assert!(fake.is_running(ubuntu));
+    assert_eq!(fake.location(ubuntu), None);
+    assert!(!target.exists());
+
+    wsl.move_distribution(ubuntu, &target, true).unwrap();
+    assert!(!fake.is_running(ubuntu));
+    assert_eq!(fake.location(ubuntu), Some(target.clone()));
+    assert!(target.is_dir());
+}
+
+#[test]
+fn targets_must_be_free_directories() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    let target = TempDir::new("move-targets");
+
+    let file = target.join("not-a-directory");
+    std::fs::write(&file, b"").unwrap();
+    let err = wsl.move_distribution(ubuntu, &file, false).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::InvalidPath));
+
+    std::fs::write(target.join("ext4.vhdx"), b"").unwrap();
+    let err = wsl.move_distribution(ubuntu, &target, false).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::InvalidPath));
+    assert_eq!(fake.location(ubuntu), None);
+
+    // Missing directories are created, however deep
+    let nested = target.join("disks").join("ubuntu");
+    wsl.move_distribution(ubuntu, &nested, false).unwrap();
+    assert_eq!(fake.location(ubuntu), Some(nested.clone()));
+    assert!(nested.is_dir());
+}
+
+#[test]
+fn relative_targets_are_made_absolute() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new_in(&std::env::current_dir().unwrap(), "move-relative");
+    let relative = dir.file_name().unwrap().to_str().unwrap();
+
+    wsl.move_distribution(ubuntu, relative, false).unwrap();
+    let location = fake.location(ubuntu).unwrap();
+    assert!(location.is_absolute());
+    assert_eq!(location, *dir);
+}
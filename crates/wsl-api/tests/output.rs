@@ -0,0 +1,96 @@
+mod common;
+
+use std::fs::File;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{CreateInstanceFlags, ExportFlags, ImportFlags, Version, Wsl2};
+use wsl_com_api_sys::error::{WSL_E_DISTRO_NOT_STOPPED, WSL_E_IMPORT_FAILED};
+
+use common::TempDir;
+
+#[test]
+fn output_is_delivered_and_returned_line_by_line() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let mut lines = vec![];
+    let converted = wsl
+        .set_version_with_progress("Ubuntu", Version::WSL1, |line| lines.push(line.to_string()))
+        .unwrap();
+    assert_eq!(
+        lines,
+        ["Conversion in progress, this may take a few minutes."]
+    );
+    assert_eq!(
+        converted.output,
+        "Conversion in progress, this may take a few minutes.\n"
+    );
+}
+
+#[test]
+fn output_is_attached_to_failures() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("output-garbage");
+    let garbage = dir.join("garbage.tar");
+    std::fs::write(&garbage, b"not a tarball").unwrap();
+
+    let mut lines = vec![];
+    let err = wsl
+        .register_distribution_with_progress(
+            "Garbage",
+            Version::WSL2,
+            File::open(&garbage).unwrap(),
+            ImportFlags::empty(),
+            |line| lines.push(line.to_string()),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert_eq!(
+        err.output(),
+        Some("bsdtar: Error opening archive: Unrecognized archive format\n")
+    );
+    assert_eq!(lines.len(), 1);
+
+    // Failures the service had nothing to say about have no output
+    wsl.start_distribution("Ubuntu", CreateInstanceFlags::empty())
+        .unwrap();
+    let err = wsl
+        .set_version_with_progress("Ubuntu", Version::WSL1, |_| {})
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_DISTRO_NOT_STOPPED);
+    assert_eq!(err.output(), None);
+}
+
+#[test]
+fn silent_operations_return_empty_output() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("output-export");
+    let tarball = dir.join("export.tar");
+
+    let exported = wsl
+        .export_distribution_with_progress(
+            "Ubuntu",
+            File::create(&tarball).unwrap(),
+            ExportFlags::empty(),
+            |line| panic!("unexpected output: {line}"),
+        )
+        .unwrap();
+    assert_eq!(exported.output, "");
+
+    let registered = wsl
+        .register_distribution_with_progress(
+            "Ubuntu-copy",
+            Version::WSL2,
+            File::open(&tarball).unwrap(),
+            ImportFlags::empty(),
+            |line| panic!("unexpected output: {line}"),
+        )
+        .unwrap();
+    assert_eq!(registered.value.1, "Ubuntu-copy");
+    assert_eq!(registered.output, "");
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use std::io::Read;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{ExportFlags, ImportFlags, RegisterOptions, Version, Wsl2, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_IMPORT_FAILED;
+
+use common::stderr;
+
+/// A fake with one distribution, and the tarball it exports.
+fn fake_with_tarball() -> (FakeBackend, Vec<u8>) {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let mut tarball = vec![];
+    Wsl2::with_backend(fake.clone())
+        .export_distribution_to_writer("Ubuntu", &mut tarball, stderr(), ExportFlags::empty())
+        .unwrap();
+    (fake, tarball)
+}
+
+#[test]
+fn builder_sets_every_option() {
+    let options = RegisterOptions::new()
+        .target_directory("disks/debian")
+        .vhd_size("8GB".parse().unwrap())
+        .fixed_vhd(true)
+        .flags(ImportFlags::NO_OOBE)
+        .flags(ImportFlags::CREATE_SHORTCUT)
+        .package_family_name("TheDebianProject.DebianGNULinux_76v4gfsz19hv4");
+    assert_eq!(
+        options.flags,
+        ImportFlags::FIXED_VHD | ImportFlags::NO_OOBE | ImportFlags::CREATE_SHORTCUT
+    );
+    assert_eq!(options.vhd_size.unwrap().bytes(), 8 << 30);
+    assert!(!options.inspect);
+
+    let options = options.fixed_vhd(false);
+    assert_eq!(
+        options.flags,
+        ImportFlags::NO_OOBE | ImportFlags::CREATE_SHORTCUT
+    );
+    assert_eq!(
+        RegisterOptions::from(ImportFlags::VHD),
+        RegisterOptions::new().flags(ImportFlags::VHD)
+    );
+}
+
+#[test]
+fn fixed_disks_need_a_size() {
+    let (fake, tarball) = fake_with_tarball();
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            RegisterOptions::new().fixed_vhd(true),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::InvalidSize));
+    assert!(wsl.resolve_distribution("Debian").is_err());
+
+    let (debian, _) = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            RegisterOptions::new()
+                .fixed_vhd(true)
+                .vhd_size("16GB".parse().unwrap()),
+        )
+        .unwrap();
+    assert_eq!(fake.disk_size(debian), Some(16 << 30));
+}
+
+#[test]
+fn target_directories_are_made_absolute() {
+    let (fake, tarball) = fake_with_tarball();
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let (debian, _) = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            RegisterOptions::new().target_directory("disks/debian"),
+        )
+        .unwrap();
+    assert_eq!(
+        fake.location(debian),
+        Some(std::env::current_dir().unwrap().join("disks/debian"))
+    );
+
+    // Plain flags install in the default location
+    let (alpine, _) = wsl
+        .register_distribution_from_reader(
+            "Alpine",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            ImportFlags::NO_OOBE,
+        )
+        .unwrap();
+    assert_eq!(fake.location(alpine), None);
+    assert_eq!(fake.disk_size(alpine), None);
+}
+
+/// Reads `.0`, then fails instead of reporting the end of the stream.
+struct Truncated<'a>(&'a [u8]);
+
+impl Read for Truncated<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => Err(std::io::ErrorKind::ConnectionReset.into()),
+            n => Ok(n),
+        }
+    }
+}
+
+#[test]
+fn streams_are_registered_like_files() {
+    let (fake, tarball) = fake_with_tarball();
+    let wsl = Wsl2::with_backend(fake);
+
+    let (debian, name) = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL1,
+            tarball.as_slice(),
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap();
+    assert_eq!(name, "Debian");
+    assert_eq!(wsl.resolve_distribution("debian").unwrap(), debian);
+    let configuration = wsl.distribution_configuration(debian).unwrap();
+    assert_eq!(configuration.version, Version::WSL1);
+
+    let err = wsl
+        .register_distribution_from_reader(
+            "DEBIAN",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionAlreadyExists));
+}
+
+#[test]
+fn streams_that_fail_partway_are_unregistered() {
+    let (fake, tarball) = fake_with_tarball();
+    let wsl = Wsl2::with_backend(fake);
+
+    // The whole tarball reaches the service, which registers it, before
+    // the source fails
+    let err = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            Truncated(&tarball),
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("connection reset"));
+    assert!(wsl.resolve_distribution("Debian").is_err());
+}
+
+#[test]
+fn service_failures_are_returned_for_streams() {
+    let (fake, tarball) = fake_with_tarball();
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    fake.fail_next_registration(WSL_E_IMPORT_FAILED);
+    let err = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            tarball.as_slice(),
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(wsl.resolve_distribution("Debian").is_err());
+
+    let err = wsl
+        .register_distribution_from_reader(
+            "Debian",
+            Version::WSL2,
+            &b"not a tarball"[..],
+            stderr(),
+            ImportFlags::empty(),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(wsl.resolve_distribution("Debian").is_err());
+}
@@ -0,0 +1,79 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{DistributionRef, Version, Wsl2, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_DEFAULT_DISTRO_NOT_FOUND;
+
+#[test]
+fn every_reference_resolves_to_the_same_distribution() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    assert_eq!(wsl.resolve_distribution("Ubuntu").unwrap(), ubuntu);
+    assert_eq!(wsl.resolve_distribution("UBUNTU").unwrap(), ubuntu);
+    assert_eq!(
+        wsl.resolve_distribution(String::from("debian")).unwrap(),
+        debian
+    );
+    assert_eq!(wsl.resolve_distribution(debian).unwrap(), debian);
+    assert_eq!(
+        wsl.resolve_distribution(DistributionRef::from(&debian))
+            .unwrap(),
+        debian
+    );
+    assert_eq!(
+        wsl.resolve_distribution(DistributionRef::Default).unwrap(),
+        ubuntu
+    );
+
+    let listed = wsl.enumerate_distributions().unwrap();
+    let listed = listed
+        .iter()
+        .find(|distro| distro.name == "Debian")
+        .unwrap();
+    assert_eq!(wsl.resolve_distribution(listed).unwrap(), debian);
+}
+
+#[test]
+fn the_default_reference_follows_the_default() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let err = wsl
+        .resolve_distribution(DistributionRef::Default)
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_DEFAULT_DISTRO_NOT_FOUND);
+
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    wsl.set_default_distribution("Debian").unwrap();
+    assert_eq!(
+        wsl.resolve_distribution(DistributionRef::Default).unwrap(),
+        debian
+    );
+}
+
+#[test]
+fn unknown_names_are_not_found() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let err = wsl.resolve_distribution("Fedora").unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    assert!(err.to_string().contains("'Fedora'"));
+
+    // Operations taking a reference resolve it the same way
+    let err = wsl.terminate_distribution("Fedora").unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
+
+#[test]
+fn references_display_what_they_name() {
+    let uuid = "d1a4f2ea-3ba8-4a43-8de9-1f6b2a3e0e5d".parse().unwrap();
+    assert_eq!(DistributionRef::from("Ubuntu").to_string(), "Ubuntu");
+    assert_eq!(
+        DistributionRef::Uuid(uuid).to_string(),
+        "{d1a4f2ea-3ba8-4a43-8de9-1f6b2a3e0e5d}"
+    );
+    assert_eq!(DistributionRef::Default.to_string(), "default distribution");
+}
@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::{CreateInstanceFlags, DiskSize, Version, Wsl2, WslErrorKind};
+
+fn parse(s: &str) -> Result<u64, WslErrorKind> {
+    s.parse::<DiskSize>()
+        .map(|size| size.bytes())
+        .map_err(|e| e.kind().unwrap())
+}
+
+/// A fake whose distributions report `used_kb` kilobytes in use to `df`.
+fn fake_with_usage(used_kb: u64) -> FakeBackend {
+    let fake = FakeBackend::new();
+    fake.on_launch(move |mut process| {
+        writeln!(
+            process.stdout,
+            "Filesystem 1024-blocks Used Available Capacity Mounted on"
+        )
+        .unwrap();
+        writeln!(process.stdout, "/dev/sdc 10485760 {used_kb} 0 20% /").unwrap();
+        0
+    });
+    fake
+}
+
+#[test]
+fn sizes_are_parsed_with_binary_units() {
+    assert_eq!(parse("512"), Ok(512));
+    assert_eq!(parse("512B"), Ok(512));
+    assert_eq!(parse("4k"), Ok(4 << 10));
+    assert_eq!(parse("4KB"), Ok(4 << 10));
+    assert_eq!(parse("256MiB"), Ok(256 << 20));
+    assert_eq!(parse(" 20 GB "), Ok(20 << 30));
+    assert_eq!(parse("1.5gib"), Ok(3 << 29));
+    assert_eq!(parse(".5T"), Ok(1 << 39));
+}
+
+#[test]
+fn malformed_sizes_are_rejected() {
+    for malformed in [
+        "",
+        "GB",
+        "1BBB",
+        "10IB",
+        "5GIBB",
+        "5GI",
+        "1.5",
+        "1.",
+        "1..5GB",
+        "-1GB",
+        "1PB",
+        "1 G B",
+        "99999999999999999999",
+        "20000000TB",
+    ] {
+        assert_eq!(
+            parse(malformed),
+            Err(WslErrorKind::InvalidSize),
+            "{malformed:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn sizes_display_in_the_largest_whole_unit() {
+    assert_eq!(DiskSize::from_bytes(20 << 30).to_string(), "20GB");
+    assert_eq!(DiskSize::from_bytes(1536 << 20).to_string(), "1.5GB");
+    assert_eq!(DiskSize::from_bytes(100).to_string(), "100B");
+}
+
+#[test]
+fn shrinking_below_the_used_space_is_refused() {
+    // 2GB in use
+    let fake = fake_with_usage(2 << 20);
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl
+        .resize_distribution(ubuntu, "1GB".parse().unwrap(), false, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DiskTooSmall));
+    assert_eq!(fake.disk_size(ubuntu), None);
+    assert!(!fake.is_running(ubuntu));
+
+    wsl.resize_distribution(ubuntu, "2GB".parse().unwrap(), false, |_| {})
+        .unwrap();
+    assert_eq!(fake.disk_size(ubuntu), Some(2 << 30));
+}
+
+#[test]
+fn resizing_needs_a_stopped_wsl2_distribution() {
+    let fake = fake_with_usage(1024);
+    let alpine = fake.add_distribution("Alpine", Version::WSL1);
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl
+        .resize_distribution(alpine, "1GB".parse().unwrap(), false, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::Wsl2Needed));
+
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+    let err = wsl
+        .resize_distribution(ubuntu, "1GB".parse().unwrap(), false, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotStopped));
+
+    wsl.resize_distribution(ubuntu, "1GB".parse().unwrap(), true, |_| {})
+        .unwrap();
+    assert_eq!(fake.disk_size(ubuntu), Some(1 << 30));
+    assert!(!fake.is_running(ubuntu));
+}
+
+#[test]
+fn running_distributions_are_measured_before_being_terminated() {
+    // 2GB in use
+    let fake = fake_with_usage(2 << 20);
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+
+    let err = wsl
+        .resize_distribution(ubuntu, "1GB".parse().unwrap(), true, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DiskTooSmall));
+    assert!(fake.is_running(ubuntu));
+
+    wsl.resize_distribution(ubuntu, "4GB".parse().unwrap(), true, |_| {})
+        .unwrap();
+    assert_eq!(fake.disk_size(ubuntu), Some(4 << 30));
+    assert!(!fake.is_running(ubuntu));
+}
+
+#[test]
+fn failing_or_unparsable_df_is_reported() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    fake.on_launch(|mut process| {
+        writeln!(process.stderr, "df: /: No such file or directory").unwrap();
+        1
+    });
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl
+        .resize_distribution(ubuntu, "1GB".parse().unwrap(), false, |_| {})
+        .unwrap_err();
+    assert!(err.to_string().contains("No such file or directory"));
+    assert_eq!(fake.disk_size(ubuntu), None);
+    assert!(!fake.is_running(ubuntu));
+
+    fake.on_launch(|mut process| {
+        writeln!(process.stdout, "Filesystem Size Used Avail Use% Mounted on").unwrap();
+        writeln!(process.stdout, "/dev/sdc 10G 2.0G 8.0G 20% /").unwrap();
+        0
+    });
+    let err = wsl
+        .resize_distribution(ubuntu, "1GB".parse().unwrap(), false, |_| {})
+        .unwrap_err();
+    assert!(err.to_string().contains("2.0G"));
+    assert_eq!(fake.disk_size(ubuntu), None);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::snapshot::SnapshotStore;
+use wsl_api::{Version, Wsl2};
+use wsl_com_api_sys::error::WSL_E_IMPORT_FAILED;
+
+use common::TempDir;
+
+#[test]
+fn failed_replacement_points_at_the_snapshot() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("snapshot-failed-replacement");
+    let store = SnapshotStore::open(&wsl, &dir).unwrap();
+    store.snapshot("Ubuntu", "before").unwrap();
+
+    fake.fail_next_registration(WSL_E_IMPORT_FAILED);
+    let err = store.restore("before", true).unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(err.to_string().contains("'before'"));
+    assert!(wsl.resolve_distribution("Ubuntu").is_err());
+    assert!(wsl.enumerate_distributions().unwrap().is_empty());
+
+    // The snapshot is all it takes to try again
+    let (restored, _) = store.restore("before", false).unwrap();
+    assert_eq!(wsl.resolve_distribution("Ubuntu").unwrap(), restored);
+}
+
+#[test]
+fn replacement_keeps_the_default() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("snapshot-replacement");
+    let store = SnapshotStore::open(&wsl, &dir).unwrap();
+    store.snapshot("Ubuntu", "before").unwrap();
+
+    let (restored, name) = store.restore("before", true).unwrap();
+    assert_ne!(restored, ubuntu);
+    assert_eq!(name, "Ubuntu");
+    assert_eq!(wsl.get_default_distribution().unwrap(), restored);
+    assert_eq!(wsl.enumerate_distributions().unwrap().len(), 1);
+}
+
+#[test]
+fn stray_files_are_not_listed_or_pruned() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("snapshot-stray");
+    let store = SnapshotStore::open(&wsl, &dir).unwrap();
+    store.snapshot("Ubuntu", "first").unwrap();
+    store.snapshot("Ubuntu", "second").unwrap();
+    std::fs::write(dir.join("settings.json"), "{\"theme\": \"dark\"}").unwrap();
+    std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+    let labels: Vec<_> = store.list().unwrap().into_iter().map(|m| m.label).collect();
+    assert_eq!(labels, ["first", "second"]);
+
+    let pruned = store.prune(1).unwrap();
+    assert_eq!(pruned.len(), 1);
+    assert_eq!(pruned[0].label, "first");
+    assert!(dir.join("settings.json").exists());
+}
+
+#[test]
+fn snapshots_leave_no_partial_files() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    let dir = TempDir::new("snapshot-partial");
+    let store = SnapshotStore::open(&wsl, &dir).unwrap();
+    let manifest = store.snapshot("Ubuntu", "first").unwrap();
+    let tarball = std::fs::read(dir.join("first.tar")).unwrap();
+    assert!(manifest.size > 0);
+    assert_eq!(manifest.size, tarball.len() as u64);
+    wsl_api::tarball::inspect(tarball.as_slice()).unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["first.json", "first.tar"]);
+}
@@ -0,0 +1,60 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{AllowUnsafeSparse, CreateInstanceFlags, Version, Wsl2, WslErrorKind};
+
+#[test]
+fn wsl1_distributions_have_no_disk() {
+    let fake = FakeBackend::new();
+    let legacy = fake.add_distribution("Legacy", Version::WSL1);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let allow_unsafe = AllowUnsafeSparse::accept_risk_of_data_corruption();
+    let err = wsl
+        .set_sparse(legacy, true, Some(allow_unsafe))
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::Wsl2Needed));
+    assert!(err.to_string().contains("'Legacy'"));
+    assert!(!fake.is_sparse(legacy));
+}
+
+#[test]
+fn running_distributions_are_not_made_sparse() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+
+    let allow_unsafe = AllowUnsafeSparse::accept_risk_of_data_corruption();
+    let err = wsl
+        .set_sparse(ubuntu, true, Some(allow_unsafe))
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotStopped));
+    assert!(!fake.is_sparse(ubuntu));
+
+    wsl.terminate_distribution(ubuntu).unwrap();
+    wsl.set_sparse(ubuntu, true, Some(allow_unsafe)).unwrap();
+    assert!(fake.is_sparse(ubuntu));
+}
+
+#[test]
+fn sparse_mode_needs_the_unsafe_opt_in_only_to_turn_on() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl.set_sparse("Ubuntu", true, None).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::ServiceRefused));
+    assert!(err.to_string().contains("data corruption"));
+
+    let allow_unsafe = AllowUnsafeSparse::accept_risk_of_data_corruption();
+    wsl.set_sparse("Ubuntu", true, Some(allow_unsafe)).unwrap();
+    wsl.set_sparse("Ubuntu", false, None).unwrap();
+    assert!(!fake.is_sparse(ubuntu));
+}
+
+#[test]
+fn unknown_distributions_are_not_found() {
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let err = wsl.set_sparse("Ubuntu", false, None).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
@@ -0,0 +1,84 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{
+    CreateInstanceFlags, DistributionRef, DistributionState, Version, Wsl2, WslErrorKind,
+};
+
+fn state(wsl: &Wsl2, name: &str) -> DistributionState {
+    wsl.enumerate_distributions()
+        .unwrap()
+        .into_iter()
+        .find(|distro| distro.name == name)
+        .unwrap()
+        .state
+}
+
+#[test]
+fn starting_is_idempotent() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+    assert_eq!(state(&wsl, "Ubuntu"), DistributionState::Stopped);
+
+    wsl.start_distribution(DistributionRef::Default, CreateInstanceFlags::empty())
+        .unwrap();
+    assert_eq!(state(&wsl, "Ubuntu"), DistributionState::Running);
+    wsl.start_distribution("Ubuntu", CreateInstanceFlags::empty())
+        .unwrap();
+    assert!(fake.is_running(ubuntu));
+}
+
+#[test]
+fn ensure_running_reports_whether_it_started() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    assert!(wsl
+        .ensure_running(ubuntu, CreateInstanceFlags::empty())
+        .unwrap());
+    assert!(!wsl
+        .ensure_running(ubuntu, CreateInstanceFlags::empty())
+        .unwrap());
+
+    // WSL stops idle distributions behind our back
+    wsl.terminate_distribution(ubuntu).unwrap();
+    assert!(wsl
+        .ensure_running(ubuntu, CreateInstanceFlags::empty())
+        .unwrap());
+    assert!(fake.is_running(ubuntu));
+}
+
+#[test]
+fn filesystem_upgrades_must_be_allowed() {
+    let fake = FakeBackend::new();
+    let legacy = fake.add_distribution("Legacy", Version::WSL1);
+    fake.require_fs_upgrade(legacy);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    let err = wsl
+        .ensure_running(legacy, CreateInstanceFlags::empty())
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::FilesystemUpgradeNeeded));
+    assert!(!fake.is_running(legacy));
+
+    assert!(wsl
+        .ensure_running(legacy, CreateInstanceFlags::ALLOW_FS_UPGRADE)
+        .unwrap());
+    // The upgrade is only needed once
+    wsl.terminate_distribution(legacy).unwrap();
+    wsl.start_distribution(legacy, CreateInstanceFlags::empty())
+        .unwrap();
+}
+
+#[test]
+fn unknown_distributions_are_not_started() {
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let err = wsl
+        .start_distribution("Ubuntu", CreateInstanceFlags::empty())
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    let err = wsl
+        .ensure_running("Ubuntu", CreateInstanceFlags::empty())
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
@@ -0,0 +1,341 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::tarball::{self, Architecture, Compression, OsRelease};
+use wsl_api::{RegisterOptions, Version, Wsl2, WslErrorKind};
+use wsl_com_api_sys::error::WSL_E_IMPORT_FAILED;
+
+use common::{stderr, TempDir};
+
+const OS_RELEASE: &[u8] = b"# Debian\nPRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\nNAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nID=debian\n";
+
+enum Entry<'a> {
+    File(&'a str, &'a [u8]),
+    Symlink(&'a str, &'a str),
+    HardLink(&'a str, &'a str),
+}
+
+fn elf(machine: u16) -> Vec<u8> {
+    let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+    elf.resize(18, 0);
+    elf.extend_from_slice(&machine.to_le_bytes());
+    elf.resize(64, 0);
+    elf
+}
+
+fn tarball(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o755);
+        match entry {
+            Entry::File(path, contents) => {
+                header.set_size(contents.len() as u64);
+                builder.append_data(&mut header, path, *contents).unwrap();
+            }
+            Entry::Symlink(path, target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, path, target).unwrap();
+            }
+            Entry::HardLink(path, target) => {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                builder.append_link(&mut header, path, target).unwrap();
+            }
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+fn debian() -> Vec<u8> {
+    let sh = elf(183);
+    tarball(&[
+        Entry::Symlink("./bin", "usr/bin"),
+        Entry::File("./usr/bin/sh", &sh),
+        Entry::Symlink("./etc/os-release", "../usr/lib/os-release"),
+        Entry::File("./usr/lib/os-release", OS_RELEASE),
+    ])
+}
+
+fn compress(compression: Compression, tar: &[u8]) -> Vec<u8> {
+    match compression {
+        Compression::None => tar.to_vec(),
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(tar).unwrap();
+            encoder.finish().unwrap()
+        }
+        Compression::Xz => {
+            let mut writer =
+                lzma_rust2::XzWriter::new(vec![], lzma_rust2::XzOptions::with_preset(1)).unwrap();
+            writer.write_all(tar).unwrap();
+            writer.finish().unwrap()
+        }
+        Compression::Zstd => {
+            ruzstd::encoding::compress_to_vec(tar, ruzstd::encoding::CompressionLevel::Fastest)
+        }
+    }
+}
+
+#[test]
+fn every_compression_is_detected_and_read() {
+    let tar = debian();
+    for compression in [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Xz,
+        Compression::Zstd,
+    ] {
+        let report = tarball::inspect(compress(compression, &tar).as_slice()).unwrap();
+        assert_eq!(report.compression, compression);
+        assert_eq!(report.os_release.id(), "debian");
+        assert_eq!(report.architecture().unwrap(), Some(Architecture::Aarch64));
+        assert_eq!(report.entries, 4);
+    }
+}
+
+#[test]
+fn os_release_values_are_unquoted() {
+    let os_release = OsRelease::parse(
+        "PRETTY_NAME=\"Ubuntu 24.04 LTS\"\nVERSION_CODENAME='noble'\nHOME_URL=\"https://\\\"x\\\"\"\nnot an assignment\n",
+    );
+    assert_eq!(os_release.pretty_name(), Some("Ubuntu 24.04 LTS"));
+    assert_eq!(os_release.get("VERSION_CODENAME"), Some("noble"));
+    assert_eq!(os_release.get("HOME_URL"), Some("https://\"x\""));
+    assert_eq!(os_release.id(), "linux");
+    assert_eq!(os_release.fields.len(), 3);
+}
+
+#[test]
+fn missing_shell_is_not_a_distribution() {
+    let tar = tarball(&[Entry::File("etc/os-release", OS_RELEASE)]);
+    let err = tarball::inspect(tar.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::NotALinuxDistribution));
+    assert!(err.to_string().contains("/bin/sh"));
+}
+
+#[test]
+fn missing_os_release_is_not_a_distribution() {
+    let sh = elf(62);
+    let tar = tarball(&[Entry::File("bin/sh", &sh)]);
+    let err = tarball::inspect(tar.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::NotALinuxDistribution));
+    assert!(err.to_string().contains("os-release"));
+}
+
+#[test]
+fn truncated_archive_is_invalid() {
+    let gz = compress(Compression::Gzip, &debian());
+    let err = tarball::inspect(&gz[..gz.len() / 2]).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::InvalidTarball));
+
+    let err = tarball::inspect(&b"not a tarball"[..]).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::InvalidTarball));
+}
+
+#[test]
+fn inspected_file_is_rewound() {
+    let tar = debian();
+    let dir = TempDir::new("tarball-rewound");
+    let path = dir.join("rootfs.tar");
+    std::fs::write(&path, &tar).unwrap();
+
+    let mut file = std::fs::File::open(&path).unwrap();
+    tarball::inspect_file(&mut file).unwrap();
+    let mut contents = vec![];
+    file.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, tar);
+}
+
+#[test]
+fn every_binary_architecture_is_reported() {
+    let (x86_64, aarch64) = (elf(62), elf(183));
+    let busybox = tarball(&[
+        Entry::File("bin/busybox", &aarch64),
+        Entry::Symlink("bin/sh", "busybox"),
+        Entry::HardLink("sbin/init", "bin/busybox"),
+        Entry::File("usr/sbin/chroot", &aarch64),
+        Entry::File("etc/os-release", OS_RELEASE),
+    ]);
+    let report = tarball::inspect(busybox.as_slice()).unwrap();
+    assert_eq!(
+        report.architectures.keys().collect::<Vec<_>>(),
+        [&Architecture::Aarch64]
+    );
+    assert_eq!(report.architecture().unwrap(), Some(Architecture::Aarch64));
+
+    // Binaries outside the usual directories, such as emulators, don't count
+    let script = tarball(&[
+        Entry::File("bin/sh", b"#!/bin/busybox\n"),
+        Entry::File("usr/libexec/qemu-x86_64", &x86_64),
+        Entry::File("etc/os-release", OS_RELEASE),
+    ]);
+    let report = tarball::inspect(script.as_slice()).unwrap();
+    assert!(report.architectures.is_empty());
+    assert_eq!(report.architecture().unwrap(), None);
+}
+
+#[test]
+fn mixed_architectures_are_rejected() {
+    let (x86_64, aarch64) = (elf(62), elf(183));
+    let mixed = tarball(&[
+        Entry::File("bin/busybox", &aarch64),
+        Entry::Symlink("bin/sh", "busybox"),
+        Entry::File("usr/sbin/arch-test", &x86_64),
+        Entry::File("etc/os-release", OS_RELEASE),
+    ]);
+    let report = tarball::inspect(mixed.as_slice()).unwrap();
+    assert_eq!(report.architectures[&Architecture::Aarch64], "bin/busybox");
+    assert_eq!(
+        report.architectures[&Architecture::X86_64],
+        "usr/sbin/arch-test"
+    );
+    let err = report.architecture().unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::MixedArchitectures));
+    assert!(err.to_string().contains("/usr/sbin/arch-test"));
+
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let err = wsl
+        .register_distribution_from_reader(
+            "Mixed",
+            Version::WSL2,
+            mixed.as_slice(),
+            stderr(),
+            RegisterOptions::new().inspect(true),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::MixedArchitectures));
+    assert!(wsl.resolve_distribution("Mixed").is_err());
+}
+
+#[test]
+fn registration_can_inspect_the_stream() {
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let inspected = RegisterOptions::new().inspect(true);
+    let not_a_distro = tarball(&[Entry::File("README", b"hello")]);
+
+    let err = wsl
+        .register_distribution_from_reader(
+            "Readme",
+            Version::WSL2,
+            not_a_distro.as_slice(),
+            stderr(),
+            inspected.clone(),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::NotALinuxDistribution));
+    assert!(wsl.resolve_distribution("Readme").is_err());
+
+    wsl.register_distribution_from_reader(
+        "Debian",
+        Version::WSL2,
+        debian().as_slice(),
+        stderr(),
+        inspected,
+    )
+    .unwrap();
+    wsl.resolve_distribution("Debian").unwrap();
+
+    // Without inspection, only the service judges the tarball
+    wsl.register_distribution_from_reader(
+        "Readme",
+        Version::WSL2,
+        not_a_distro.as_slice(),
+        stderr(),
+        RegisterOptions::new(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn registration_inspects_a_file_before_the_service() {
+    let fake = FakeBackend::new();
+    let wsl = Wsl2::with_backend(fake.clone());
+    let dir = TempDir::new("tarball-inspect");
+    let path = dir.join("rootfs.tar");
+    std::fs::write(&path, tarball(&[Entry::File("README", b"hello")])).unwrap();
+
+    // The service would fail the next registration, were it asked
+    fake.fail_next_registration(WSL_E_IMPORT_FAILED);
+    let err = wsl
+        .register_distribution(
+            "Readme",
+            Version::WSL2,
+            std::fs::File::open(&path).unwrap(),
+            stderr(),
+            RegisterOptions::new().inspect(true),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::NotALinuxDistribution));
+
+    let err = wsl
+        .register_distribution(
+            "Readme",
+            Version::WSL2,
+            std::fs::File::open(&path).unwrap(),
+            stderr(),
+            RegisterOptions::new(),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+}
+
+#[test]
+fn failed_reads_keep_the_service_error() {
+    struct Truncated<'a>(&'a [u8]);
+
+    impl Read for Truncated<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(std::io::ErrorKind::ConnectionReset.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let err = wsl
+        .register_distribution_from_reader(
+            "Readme",
+            Version::WSL2,
+            Truncated(b"not a tarball"),
+            stderr(),
+            RegisterOptions::new(),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(err.to_string().contains("connection reset"));
+}
+
+#[test]
+fn uninspected_files_are_judged_by_the_service() {
+    let wsl = Wsl2::with_backend(FakeBackend::new());
+    let dir = TempDir::new("tarball-uninspected");
+    let path = dir.join("rootfs.tar");
+
+    std::fs::write(&path, b"not a tarball").unwrap();
+    let err = wsl
+        .register_distribution(
+            "Garbage",
+            Version::WSL2,
+            std::fs::File::open(&path).unwrap(),
+            stderr(),
+            RegisterOptions::new(),
+        )
+        .unwrap_err();
+    assert_eq!(err.hresult(), WSL_E_IMPORT_FAILED);
+    assert!(wsl.resolve_distribution("Garbage").is_err());
+
+    std::fs::write(&path, debian()).unwrap();
+    wsl.register_distribution(
+        "Debian",
+        Version::WSL2,
+        std::fs::File::open(&path).unwrap(),
+        stderr(),
+        RegisterOptions::new(),
+    )
+    .unwrap();
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use wsl_api::fake::FakeBackend;
+use wsl_api::transcript::{Call, RecordingBackend, ReplayBackend, Returned};
+use wsl_api::{RegisterOptions, Version, Wsl2};
+
+use common::TempDir;
+
+#[test]
+fn clones_replay_whichever_half_arrives_first() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let recording = RecordingBackend::new(fake);
+    let wsl = Wsl2::with_backend(recording.clone());
+    let (guid, name) = wsl
+        .clone_distribution("Ubuntu", "Ubuntu-copy", RegisterOptions::default())
+        .unwrap();
+
+    let mut transcript = recording.finish().unwrap();
+    let export = transcript
+        .entries
+        .iter()
+        .position(|entry| matches!(entry.call, Call::ExportDistributionPipe { .. }))
+        .unwrap();
+    let register = transcript
+        .entries
+        .iter()
+        .position(|entry| matches!(entry.call, Call::RegisterDistributionPipe { .. }))
+        .unwrap();
+    // Swap the two concurrent calls
+    transcript.entries.swap(export, register);
+
+    let replay = ReplayBackend::new(transcript);
+    let wsl = Wsl2::with_backend(replay.clone());
+    assert_eq!(
+        wsl.clone_distribution("Ubuntu", "Ubuntu-copy", RegisterOptions::default())
+            .unwrap(),
+        (guid, name)
+    );
+    replay.finish().unwrap();
+}
+
+#[test]
+fn finishing_writes_the_file_and_reports_failures() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let dir = TempDir::new("transcript");
+    let path = dir.join("transcript.json");
+    let recording = RecordingBackend::with_file(fake.clone(), &path);
+    let wsl = Wsl2::with_backend(recording.clone());
+    wsl.enumerate_distributions().unwrap();
+    assert!(!path.exists());
+
+    let transcript = recording.finish().unwrap();
+    assert_eq!(transcript.entries.len(), 1);
+    let replay = ReplayBackend::load(&path).unwrap();
+    assert_eq!(replay.remaining(), 1);
+    assert!(replay.finish().is_err());
+
+    let unwritable = dir.join("missing").join("transcript.json");
+    let recording = RecordingBackend::with_file(fake, unwritable);
+    assert!(recording.finish().is_err());
+}
+
+#[test]
+fn launches_replay_their_output_and_exit_code() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    fake.on_launch(|mut process| {
+        let mut input = String::new();
+        process.stdin.read_to_string(&mut input).unwrap();
+        write!(process.stdout, "{}", input.to_uppercase()).unwrap();
+        write!(process.stderr, "warning").unwrap();
+        3
+    });
+    let recording = RecordingBackend::new(fake);
+    let wsl = Wsl2::with_backend(recording.clone());
+
+    let run = |wsl: &Wsl2| {
+        let mut process = wsl
+            .launch("Ubuntu", "/bin/sh", &["sh"], None, "root")
+            .unwrap();
+        process.stdin.take().unwrap().write_all(b"hello").unwrap();
+        let mut stdout = String::new();
+        process
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut stdout)
+            .unwrap();
+        let mut stderr = String::new();
+        process
+            .stderr
+            .take()
+            .unwrap()
+            .read_to_string(&mut stderr)
+            .unwrap();
+        (stdout, stderr, process.wait().unwrap().code())
+    };
+    let recorded = run(&wsl);
+    assert_eq!(
+        recorded,
+        ("HELLO".to_string(), "warning".to_string(), Some(3))
+    );
+
+    let transcript = recording.finish().unwrap();
+    let launch = transcript.entries.last().unwrap();
+    assert!(matches!(
+        &launch.returned,
+        Some(Returned::Process { exit_code: 3, stdout, .. }) if stdout == "HELLO"
+    ));
+
+    let replay = ReplayBackend::new(transcript);
+    assert_eq!(run(&Wsl2::with_backend(replay.clone())), recorded);
+    replay.finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Call does not match the transcript")]
+fn calls_out_of_order_do_not_replay() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let recording = RecordingBackend::new(fake);
+    let wsl = Wsl2::with_backend(recording.clone());
+    wsl.enumerate_distributions().unwrap();
+    wsl.get_default_distribution().unwrap();
+
+    let transcript = recording.finish().unwrap();
+    assert!(transcript
+        .entries
+        .iter()
+        .all(|entry| entry.concurrent.is_none()));
+    let wsl = Wsl2::with_backend(ReplayBackend::new(transcript));
+    _ = wsl.get_default_distribution();
+}
+
+#[test]
+#[should_panic(expected = "1 recorded calls were not replayed")]
+fn replays_left_unfinished_panic() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let recording = RecordingBackend::new(fake);
+    let wsl = Wsl2::with_backend(recording.clone());
+    wsl.enumerate_distributions().unwrap();
+    wsl.get_default_distribution().unwrap();
+
+    let wsl = Wsl2::with_backend(ReplayBackend::new(recording.finish().unwrap()));
+    wsl.enumerate_distributions().unwrap();
+}
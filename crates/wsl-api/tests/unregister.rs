@@ -0,0 +1,72 @@
+use wsl_api::fake::FakeBackend;
+use wsl_api::{CreateInstanceFlags, DistributionRef, Version, Wsl2, WslErrorKind};
+
+#[test]
+fn terminating_stops_a_running_distribution() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake.clone());
+
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+    wsl.terminate_distribution("Ubuntu").unwrap();
+    assert!(!fake.is_running(ubuntu));
+
+    // Terminating a stopped distribution does nothing
+    wsl.terminate_distribution(ubuntu).unwrap();
+}
+
+#[test]
+fn mismatched_names_are_not_unregistered() {
+    let fake = FakeBackend::new();
+    fake.add_distribution("Ubuntu", Version::WSL2);
+    let scratch = fake.add_distribution("Scratch", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let err = wsl
+        .unregister_distribution(scratch, "Scratch-old", false)
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNameMismatch));
+    assert!(err.to_string().contains("'Scratch'"));
+    assert_eq!(wsl.resolve_distribution("Scratch").unwrap(), scratch);
+
+    // The name is compared without regard to case
+    wsl.unregister_distribution(scratch, "SCRATCH", false)
+        .unwrap();
+    let err = wsl.resolve_distribution("Scratch").unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
+
+#[test]
+fn the_default_is_only_unregistered_when_forced() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let debian = fake.add_distribution("Debian", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+
+    let err = wsl
+        .unregister_distribution(DistributionRef::Default, "Ubuntu", false)
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionIsDefault));
+    assert_eq!(wsl.resolve_distribution("Ubuntu").unwrap(), ubuntu);
+
+    wsl.unregister_distribution(DistributionRef::Default, "Ubuntu", true)
+        .unwrap();
+    assert!(wsl.resolve_distribution("Ubuntu").is_err());
+    assert_eq!(wsl.get_default_distribution().unwrap(), debian);
+}
+
+#[test]
+fn unknown_distributions_are_not_found() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Wsl2::with_backend(fake);
+    wsl.unregister_distribution(ubuntu, "Ubuntu", true).unwrap();
+
+    let err = wsl
+        .unregister_distribution(ubuntu, "Ubuntu", true)
+        .unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+    let err = wsl.terminate_distribution(ubuntu).unwrap_err();
+    assert_eq!(err.kind(), Some(WslErrorKind::DistributionNotFound));
+}
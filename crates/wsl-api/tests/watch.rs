@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use windows::Win32::Foundation::E_UNEXPECTED;
+use wsl_api::fake::FakeBackend;
+use wsl_api::watch::{self, DistributionEvent, DistributionWatcher};
+use wsl_api::{CreateInstanceFlags, Distribution, DistributionState, Version, Wsl2};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The distribution numbered `n`, as an enumeration would list it.
+fn distro(n: u128, state: DistributionState, version: Version, is_default: bool) -> Distribution {
+    Distribution {
+        name: format!("Distro{n}"),
+        uuid: uuid::Uuid::from_u128(n),
+        version,
+        state,
+        flags: None,
+        is_default,
+    }
+}
+
+#[test]
+fn polling_continues_after_a_failed_enumeration() {
+    let fake = FakeBackend::new();
+    let ubuntu = fake.add_distribution("Ubuntu", Version::WSL2);
+    let wsl = Arc::new(Wsl2::with_backend(fake.clone()));
+    let (watcher, events) =
+        DistributionWatcher::channel(wsl.clone(), Duration::from_millis(10)).unwrap();
+
+    fake.fail_next_enumeration(E_UNEXPECTED);
+    let err = events.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+    assert_eq!(err.hresult(), E_UNEXPECTED);
+
+    wsl.start_distribution(ubuntu, CreateInstanceFlags::empty())
+        .unwrap();
+    match events.recv_timeout(TIMEOUT).unwrap().unwrap() {
+        DistributionEvent::Started(distro) => assert_eq!(distro.uuid, ubuntu),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    watcher.stop();
+    assert!(events.recv_timeout(TIMEOUT).is_err());
+}
+
+#[test]
+fn each_change_is_reported() {
+    use DistributionEvent::{DefaultChanged, Registered, Started, Unregistered, VersionChanged};
+    use DistributionState::*;
+    use Version::*;
+
+    let id = uuid::Uuid::from_u128;
+    let cases = [
+        (
+            "unchanged",
+            vec![distro(1, Running, WSL2, true)],
+            vec![distro(1, Running, WSL2, true)],
+            vec![],
+        ),
+        (
+            "registered as the first default",
+            vec![],
+            vec![distro(1, Stopped, WSL2, true)],
+            vec![
+                Registered(distro(1, Stopped, WSL2, true)),
+                DefaultChanged {
+                    previous: None,
+                    current: Some(id(1)),
+                },
+            ],
+        ),
+        (
+            "default unregistered",
+            vec![
+                distro(1, Stopped, WSL2, true),
+                distro(2, Stopped, WSL2, false),
+            ],
+            vec![distro(2, Stopped, WSL2, true)],
+            vec![
+                Unregistered(distro(1, Stopped, WSL2, true)),
+                DefaultChanged {
+                    previous: Some(id(1)),
+                    current: Some(id(2)),
+                },
+            ],
+        ),
+        (
+            "started",
+            vec![distro(1, Stopped, WSL2, false)],
+            vec![distro(1, Running, WSL2, false)],
+            vec![Started(distro(1, Running, WSL2, false))],
+        ),
+        (
+            "stopped",
+            vec![distro(1, Running, WSL2, false)],
+            vec![distro(1, Stopped, WSL2, false)],
+            vec![DistributionEvent::Stopped(distro(1, Stopped, WSL2, false))],
+        ),
+        (
+            "running to converting",
+            vec![distro(1, Running, WSL2, false)],
+            vec![distro(1, Converting, WSL2, false)],
+            vec![DistributionEvent::Stopped(distro(
+                1, Converting, WSL2, false,
+            ))],
+        ),
+        (
+            "installing to stopped",
+            vec![distro(1, Installing, WSL2, false)],
+            vec![distro(1, Stopped, WSL2, false)],
+            vec![],
+        ),
+        (
+            "converted",
+            vec![distro(1, Stopped, WSL2, false)],
+            vec![distro(1, Stopped, WSL1, false)],
+            vec![VersionChanged {
+                distribution: distro(1, Stopped, WSL1, false),
+                previous: WSL2,
+            }],
+        ),
+        (
+            "default moved to a new distribution",
+            vec![distro(1, Stopped, WSL2, true)],
+            vec![
+                distro(1, Stopped, WSL2, false),
+                distro(2, Stopped, WSL2, true),
+            ],
+            vec![
+                Registered(distro(2, Stopped, WSL2, true)),
+                DefaultChanged {
+                    previous: Some(id(1)),
+                    current: Some(id(2)),
+                },
+            ],
+        ),
+    ];
+    for (case, previous, current, expected) in cases {
+        assert_eq!(watch::diff(&previous, &current), expected, "{case}");
+    }
+}
+
+#[test]
+fn events_come_in_a_fixed_order() {
+    use DistributionEvent::{DefaultChanged, Registered, Started, Unregistered, VersionChanged};
+    use DistributionState::*;
+    use Version::*;
+
+    let previous = [
+        distro(1, Running, WSL2, true),
+        distro(2, Stopped, WSL2, false),
+        distro(3, Stopped, WSL2, false),
+    ];
+    let current = [
+        distro(4, Stopped, WSL2, true),
+        distro(3, Running, WSL1, false),
+        distro(5, Running, WSL2, false),
+    ];
+    // Unregistrations and registrations in the order they were listed, then
+    // changes to the rest, a conversion before a start, and the default last
+    assert_eq!(
+        watch::diff(&previous, &current),
+        [
+            Unregistered(distro(1, Running, WSL2, true)),
+            Unregistered(distro(2, Stopped, WSL2, false)),
+            Registered(distro(4, Stopped, WSL2, true)),
+            Registered(distro(5, Running, WSL2, false)),
+            VersionChanged {
+                distribution: distro(3, Running, WSL1, false),
+                previous: WSL2,
+            },
+            Started(distro(3, Running, WSL1, false)),
+            DefaultChanged {
+                previous: Some(uuid::Uuid::from_u128(1)),
+                current: Some(uuid::Uuid::from_u128(4)),
+            },
+        ]
+    );
+}
@@ -8,6 +8,14 @@ pub const LXSS_DISTRO_FLAGS_WSLCORE_MODE: u32 = 0x10;
 
 pub const LXSS_ENUMERATE_FLAGS_DEFAULT: u32 = 0x1;
 
+// LxssDistributionState
+pub const LXSS_DISTRIBUTION_STATE_INVALID: u32 = 0;
+pub const LXSS_DISTRIBUTION_STATE_INSTALLED: u32 = 1;
+pub const LXSS_DISTRIBUTION_STATE_RUNNING: u32 = 2;
+pub const LXSS_DISTRIBUTION_STATE_INSTALLING: u32 = 3;
+pub const LXSS_DISTRIBUTION_STATE_UNINSTALLING: u32 = 4;
+pub const LXSS_DISTRIBUTION_STATE_CONVERTING: u32 = 5;
+
 pub const LXSS_GET_DISTRO_ID_LIST_ALL: u32 = 0x1;
 
 pub const LXSS_CREATE_INSTANCE_FLAGS_ALLOW_FS_UPGRADE: u32 = 0x1;
@@ -30,8 +38,11 @@ pub const LXSS_ATTACH_MOUNT_FLAGS_PASS_THROUGH: u32 = 0x1;
 pub const LXSS_ATTACH_MOUNT_FLAGS_VHD: u32 = 0x2;
 
 // Interop message types handled at this time
+pub const LX_INIT_MESSAGE_CREATE_PROCESS: u32 = 0x6;
+pub const LX_INIT_MESSAGE_CREATE_PROCESS_RESPONSE: u32 = 0x7;
 pub const LX_INIT_MESSAGE_CREATE_PROCESS_UTILITY_VM: u32 = 0x8;
 pub const LX_INIT_MESSAGE_EXIT_STATUS: u32 = 0x9;
+pub const LX_INIT_MESSAGE_WINDOW_SIZE_CHANGED: u32 = 0xA;
 
 // Interop flags
 pub const LX_INIT_CREATE_PROCESS_RESULT_FLAG_GUI_APPLICATION: u32 = 0x1;
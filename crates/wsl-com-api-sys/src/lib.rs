@@ -348,6 +348,50 @@ impl ILxssUserSession {
         }
     }
 
+    /// # Safety
+    ///
+    /// `name` must point to a null-terminated UTF-16 string that stays valid
+    /// until the call returns.
+    pub unsafe fn GetDistributionId(&self, name: PCWSTR, flags: u32) -> LxssResult<GUID> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let mut guid = MaybeUninit::uninit();
+            let result = ((*vtable).GetDistributionId)(
+                self.0.as_raw(),
+                name,
+                flags,
+                std::ptr::from_mut(&mut error_info),
+                guid.as_mut_ptr(),
+            );
+            if result.is_ok() {
+                Ok(guid.assume_init())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The session must be live; the call takes no pointers from the caller.
+    pub unsafe fn SetDefaultDistribution(&self, distro_guid: GUID) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).SetDefaultDistribution)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
     pub unsafe fn UnregisterDistribution(&self, distro_guid: GUID) -> LxssResult<()> {
         unsafe {
             let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
@@ -406,6 +450,47 @@ impl ILxssUserSession {
         }
     }
 
+    /// The name, the environment strings and the array holding them are
+    /// allocated with `CoTaskMemAlloc` and must be freed by the caller.
+    ///
+    /// # Safety
+    ///
+    /// The session must be live. The returned pointers are owned by the
+    /// caller and must not be used after they have been freed.
+    pub unsafe fn GetDistributionConfiguration(
+        &self,
+        distro_guid: GUID,
+    ) -> LxssResult<GetDistributionConfigurationResult> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let mut configuration = GetDistributionConfigurationResult {
+                DistributionName: PWSTR::null(),
+                Version: 0,
+                DefaultUid: 0,
+                DefaultEnvironmentCount: 0,
+                DefaultEnvironment: std::ptr::null_mut(),
+                Flags: 0,
+            };
+            let result = ((*vtable).GetDistributionConfiguration)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                std::ptr::from_mut(&mut configuration.DistributionName),
+                std::ptr::from_mut(&mut configuration.Version),
+                std::ptr::from_mut(&mut configuration.DefaultUid),
+                std::ptr::from_mut(&mut configuration.DefaultEnvironmentCount),
+                std::ptr::from_mut(&mut configuration.DefaultEnvironment),
+                std::ptr::from_mut(&mut configuration.Flags),
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(configuration)
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
     pub unsafe fn EnumerateDistributions(&self) -> LxssResult<(u32, *const LXSS_ENUMERATE_INFO)> {
         unsafe {
             let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
@@ -491,6 +576,56 @@ impl ILxssUserSession {
         }
     }
 
+    /// # Safety
+    ///
+    /// `name` and `target_directory` must point to null-terminated UTF-16
+    /// strings, and `package_family_name` to one or be null, all valid until
+    /// the call returns. `pipe_handle` must be the read end of an open pipe
+    /// and `stderr_handle` an open writable handle, and both must stay open
+    /// until the call returns. The returned `InstalledName` is allocated with
+    /// `CoTaskMemAlloc` and must be freed by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn RegisterDistributionPipe(
+        &self,
+        name: PCWSTR,
+        version: u32,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        target_directory: PCWSTR,
+        flags: u32,
+        vhd_size: u64, // zero = default size
+        package_family_name: PCWSTR,
+    ) -> LxssResult<RegisterDistributionResult> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let mut installed_name = PWSTR::null();
+            let mut guid = MaybeUninit::uninit();
+            let result = ((*vtable).RegisterDistributionPipe)(
+                self.0.as_raw(),
+                name,
+                version,
+                pipe_handle,
+                stderr_handle,
+                target_directory,
+                flags,
+                vhd_size,
+                package_family_name,
+                std::ptr::from_mut(&mut installed_name),
+                std::ptr::from_mut(&mut error_info),
+                guid.as_mut_ptr(),
+            );
+            if result.is_ok() {
+                Ok(RegisterDistributionResult {
+                    Guid: guid.assume_init(),
+                    InstalledName: installed_name,
+                })
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
     pub unsafe fn ExportDistribution(
         &self,
         distro_guid: GUID,
@@ -517,6 +652,38 @@ impl ILxssUserSession {
         }
     }
 
+    /// # Safety
+    ///
+    /// `pipe_handle` must be the write end of an open pipe and
+    /// `stderr_handle` an open writable handle, and both must stay open until
+    /// the call returns. The service writes the whole tarball before
+    /// returning, so the read end must be drained concurrently.
+    pub unsafe fn ExportDistributionPipe(
+        &self,
+        distro_guid: GUID,
+        pipe_handle: HANDLE,
+        stderr_handle: HANDLE,
+        flags: u32,
+    ) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).ExportDistributionPipe)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                pipe_handle,
+                stderr_handle,
+                flags,
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
     pub unsafe fn CreateLxProcess(
         &self,
         distro_guid: GUID,
@@ -583,6 +750,208 @@ impl ILxssUserSession {
         }
     }
 
+    /// # Safety
+    ///
+    /// `output_handle` must be an open writable handle that stays open until
+    /// the call returns.
+    pub unsafe fn ResizeDistribution(
+        &self,
+        distro_guid: GUID,
+        output_handle: HANDLE,
+        new_size: u64,
+    ) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).ResizeDistribution)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                output_handle,
+                new_size,
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The session must be live; the call takes no pointers from the caller.
+    pub unsafe fn SetSparse(
+        &self,
+        distro_guid: GUID,
+        sparse: i32,
+        allow_unsafe: i32,
+    ) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).SetSparse)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                sparse,
+                allow_unsafe,
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `name` and `vhd_path` must point to null-terminated UTF-16 strings
+    /// that stay valid until the call returns.
+    pub unsafe fn ImportDistributionInplace(
+        &self,
+        name: PCWSTR,
+        vhd_path: PCWSTR,
+    ) -> LxssResult<GUID> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let mut guid = MaybeUninit::uninit();
+            let result = ((*vtable).ImportDistributionInplace)(
+                self.0.as_raw(),
+                name,
+                vhd_path,
+                std::ptr::from_mut(&mut error_info),
+                guid.as_mut_ptr(),
+            );
+            if result.is_ok() {
+                Ok(guid.assume_init())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `location` must point to a null-terminated UTF-16 string that stays
+    /// valid until the call returns.
+    pub unsafe fn MoveDistribution(&self, distro_guid: GUID, location: PCWSTR) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).MoveDistribution)(
+                self.0.as_raw(),
+                std::ptr::from_ref(&distro_guid),
+                location,
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `disk` must point to a null-terminated UTF-16 string that stays valid
+    /// until the call returns.
+    pub unsafe fn AttachDisk(&self, disk: PCWSTR, flags: u32) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let result = ((*vtable).AttachDisk)(
+                self.0.as_raw(),
+                disk,
+                flags,
+                std::ptr::from_mut(&mut error_info),
+            );
+            if result.is_ok() {
+                Ok(())
+            } else {
+                Err((result, error_info))
+            }
+        }
+    }
+
+    /// `result` and `step` are filled in whether or not the call succeeds.
+    ///
+    /// # Safety
+    ///
+    /// `disk` must be null or point to a null-terminated UTF-16 string that
+    /// stays valid until the call returns.
+    pub unsafe fn DetachDisk(
+        &self,
+        disk: PCWSTR, // null = every attached disk
+        result: &mut i32,
+        step: &mut i32,
+    ) -> LxssResult<()> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let hresult = ((*vtable).DetachDisk)(
+                self.0.as_raw(),
+                disk,
+                std::ptr::from_mut(result),
+                std::ptr::from_mut(step),
+                std::ptr::from_mut(&mut error_info),
+            );
+            if hresult.is_ok() {
+                Ok(())
+            } else {
+                Err((hresult, error_info))
+            }
+        }
+    }
+
+    /// `result` and `step` are filled in whether or not the call succeeds.
+    ///
+    /// # Safety
+    ///
+    /// `disk` must point to a null-terminated UTF-16 string, and `name`,
+    /// `ttype` and `options` to one or be null, all valid until the call
+    /// returns. The returned mount name is allocated with `CoTaskMemAlloc`
+    /// and must be freed by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn MountDisk(
+        &self,
+        disk: PCWSTR,
+        flags: u32,
+        partition_index: u32, // zero = whole disk
+        name: PCWSTR,
+        ttype: PCWSTR,
+        options: PCWSTR,
+        result: &mut i32,
+        step: &mut i32,
+    ) -> LxssResult<PWSTR> {
+        unsafe {
+            let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
+            let mut error_info = std::mem::zeroed();
+            let mut mount_name = PWSTR::null();
+            let hresult = ((*vtable).MountDisk)(
+                self.0.as_raw(),
+                disk,
+                flags,
+                partition_index,
+                name,
+                ttype,
+                options,
+                std::ptr::from_mut(result),
+                std::ptr::from_mut(step),
+                std::ptr::from_mut(&mut mount_name),
+                std::ptr::from_mut(&mut error_info),
+            );
+            if hresult.is_ok() {
+                Ok(mount_name)
+            } else {
+                Err((hresult, error_info))
+            }
+        }
+    }
+
     pub unsafe fn Shutdown(&self, force: i32) -> Result<()> {
         unsafe {
             let vtable = self.0.vtable() as *const _ as *const ILxssUserSession_Vtbl;
@@ -601,6 +970,16 @@ pub struct RegisterDistributionResult {
     pub InstalledName: PWSTR,
 }
 
+#[derive(Debug)]
+pub struct GetDistributionConfigurationResult {
+    pub DistributionName: PWSTR,
+    pub Version: u32,
+    pub DefaultUid: u32,
+    pub DefaultEnvironmentCount: u32,
+    pub DefaultEnvironment: *mut PCSTR,
+    pub Flags: u32,
+}
+
 #[derive(Debug)]
 pub struct CreateLxProcessResult {
     pub DistributionId: GUID,